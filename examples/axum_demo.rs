@@ -11,7 +11,7 @@
 
 use axum::{routing::{get, post}, Router, extract::Form, response::Html};
 use serde::Deserialize;
-use webpay::{client::{WebpayClient, Environment, Credentials}, types::CreateRequest};
+use webpay::{client::{WebpayClient, Environment, Credentials}, types::{CreateRequest, TokenWs}};
 use webpay::webpay_plus::is_authorized;
 
 #[tokio::main]
@@ -50,8 +50,8 @@ async fn main() {
 async fn pay(wp: WebpayClient) -> Html<String> {
     // 1. Define the transaction details.
     let req = CreateRequest {
-        buy_order: "ORDER-AXUM-123".into(),
-        session_id: "sess-axum-456".into(),
+        buy_order: "ORDER-AXUM-123".parse().unwrap(),
+        session_id: "sess-axum-456".parse().unwrap(),
         amount: 1990,
         // This is the URL where Webpay will redirect the user after the transaction is completed.
         // For production, this must be a publicly accessible URL.
@@ -64,34 +64,17 @@ async fn pay(wp: WebpayClient) -> Html<String> {
     let created = wp.wp_create(&req).await.expect("Failed to create transaction");
     println!("Transaction created: {:#?}", created);
 
-    // 3. Generate HTML to auto-submit a form that redirects the user to the Webpay URL.
+    // 3. Render a page that auto-submits a form redirecting the user to the Webpay URL.
     // This is the standard way to redirect a user to the Webpay platform.
-    Html(format!(
-        r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Redirecting to Webpay...</title>
-        </head>
-        <body>
-            <p>You are being redirected to Webpay to complete your payment.</p>
-            <form id="webpay-form" action="{url}" method="POST">
-                <input type="hidden" name="token_ws" value="{token}"/>
-            </form>
-            <script>
-                // Automatically submit the form to redirect the user.
-                document.getElementById('webpay-form').submit();
-            </script>
-        </body>
-        </html>
-    "#, url=created.url, token=created.token))
+    // `redirect_form` escapes both values and shows a button if JavaScript is disabled.
+    Html(created.redirect_form())
 }
 
 // Struct to deserialize the form data received from Webpay upon return.
 #[derive(Deserialize, Debug)]
 struct ReturnForm {
     // `token_ws` is present in successful or rejected transactions.
-    token_ws: Option<String>,
+    token_ws: Option<TokenWs>,
     // These `TBK_*` fields are present if the user aborts the transaction.
     #[serde(rename = "TBK_TOKEN")]
     tbk_token: Option<String>,
@@ -0,0 +1,89 @@
+// This example does what `axum_demo` does by hand, using the `axum` feature instead.
+// `webpay::axum::router` serves the `/pay` and `/return` endpoints; the application only
+// supplies and remembers the order and reacts to the outcome.
+//
+// To run this example:
+// 1. Run `cargo run --example axum_router --features axum`.
+// 2. Open `http://127.0.0.1:3000/` in your browser and press "Pay".
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use axum::{extract::Request, http::StatusCode, response::{Html, IntoResponse, Response}, routing::get, Router};
+use webpay::axum::{router, CheckoutHandler};
+use webpay::callback::PaymentOutcome;
+use webpay::client::{WebpayClient, Environment, Credentials};
+use webpay::types::{CreateRequest, CreateResponse, TokenWs};
+use webpay::verify::ExpectedOrder;
+
+#[derive(Default)]
+struct Shop {
+    // A real shop would keep this in its database.
+    orders: Mutex<HashMap<TokenWs, ExpectedOrder>>,
+}
+
+#[axum::async_trait]
+impl CheckoutHandler for Shop {
+    // Called for every POST to `/webpay/pay`. A real shop would load the order
+    // referenced by the request (e.g., from a query parameter or the session).
+    async fn order(&self, _req: Request) -> Result<CreateRequest, Response> {
+        Ok(CreateRequest {
+            buy_order: "ORDER-AXUM-123".parse().unwrap(),
+            session_id: "sess-axum-456".parse().unwrap(),
+            amount: 1990,
+            // Must point at the `/return` route of the router.
+            return_url: "http://127.0.0.1:3000/webpay/return".into(),
+        })
+    }
+
+    // Remembers which order each token belongs to.
+    async fn on_created(&self, request: &CreateRequest, response: &CreateResponse) {
+        self.orders.lock().unwrap().insert(response.token.clone(), ExpectedOrder::from(request));
+    }
+
+    // The return callback only carries the token; the commit is checked against this order.
+    async fn expected_order(&self, token: &TokenWs) -> Result<ExpectedOrder, Response> {
+        self.orders.lock().unwrap().get(token).cloned().ok_or_else(|| (StatusCode::NOT_FOUND, "unknown order").into_response())
+    }
+
+    // Called once the return callback has been classified (and committed, if completed).
+    // Here you should update your application's state (e.g., mark the order as paid).
+    async fn on_outcome(&self, outcome: PaymentOutcome) -> Response {
+        match outcome {
+            PaymentOutcome::Authorized(tx) => Html(format!(
+                "<h1>Payment Successful! ✅</h1><p>Authorization Code: <strong>{}</strong></p>",
+                tx.commit().authorization_code.as_deref().unwrap_or("N/A")
+            )).into_response(),
+            PaymentOutcome::Rejected(_) => Html("<h1>Payment Rejected ❌</h1>").into_response(),
+            PaymentOutcome::Aborted { .. } | PaymentOutcome::TimedOut { .. } | PaymentOutcome::FormError { .. } => {
+                Html("<h1>Payment Aborted</h1>").into_response()
+            }
+            // The commit failed (e.g., a network error, or a token from another order):
+            // check `wp_status` before retrying.
+            PaymentOutcome::CommitFailed { error, .. } => {
+                println!("Commit failed: {}", error);
+                Html("<h1>We could not confirm your payment</h1>").into_response()
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let wp = WebpayClient::new(
+        Environment::Integration,
+        Credentials {
+            commerce_code: "597055555532".into(),
+            api_key: "579B532A7440BB0C9079DED94D31EA1615BACEB56610332264630D42D0A36B1C".into(),
+        },
+    );
+
+    // `/pay` only answers POST, so the checkout page submits a form to it.
+    let app: Router = Router::new()
+        .route("/", get(|| async { Html(r#"<form method="post" action="/webpay/pay"><button>Pay</button></form>"#) }))
+        .nest("/webpay", router(wp, Shop::default()));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    println!("Visit http://127.0.0.1:3000/ to start a transaction.");
+    axum::serve(listener, app).await.unwrap();
+}
@@ -5,6 +5,10 @@
 // Usage:
 // cargo run --example transaction_scenarios -- <scenario>
 //
+// With the `mock` feature and `--mock`, the scenarios run against a local mock of Webpay
+// and a simulated cardholder fills in the payment form, so no input is needed:
+// cargo run --example transaction_scenarios --features mock -- <scenario> --mock
+//
 // Available scenarios:
 // - success: Simulates a successful payment.
 // - rejected: Simulates a payment rejected by the user on the Webpay platform.
@@ -13,9 +17,56 @@
 
 use std::env;
 use webpay::client::{WebpayClient, Environment, Credentials};
-use webpay::types::CreateRequest;
+use webpay::types::{CreateRequest, CreateResponse, TokenWs};
 use webpay::webpay_plus::is_authorized;
 
+// Who fills in the Webpay payment form: you, in a browser, or a simulated cardholder on
+// the mock server.
+enum Cardholder {
+    Interactive,
+    #[cfg(feature = "mock")]
+    Simulated(webpay::mock::Cardholder),
+}
+
+impl Cardholder {
+    // Completes the payment form, approving or rejecting the payment, and returns the
+    // `token_ws` Webpay sends back to the `return_url`.
+    #[cfg_attr(not(feature = "mock"), allow(unused_variables))]
+    async fn complete(&self, created: &CreateResponse, approve: bool) -> TokenWs {
+        match self {
+            Cardholder::Interactive => {
+                let mut token_ws = String::new();
+                std::io::stdin().read_line(&mut token_ws).unwrap();
+                token_ws.trim().parse().expect("Invalid token_ws")
+            }
+            #[cfg(feature = "mock")]
+            Cardholder::Simulated(cardholder) => {
+                use webpay::mock::TestCard;
+                let card = if approve { TestCard::VISA } else { TestCard::MASTERCARD };
+                println!("   (simulated) Paying with the {} test card {}", card.brand, card.number);
+                let callback = cardholder.pay(created, card.number).await.expect("Failed to complete the payment form");
+                let token_ws = callback.params().token_ws.expect("token_ws");
+                println!("   (simulated) Webpay returned token_ws={}", token_ws);
+                token_ws.parse().expect("Invalid token_ws")
+            }
+        }
+    }
+
+    // Cancels the payment in the form.
+    #[cfg_attr(not(feature = "mock"), allow(unused_variables))]
+    async fn abort(&self, created: &CreateResponse) {
+        match self {
+            Cardholder::Interactive => {}
+            #[cfg(feature = "mock")]
+            Cardholder::Simulated(cardholder) => {
+                let callback = cardholder.abort(created).await.expect("Failed to abort the payment form");
+                println!("\n   (simulated) Webpay returned {:?}", callback.fields);
+                println!("   (simulated) Classified as {:?}", callback.params().classify().expect("Invalid return"));
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
@@ -26,24 +77,39 @@ async fn main() {
     }
 
     let scenario = &args[1];
+    let mock = args[2..].iter().any(|a| a == "--mock");
 
     // The WebpayClient is the main entry point to the library.
     // It requires the environment (Integration or Production) and credentials.
     // For this example, we use the integration environment and credentials.
-    let wp = WebpayClient::new(
-        Environment::Integration,
-        Credentials {
-            commerce_code: "597055555532".into(),
-            api_key: "579B532A7440BB0C9079DED94D31EA1615BACEB56610332264630D42D0A36B1C".into(),
-        },
-    );
+    let credentials = Credentials {
+        commerce_code: "597055555532".into(),
+        api_key: "579B532A7440BB0C9079DED94D31EA1615BACEB56610332264630D42D0A36B1C".into(),
+    };
+    #[cfg(feature = "mock")]
+    let (_server, wp, cardholder) = if mock {
+        // The mock stops when `_server` is dropped, at the end of `main`.
+        let server = webpay::mock::MockWebpay::start().await.expect("Failed to start the mock server");
+        println!("Running against the mock Webpay at {}", server.base_url());
+        let wp = WebpayClient::new(server.environment(), credentials);
+        (Some(server), wp, Cardholder::Simulated(webpay::mock::Cardholder::new()))
+    } else {
+        (None, WebpayClient::new(Environment::Integration, credentials), Cardholder::Interactive)
+    };
+    #[cfg(not(feature = "mock"))]
+    let (wp, cardholder) = if mock {
+        println!("--mock requires the `mock` feature: cargo run --example transaction_scenarios --features mock -- {} --mock", scenario);
+        return;
+    } else {
+        (WebpayClient::new(Environment::Integration, credentials), Cardholder::Interactive)
+    };
 
     // Based on the command-line argument, we run the corresponding scenario.
     match scenario.as_str() {
-        "success" => handle_success(&wp).await,
-        "rejected" => handle_rejected(&wp).await,
-        "abort" => handle_abort(&wp).await,
-        "refund" => handle_refund(&wp).await,
+        "success" => handle_success(&wp, &cardholder).await,
+        "rejected" => handle_rejected(&wp, &cardholder).await,
+        "abort" => handle_abort(&wp, &cardholder).await,
+        "refund" => handle_refund(&wp, &cardholder).await,
         _ => {
             println!("Invalid scenario: {}", scenario);
             println!("Available scenarios: success, rejected, abort, refund");
@@ -53,14 +119,14 @@ async fn main() {
 
 // Scenario 1: A successful transaction.
 // The user completes the payment on the Webpay platform.
-async fn handle_success(wp: &WebpayClient) {
+async fn handle_success(wp: &WebpayClient, cardholder: &Cardholder) {
     println!("\n--- Running Scenario: Successful Transaction ---");
     println!("This scenario simulates a user successfully completing a payment.");
 
     // 1. Create the transaction details.
     let req = CreateRequest {
-        buy_order: "ORDER-SUCCESS-123".into(),
-        session_id: "sess-success-456".into(),
+        buy_order: "ORDER-SUCCESS-123".parse().unwrap(),
+        session_id: "sess-success-456".parse().unwrap(),
         amount: 1500,
         return_url: "http://localhost:3000/webpay/return".into(), // URL where the user will be redirected after payment.
     };
@@ -77,13 +143,11 @@ async fn handle_success(wp: &WebpayClient) {
     // and the token (`token_ws`) would be part of the POST request.
     println!("\n[Step 4] After completing the payment, you will be redirected to a blank page.");
     println!("   Please enter the 'token_ws' value from the form data of that page:");
-    let mut token_ws = String::new();
-    std::io::stdin().read_line(&mut token_ws).unwrap();
-    let token_ws = token_ws.trim();
+    let token_ws = cardholder.complete(&created, true).await;
 
     // 4. Commit the transaction using the received token.
     println!("\n[Step 5] Committing the transaction with token: {}", token_ws);
-    let commit = wp.wp_commit(token_ws).await.expect("Failed to commit transaction");
+    let commit = wp.wp_commit(&token_ws).await.expect("Failed to commit transaction");
     println!("\n[Step 6] Transaction committed. Response:\n{:#?}", commit);
 
     // 5. Check if the transaction was authorized.
@@ -99,14 +163,14 @@ async fn handle_success(wp: &WebpayClient) {
 
 // Scenario 2: A transaction rejected by the user.
 // The user cancels the payment on the Webpay platform.
-async fn handle_rejected(wp: &WebpayClient) {
+async fn handle_rejected(wp: &WebpayClient, cardholder: &Cardholder) {
     println!("\n--- Running Scenario: Rejected Transaction ---");
     println!("This scenario simulates a user rejecting a payment on the Webpay platform.");
 
     // 1. Create the transaction details.
     let req = CreateRequest {
-        buy_order: "ORDER-REJECTED-123".into(),
-        session_id: "sess-rejected-456".into(),
+        buy_order: "ORDER-REJECTED-123".parse().unwrap(),
+        session_id: "sess-rejected-456".parse().unwrap(),
         amount: 2000,
         return_url: "http://localhost:3000/webpay/return".into(),
     };
@@ -120,13 +184,11 @@ async fn handle_rejected(wp: &WebpayClient) {
 
     // 3. Manually get the token from the user.
     println!("\n[Step 4] After rejecting the payment, enter the 'token_ws' from the form data:");
-    let mut token_ws = String::new();
-    std::io::stdin().read_line(&mut token_ws).unwrap();
-    let token_ws = token_ws.trim();
+    let token_ws = cardholder.complete(&created, false).await;
 
     // 4. Commit the transaction.
     println!("\n[Step 5] Committing the transaction with token: {}", token_ws);
-    let commit = wp.wp_commit(token_ws).await.expect("Failed to commit transaction");
+    let commit = wp.wp_commit(&token_ws).await.expect("Failed to commit transaction");
     println!("\n[Step 6] Transaction committed. Response:\n{:#?}", commit);
 
     // 5. Check if the transaction was authorized.
@@ -141,14 +203,14 @@ async fn handle_rejected(wp: &WebpayClient) {
 
 // Scenario 3: A transaction aborted by the user.
 // The user closes the browser or navigates away before completing the payment.
-async fn handle_abort(wp: &WebpayClient) {
+async fn handle_abort(wp: &WebpayClient, cardholder: &Cardholder) {
     println!("\n--- Running Scenario: Aborted Transaction ---");
     println!("This scenario simulates a user aborting a payment by closing the browser.");
 
     // 1. Create the transaction details.
     let req = CreateRequest {
-        buy_order: "ORDER-ABORT-123".into(),
-        session_id: "sess-abort-456".into(),
+        buy_order: "ORDER-ABORT-123".parse().unwrap(),
+        session_id: "sess-abort-456".parse().unwrap(),
         amount: 2500,
         return_url: "http://localhost:3000/webpay/return".into(),
     };
@@ -159,6 +221,7 @@ async fn handle_abort(wp: &WebpayClient) {
     println!("\n[Step 2] Transaction created successfully. Response:\n{:#?}", created);
     println!("\n[Step 3] Please open the following URL, but instead of paying, close the tab or browser.");
     println!("{}", created.url);
+    cardholder.abort(&created).await;
 
     // 3. Explain the abortion flow.
     println!("\n[Step 4] When a user aborts, Webpay redirects them to the `return_url` with different parameters.");
@@ -171,14 +234,14 @@ async fn handle_abort(wp: &WebpayClient) {
 }
 
 // Scenario 4: A successful transaction followed by a refund.
-async fn handle_refund(wp: &WebpayClient) {
+async fn handle_refund(wp: &WebpayClient, cardholder: &Cardholder) {
     println!("\n--- Running Scenario: Refund Transaction ---");
     println!("This scenario simulates a successful payment followed by a partial refund.");
 
     // 1. Create and commit a successful transaction first.
     let req = CreateRequest {
-        buy_order: "ORDER-REFUND-123".into(),
-        session_id: "sess-refund-456".into(),
+        buy_order: "ORDER-REFUND-123".parse().unwrap(),
+        session_id: "sess-refund-456".parse().unwrap(),
         amount: 3000,
         return_url: "http://localhost:3000/webpay/return".into(),
     };
@@ -190,12 +253,10 @@ async fn handle_refund(wp: &WebpayClient) {
     println!("{}", created.url);
 
     println!("\n[Step 4] After completing the payment, enter the 'token_ws':");
-    let mut token_ws = String::new();
-    std::io::stdin().read_line(&mut token_ws).unwrap();
-    let token_ws = token_ws.trim();
+    let token_ws = cardholder.complete(&created, true).await;
 
     println!("\n[Step 5] Committing the transaction with token: {}", token_ws);
-    let commit = wp.wp_commit(token_ws).await.expect("Failed to commit transaction");
+    let commit = wp.wp_commit(&token_ws).await.expect("Failed to commit transaction");
     println!("\n[Step 6] Transaction committed. Response:\n{:#?}", commit);
 
     // 2. If the transaction was successful, proceed with the refund.
@@ -206,7 +267,7 @@ async fn handle_refund(wp: &WebpayClient) {
         println!("   - Amount to refund: {}", amount_to_refund);
 
         // 3. Call `wp_refund` with the token of the original transaction and the amount.
-        let refund = wp.wp_refund(token_ws, amount_to_refund).await.expect("Failed to refund transaction");
+        let refund = wp.wp_refund(&token_ws, amount_to_refund).await.expect("Failed to refund transaction");
         println!("\n[Step 8] Refund processed. Response:\n{:#?}", refund);
 
         // 4. Check the refund response.
@@ -0,0 +1,174 @@
+//! Actix-web integration (feature `actix`).
+//!
+//! Mirrors [`crate::axum`]: [`scope`] serves a `/pay` endpoint that creates the transaction
+//! and renders the redirect form, and a `/return` endpoint that classifies the Webpay
+//! callback and commits it, verified against the order. Your [`CheckoutHandler`] supplies
+//! and remembers the order and decides what to answer for each [`PaymentOutcome`].
+
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::{
+    body::BoxBody,
+    dev::Payload,
+    error::UrlencodedError,
+    http::{header::ContentType, Method, StatusCode},
+    web, FromRequest, HttpRequest, HttpResponse, Responder, ResponseError, Scope,
+};
+use async_trait::async_trait;
+
+use crate::callback::{InvalidReturn, PaymentOutcome, ReturnParams, WebpayReturn};
+use crate::gateway::PaymentGateway;
+use crate::redirect::RedirectForm;
+use crate::types::*;
+use crate::verify::ExpectedOrder;
+
+/// Error of the [`WebpayReturn`] and [`ReturnParams`] extractors; answers `400 Bad Request`.
+#[derive(Debug, thiserror::Error)]
+pub enum ReturnRejection {
+    #[error("{0}")]
+    Form(String),
+    #[error(transparent)]
+    Invalid(#[from] InvalidReturn),
+}
+
+impl From<UrlencodedError> for ReturnRejection {
+    fn from(e: UrlencodedError) -> Self { ReturnRejection::Form(e.to_string()) }
+}
+
+impl ResponseError for ReturnRejection {
+    fn status_code(&self) -> StatusCode { StatusCode::BAD_REQUEST }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().body(format!("invalid Webpay return: {}", self))
+    }
+}
+
+/// Reads the return parameters from the query on GET and HEAD, and from the form body otherwise.
+impl FromRequest for ReturnParams {
+    type Error = ReturnRejection;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if req.method() == Method::GET || req.method() == Method::HEAD {
+            let params = web::Query::<ReturnParams>::from_query(req.query_string())
+                .map(web::Query::into_inner)
+                .map_err(|e| ReturnRejection::Form(e.to_string()));
+            return Box::pin(std::future::ready(params));
+        }
+        let form = web::UrlEncoded::<ReturnParams>::new(req, payload);
+        Box::pin(async move { Ok(form.await?) })
+    }
+}
+
+/// Reads and classifies the return parameters.
+impl FromRequest for WebpayReturn {
+    type Error = ReturnRejection;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let params = ReturnParams::from_request(req, payload);
+        Box::pin(async move { Ok(params.await?.classify()?) })
+    }
+}
+
+/// Renders the auto-submitting redirect form of a `CreateResponse`.
+impl Responder for CreateResponse {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        RedirectPage { created: self, form: RedirectForm::default() }.respond_to(req)
+    }
+}
+
+/// Renders the redirect form of a `CreateResponse` with custom [`RedirectForm`] options.
+pub struct RedirectPage {
+    pub created: CreateResponse,
+    pub form: RedirectForm,
+}
+
+impl Responder for RedirectPage {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(self.created.render_redirect_form(&self.form))
+    }
+}
+
+/// The application side of the checkout served by [`scope`].
+#[async_trait(?Send)]
+pub trait CheckoutHandler: 'static {
+    /// Builds the order to pay for a request to `/pay`, or answers it directly.
+    async fn order(&self, req: &HttpRequest) -> Result<CreateRequest, HttpResponse>;
+
+    /// Called once the transaction is created, before the redirect form is rendered.
+    /// Store what [`expected_order`](Self::expected_order) needs here, keyed by `response.token`.
+    async fn on_created(&self, _request: &CreateRequest, _response: &CreateResponse) {}
+
+    /// Looks up the order a returning token was created for, or answers the callback
+    /// directly (e.g., `404 Not Found` for unknown tokens, which are then never committed).
+    async fn expected_order(&self, token: &TokenWs) -> Result<ExpectedOrder, HttpResponse>;
+
+    /// Reacts to the outcome of a return callback, e.g., by marking the order as paid.
+    async fn on_outcome(&self, outcome: PaymentOutcome) -> HttpResponse;
+
+    /// Answers a `/pay` request whose `wp_create` call failed. Defaults to `502 Bad Gateway`.
+    async fn on_create_error(&self, error: WebpayError) -> HttpResponse {
+        HttpResponse::BadGateway().body(format!("could not start the payment: {}", error))
+    }
+}
+
+struct Checkout<G, H> {
+    gateway: G,
+    handler: H,
+}
+
+/// Returns a scope mounted at `path` with the checkout endpoints:
+///
+/// * `POST {path}/pay` creates the transaction for [`CheckoutHandler::order`], reports it
+///   to [`CheckoutHandler::on_created`] and renders the auto-submitting redirect form.
+///   Other methods get `405 Method Not Allowed`.
+/// * `{path}/return` (GET and POST) is the `return_url`: it commits completed payments,
+///   checking them against [`CheckoutHandler::expected_order`], and hands the outcome to
+///   [`CheckoutHandler::on_outcome`]. Malformed callbacks get `400 Bad Request`.
+///
+/// The scope is built inside the `HttpServer::new` closure, once per worker, so the
+/// gateway and handler are usually cheap clones of shared state.
+pub fn scope<G, H>(path: &str, gateway: G, handler: H) -> Scope
+where
+    G: PaymentGateway + 'static,
+    H: CheckoutHandler,
+{
+    web::scope(path)
+        .app_data(web::Data::new(Checkout { gateway, handler }))
+        .service(web::resource("/pay").route(web::post().to(pay::<G, H>)))
+        .route("/return", web::get().to(webpay_return::<G, H>))
+        .route("/return", web::post().to(webpay_return::<G, H>))
+}
+
+async fn pay<G: PaymentGateway + 'static, H: CheckoutHandler>(checkout: web::Data<Checkout<G, H>>, req: HttpRequest) -> HttpResponse {
+    let order = match checkout.handler.order(&req).await {
+        Ok(order) => order,
+        Err(response) => return response,
+    };
+    match checkout.gateway.wp_create(&order).await {
+        Ok(created) => {
+            checkout.handler.on_created(&order, &created).await;
+            created.respond_to(&req)
+        }
+        Err(error) => checkout.handler.on_create_error(error).await,
+    }
+}
+
+async fn webpay_return<G: PaymentGateway + 'static, H: CheckoutHandler>(checkout: web::Data<Checkout<G, H>>, ret: WebpayReturn) -> HttpResponse {
+    let outcome = match &ret {
+        WebpayReturn::Completed { token } => match checkout.handler.expected_order(token).await {
+            Ok(expected) => ret.process_verified(&checkout.gateway, &expected).await,
+            Err(response) => return response,
+        },
+        _ => ret.process(&checkout.gateway).await,
+    };
+    checkout.handler.on_outcome(outcome).await
+}
@@ -0,0 +1,138 @@
+//! Axum integration (feature `axum`).
+//!
+//! [`router`] serves what `examples/axum_demo.rs` does by hand: a `/pay` endpoint that
+//! creates the transaction and renders the redirect form, and a `/return` endpoint that
+//! classifies the Webpay callback and commits it, verified against the order. Your
+//! [`CheckoutHandler`] supplies and remembers the order and decides what to answer for each
+//! [`PaymentOutcome`].
+
+use std::sync::Arc;
+
+use ::axum::{
+    async_trait,
+    extract::{rejection::FormRejection, Form, FromRequest, Request, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+
+use crate::callback::{InvalidReturn, PaymentOutcome, ReturnParams, WebpayReturn};
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+use crate::verify::ExpectedOrder;
+
+/// Rejection of the [`WebpayReturn`] and [`ReturnParams`] extractors; answers `400 Bad Request`.
+#[derive(Debug, thiserror::Error)]
+pub enum ReturnRejection {
+    #[error(transparent)]
+    Form(#[from] FormRejection),
+    #[error(transparent)]
+    Invalid(#[from] InvalidReturn),
+}
+
+impl IntoResponse for ReturnRejection {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, format!("invalid Webpay return: {}", self)).into_response()
+    }
+}
+
+/// Reads the return parameters from the POST form body, or from the query on GET.
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for ReturnParams {
+    type Rejection = ReturnRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // `Form` reads the query string for GET and HEAD, and the body otherwise.
+        let Form(params) = Form::<ReturnParams>::from_request(req, state).await?;
+        Ok(params)
+    }
+}
+
+/// Reads and classifies the return parameters.
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for WebpayReturn {
+    type Rejection = ReturnRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let params = ReturnParams::from_request(req, state).await?;
+        Ok(params.classify()?)
+    }
+}
+
+/// The application side of the checkout served by [`router`].
+#[async_trait]
+pub trait CheckoutHandler: Send + Sync + 'static {
+    /// Builds the order to pay for a request to `/pay`, or answers it directly.
+    async fn order(&self, req: Request) -> Result<CreateRequest, Response>;
+
+    /// Called once the transaction is created, before the redirect form is rendered.
+    /// Store what [`expected_order`](Self::expected_order) needs here, keyed by `response.token`.
+    async fn on_created(&self, _request: &CreateRequest, _response: &CreateResponse) {}
+
+    /// Looks up the order a returning token was created for, or answers the callback
+    /// directly (e.g., `404 Not Found` for unknown tokens, which are then never committed).
+    async fn expected_order(&self, token: &TokenWs) -> Result<ExpectedOrder, Response>;
+
+    /// Reacts to the outcome of a return callback, e.g., by marking the order as paid.
+    async fn on_outcome(&self, outcome: PaymentOutcome) -> Response;
+
+    /// Answers a `/pay` request whose `wp_create` call failed. Defaults to `502 Bad Gateway`.
+    async fn on_create_error(&self, error: WebpayError) -> Response {
+        (StatusCode::BAD_GATEWAY, format!("could not start the payment: {}", error)).into_response()
+    }
+}
+
+struct Checkout<G, H> {
+    gateway: G,
+    handler: H,
+}
+
+/// Returns a router with the checkout endpoints:
+///
+/// * `POST /pay` creates the transaction for [`CheckoutHandler::order`], reports it to
+///   [`CheckoutHandler::on_created`] and renders the auto-submitting redirect form. It is
+///   not served on GET, so links and prefetches can't create transactions.
+/// * `/return` (GET and POST) is the `return_url`: it commits completed payments, checking
+///   them against [`CheckoutHandler::expected_order`], and hands the outcome to
+///   [`CheckoutHandler::on_outcome`]. Malformed callbacks get `400 Bad Request`.
+///
+/// Nest it to mount the endpoints elsewhere, e.g., `Router::new().nest("/webpay", router(client, handler))`,
+/// and remember that `return_url` must point at the final `/return` path.
+pub fn router<G, H, S>(gateway: G, handler: H) -> Router<S>
+where
+    G: PaymentGateway + 'static,
+    H: CheckoutHandler,
+    S: Clone + Send + Sync + 'static,
+{
+    let checkout = Arc::new(Checkout { gateway, handler });
+    Router::new()
+        .route("/pay", post(pay::<G, H>))
+        .route("/return", get(webpay_return::<G, H>).post(webpay_return::<G, H>))
+        .with_state(checkout)
+}
+
+async fn pay<G: PaymentGateway, H: CheckoutHandler>(State(checkout): State<Arc<Checkout<G, H>>>, req: Request) -> Response {
+    let order = match checkout.handler.order(req).await {
+        Ok(order) => order,
+        Err(response) => return response,
+    };
+    match checkout.gateway.wp_create(&order).await {
+        Ok(created) => {
+            checkout.handler.on_created(&order, &created).await;
+            Html(created.redirect_form()).into_response()
+        }
+        Err(error) => checkout.handler.on_create_error(error).await,
+    }
+}
+
+async fn webpay_return<G: PaymentGateway, H: CheckoutHandler>(State(checkout): State<Arc<Checkout<G, H>>>, ret: WebpayReturn) -> Response {
+    let outcome = match &ret {
+        WebpayReturn::Completed { token } => match checkout.handler.expected_order(token).await {
+            Ok(expected) => ret.process_verified(&checkout.gateway, &expected).await,
+            Err(response) => return response,
+        },
+        _ => ret.process(&checkout.gateway).await,
+    };
+    checkout.handler.on_outcome(outcome).await
+}
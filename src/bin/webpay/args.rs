@@ -0,0 +1,62 @@
+//! A small command-line parser: positional arguments, `--name value` / `--name=value`
+//! options and `--flag` switches.
+
+use std::collections::HashMap;
+
+use crate::CliError;
+
+/// Options that never take a value.
+const FLAGS: &[&str] = &["json", "help"];
+
+pub struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut parsed = Args { positional: Vec::new(), options: HashMap::new(), flags: Vec::new() };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                parsed.positional.push(arg);
+                continue;
+            };
+            if let Some((name, value)) = name.split_once('=') {
+                parsed.options.insert(name.into(), value.into());
+            } else if FLAGS.contains(&name) {
+                parsed.flags.push(name.into());
+            } else {
+                let value = args.next().ok_or_else(|| CliError::Usage(format!("--{} needs a value", name)))?;
+                parsed.options.insert(name.into(), value);
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the `n`th positional argument (the subcommand is the first).
+    pub fn positional(&self, n: usize, what: &str) -> Result<&str, CliError> {
+        self.positional.get(n).map(String::as_str).ok_or_else(|| CliError::Usage(format!("missing {}", what)))
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, CliError> {
+        self.option(name).ok_or_else(|| CliError::Usage(format!("missing --{}", name)))
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    /// Parses a positional argument or option value.
+    pub fn parse_value<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, CliError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        value.parse().map_err(|e| CliError::Usage(format!("invalid {} {:?}: {}", what, value, e)))
+    }
+}
@@ -0,0 +1,87 @@
+//! Credentials and environment, from flags, environment variables and profile files.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use webpay::client::{Credentials, Environment};
+
+use crate::args::Args;
+use crate::CliError;
+
+/// The public Webpay Plus integration credentials, used when nothing else is configured
+/// and the environment is `integration`.
+const INTEGRATION_COMMERCE_CODE: &str = "597055555532";
+const INTEGRATION_API_KEY: &str = "579B532A7440BB0C9079DED94D31EA1615BACEB56610332264630D42D0A36B1C";
+
+pub struct Config {
+    pub env: Environment,
+    pub creds: Credentials,
+}
+
+impl Config {
+    /// Resolves each setting from, in order: command-line flags, `WEBPAY_*` environment
+    /// variables, then the selected profile.
+    pub fn load(args: &Args) -> Result<Self, CliError> {
+        let profile = load_profile(args)?;
+        let setting = |flag: &str, var: &str| {
+            args.option(flag)
+                .map(str::to_string)
+                .or_else(|| std::env::var(var).ok().filter(|v| !v.is_empty()))
+                .or_else(|| profile.get(flag.replace('-', "_").as_str()).cloned())
+        };
+
+        let env = match setting("env", "WEBPAY_ENV").as_deref().unwrap_or("integration") {
+            "integration" => Environment::Integration,
+            "production" => Environment::Production,
+            url if url.starts_with("http://") || url.starts_with("https://") => Environment::Custom(url.trim_end_matches('/').into()),
+            other => return Err(CliError::Config(format!("unknown environment {:?} (expected integration, production or a URL)", other))),
+        };
+        let creds = match (setting("commerce-code", "WEBPAY_COMMERCE_CODE"), setting("api-key", "WEBPAY_API_KEY")) {
+            (Some(commerce_code), Some(api_key)) => Credentials { commerce_code, api_key },
+            (None, None) if !matches!(env, Environment::Production) => {
+                Credentials { commerce_code: INTEGRATION_COMMERCE_CODE.into(), api_key: INTEGRATION_API_KEY.into() }
+            }
+            _ => return Err(CliError::Config("both a commerce code and an API key are required".into())),
+        };
+        Ok(Self { env, creds })
+    }
+}
+
+/// Reads the `[name]` section of the profile file, if any.
+///
+/// The file is INI-like: `[profile]` headers followed by `key = value` lines, with `#`
+/// comments. Keys are `env`, `commerce_code` and `api_key`.
+fn load_profile(args: &Args) -> Result<HashMap<String, String>, CliError> {
+    let name = args.option("profile").map(str::to_string).or_else(|| std::env::var("WEBPAY_PROFILE").ok());
+    let explicit = args.option("profile-file").map(PathBuf::from).or_else(|| std::env::var_os("WEBPAY_PROFILE_FILE").map(PathBuf::from));
+    let path = match explicit.clone().or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".webpay").join("credentials"))) {
+        Some(path) => path,
+        None => return Ok(HashMap::new()),
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        // A missing default file is fine unless a profile was asked for.
+        Err(_) if explicit.is_none() && name.is_none() => return Ok(HashMap::new()),
+        Err(e) => return Err(CliError::Config(format!("cannot read {}: {}", path.display(), e))),
+    };
+
+    let name = name.unwrap_or_else(|| "default".into());
+    let mut section = None;
+    let mut found = false;
+    let mut values = HashMap::new();
+    for line in contents.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = Some(header.trim().to_string());
+            found |= header.trim() == name;
+        } else if section.as_deref() == Some(name.as_str()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| CliError::Config(format!("{}: expected `key = value`, got {:?}", path.display(), line)))?;
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    if !found && (explicit.is_some() || name != "default") {
+        return Err(CliError::Config(format!("profile {:?} not found in {}", name, path.display())));
+    }
+    Ok(values)
+}
@@ -0,0 +1,215 @@
+//! `webpay`: check, commit, refund and capture Webpay Plus transactions from the shell
+//! (feature `cli`). Run `webpay --help` for usage.
+
+mod args;
+mod config;
+mod output;
+
+use std::process::ExitCode;
+
+use serde_json::json;
+use webpay::bulk::{parse_refunds, refund_results_csv, InvalidBatch, RateLimiter, RefundJournal, RefundOutcome, RefundRunner};
+use webpay::client::{ApiVersion, Product, WebpayClient};
+use webpay::types::{CreateRequest, TokenWs, WebpayError};
+
+use crate::args::Args;
+use crate::config::Config;
+
+const USAGE: &str = "\
+Usage: webpay [OPTIONS] <COMMAND>
+
+Commands:
+  create --buy-order <ID> --session-id <ID> --amount <CLP> --return-url <URL>
+  status <TOKEN>
+  commit <TOKEN>
+  refund <TOKEN> <AMOUNT>
+  capture <TOKEN> --buy-order <ID> --authorization-code <CODE> --amount <CLP>
+  refund-batch <CSV> [--output <CSV>] [--journal <PATH>] [--concurrency <N>] [--rate <PER_SECOND>]
+      refunds every token,amount row; re-running skips rows already refunded
+      (journal defaults to <CSV>.journal, results go to stdout by default)
+
+Options:
+  --env <ENV>             integration (default), production or a base URL [WEBPAY_ENV]
+  --commerce-code <CODE>  [WEBPAY_COMMERCE_CODE]
+  --api-key <KEY>         [WEBPAY_API_KEY]
+  --profile <NAME>        profile to read from the profile file [WEBPAY_PROFILE]
+  --profile-file <PATH>   defaults to ~/.webpay/credentials [WEBPAY_PROFILE_FILE]
+  --api-version <VER>     Webpay Plus API version, e.g. 1.2
+  --json                  print JSON instead of tables
+  --help                  print this help
+
+Without credentials, the public integration credentials are used.
+
+Exit codes: 0 success, 2 usage or invalid input, 3 configuration, 4 rejected or
+declined by Transbank (for batches: some rows failed), 5 network or transport failure, 1 anything
+else.
+";
+
+/// Everything that can make the tool fail, each with its own exit code.
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}")]
+    Config(String),
+    #[error(transparent)]
+    Webpay(#[from] WebpayError),
+    #[error(transparent)]
+    Batch(#[from] InvalidBatch),
+    #[error("{0} of {1} rows were not refunded")]
+    BatchIncomplete(usize, usize),
+    #[error("{operation} declined with response code {}", response_code.map_or_else(|| "none".to_string(), |c| c.to_string()))]
+    Declined { operation: &'static str, response_code: Option<i32> },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) | CliError::Batch(_) => 2,
+            CliError::BatchIncomplete(..) | CliError::Declined { .. } => 4,
+            CliError::Io(_) => 1,
+            CliError::Config(_) => 3,
+            CliError::Webpay(WebpayError::Http(_) | WebpayError::Transport(_)) => 5,
+            CliError::Webpay(WebpayError::Status(_) | WebpayError::StatusExpired(_) | WebpayError::Unsupported { .. } | WebpayError::InvalidId(_)) => 4,
+            CliError::Webpay(_) => 1,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            CliError::Usage(_) => "usage",
+            CliError::Batch(_) => "invalid_batch",
+            CliError::BatchIncomplete(..) => "batch_incomplete",
+            CliError::Declined { .. } => "declined",
+            CliError::Io(_) => "io",
+            CliError::Config(_) => "config",
+            CliError::Webpay(WebpayError::Http(_) | WebpayError::Transport(_)) => "transport",
+            CliError::Webpay(_) => "webpay",
+        }
+    }
+
+    fn report(&self, json: bool) {
+        if !json {
+            eprintln!("error: {}", self);
+            match self {
+                CliError::Usage(_) => eprintln!("\n{}", USAGE),
+                CliError::Batch(InvalidBatch(errors)) => errors.iter().for_each(|e| eprintln!("  {}", e)),
+                _ => {}
+            }
+            return;
+        }
+        let mut error = json!({ "kind": self.kind(), "message": self.to_string(), "exit_code": self.exit_code() });
+        if let CliError::Webpay(WebpayError::Status(e) | WebpayError::StatusExpired(e)) = self {
+            error["status"] = json!(e.status.as_u16());
+            error["operation"] = json!(e.operation);
+            error["error_message"] = json!(e.message());
+        }
+        if let CliError::Declined { operation, response_code } = self {
+            error["operation"] = json!(operation);
+            error["response_code"] = json!(response_code);
+        }
+        if let CliError::Batch(InvalidBatch(errors)) = self {
+            error["rows"] = errors.iter().map(|e| json!({ "line": e.line, "reason": e.reason })).collect();
+        }
+        eprintln!("{}", json!({ "error": error }));
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            e.report(false);
+            return ExitCode::from(e.exit_code());
+        }
+    };
+    if args.flag("help") {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let runtime = tokio::runtime::Runtime::new().expect("tokio runtime");
+    match runtime.block_on(run(&args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            e.report(args.flag("json"));
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+async fn run(args: &Args) -> Result<(), CliError> {
+    let command = args.positional(0, "command")?;
+    let config = Config::load(args)?;
+    let mut client = WebpayClient::new(config.env, config.creds);
+    if let Some(version) = args.option("api-version") {
+        let version: ApiVersion = Args::parse_value(version, "API version")?;
+        client = client.with_api_version(Product::WebpayPlus, version);
+    }
+    let json = args.flag("json");
+    let token = || -> Result<TokenWs, CliError> { Args::parse_value(args.positional(1, "token")?, "token") };
+
+    match command {
+        "create" => {
+            let req = CreateRequest {
+                buy_order: Args::parse_value(args.required("buy-order")?, "buy order")?,
+                session_id: Args::parse_value(args.required("session-id")?, "session id")?,
+                amount: Args::parse_value(args.required("amount")?, "amount")?,
+                return_url: args.required("return-url")?.into(),
+            };
+            output::print(&client.wp_create(&req).await?, json);
+        }
+        "status" => output::print(&client.wp_status(&token()?).await?, json),
+        "commit" => output::print(&client.wp_commit(&token()?).await?, json),
+        "refund" => {
+            let amount = Args::parse_value(args.positional(2, "amount")?, "amount")?;
+            let response = client.wp_refund(&token()?, amount).await?;
+            output::print(&response, json);
+            if !response.is_approved() {
+                return Err(CliError::Declined { operation: "refund", response_code: response.response_code });
+            }
+        }
+        "capture" => {
+            let buy_order = Args::parse_value(args.required("buy-order")?, "buy order")?;
+            let amount = Args::parse_value(args.required("amount")?, "amount")?;
+            let response = client.wp_capture(&token()?, &buy_order, args.required("authorization-code")?, amount).await?;
+            output::print(&response, json);
+            if response.response_code != Some(0) {
+                return Err(CliError::Declined { operation: "capture", response_code: response.response_code });
+            }
+        }
+        "refund-batch" => refund_batch(args, client).await?,
+        other => return Err(CliError::Usage(format!("unknown command {:?}", other))),
+    }
+    Ok(())
+}
+
+async fn refund_batch(args: &Args, client: WebpayClient) -> Result<(), CliError> {
+    let input = args.positional(1, "input CSV")?;
+    let rows = parse_refunds(&std::fs::read_to_string(input)?)?;
+    let journal = args.option("journal").map(str::to_string).unwrap_or_else(|| format!("{}.journal", input));
+    let mut runner = RefundRunner::new(client).journal(RefundJournal::open(journal)?);
+    if let Some(n) = args.option("concurrency") {
+        runner = runner.concurrency(Args::parse_value(n, "concurrency")?);
+    }
+    if let Some(rate) = args.option("rate") {
+        runner = runner.rate_limit(RateLimiter::per_second(Args::parse_value(rate, "rate")?));
+    }
+
+    let total = rows.len();
+    let results = runner.run(rows).await;
+    let csv = refund_results_csv(&results);
+    match args.option("output") {
+        Some(path) => std::fs::write(path, csv)?,
+        None => print!("{}", csv),
+    }
+    let count = |f: fn(&RefundOutcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    let refunded = count(|o| matches!(o, RefundOutcome::Refunded(_)));
+    let skipped = count(|o| matches!(o, RefundOutcome::Skipped(_)));
+    eprintln!("{} refunded, {} already refunded, {} not refunded", refunded, skipped, total - refunded - skipped);
+    match total - refunded - skipped {
+        0 => Ok(()),
+        n => Err(CliError::BatchIncomplete(n, total)),
+    }
+}
@@ -0,0 +1,36 @@
+//! Human-readable tables and JSON output.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Prints `value` as pretty JSON, or as a two-column table of its (flattened) fields.
+pub fn print<T: Serialize>(value: &T, json: bool) {
+    let value = serde_json::to_value(value).expect("responses serialize to JSON");
+    if json {
+        println!("{}", serde_json::to_string_pretty(&value).expect("JSON values serialize"));
+    } else {
+        print!("{}", table(&value));
+    }
+}
+
+/// Renders the non-null fields of `value`, nested objects as `parent.child`.
+pub fn table(value: &Value) -> String {
+    let mut rows = Vec::new();
+    flatten("", value, &mut rows);
+    let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    rows.iter().map(|(k, v)| format!("{:<width$}  {}\n", k, v, width = width)).collect()
+}
+
+fn flatten(prefix: &str, value: &Value, rows: &mut Vec<(String, String)>) {
+    match value {
+        Value::Null => {}
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, rows);
+            }
+        }
+        Value::String(s) => rows.push((prefix.into(), s.clone())),
+        other => rows.push((prefix.into(), other.to_string())),
+    }
+}
@@ -0,0 +1,168 @@
+//! A synchronous Webpay client (feature `blocking`).
+//!
+//! [`WebpayClient`] mirrors the async `crate::client::WebpayClient`, with the same
+//! request/response types, API version configuration and `WebpayError`, but its
+//! methods block the calling thread. Don't use it from inside an async runtime.
+
+use reqwest::blocking::Client as HttpClient;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::client::{ApiVersion, ApiVersions, Credentials, Environment, Product};
+use crate::types::*;
+use crate::webpay_plus::{gate_fields, wp_decode, wp_path, Operation};
+
+/// The blocking Webpay client.
+#[derive(Clone)]
+pub struct WebpayClient {
+    pub env: Environment,
+    pub creds: Credentials,
+    /// API version used for each product; defaults to v1.2 everywhere.
+    pub versions: ApiVersions,
+    http: HttpClient,
+}
+
+impl WebpayClient {
+    /// Creates a new blocking Webpay client.
+    pub fn new(env: Environment, creds: Credentials) -> Self {
+        Self::new_with_timeout(env, creds, Duration::from_secs(20))
+    }
+
+    /// Creates a new blocking Webpay client with a custom timeout.
+    pub fn new_with_timeout(env: Environment, creds: Credentials, timeout: Duration) -> Self {
+        let http = HttpClient::builder()
+            .timeout(timeout)
+            .build()
+            .expect("reqwest client");
+        Self { env, creds, versions: ApiVersions::default(), http }
+    }
+
+    /// Uses `version` for every call made to `product`.
+    pub fn with_api_version(mut self, product: Product, version: ApiVersion) -> Self {
+        self.versions.set(product, version);
+        self
+    }
+
+    /// Returns the API version used for `product`.
+    pub fn api_version(&self, product: Product) -> ApiVersion {
+        self.versions.get(product)
+    }
+
+    /// Returns a reference to the underlying HTTP client.
+    pub fn http(&self) -> &HttpClient { &self.http }
+
+    /// Returns the full URL for a given path.
+    pub fn endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.env.base_url(), path)
+    }
+
+    /// Calls a Webpay Plus operation and decodes its JSON response, keeping the raw response.
+    fn wp_call<T: DeserializeOwned>(&self, op: Operation, method: Method, path: &str, body: Option<&impl Serialize>) -> Result<RawResponse<T>, WebpayError> {
+        let url = self.endpoint(&wp_path(self.api_version(Product::WebpayPlus), op, path)?);
+        let mut req = self.http.request(method, url).headers(self.creds.request_headers());
+        if let Some(body) = body {
+            req = req.body(serde_json::to_vec(body)?);
+        }
+        let started = Instant::now();
+        let res = req.send()?;
+        let (status, headers) = (res.status(), res.headers().clone());
+        let body = res.bytes()?;
+        let latency = started.elapsed();
+        wp_decode(op, RawResponse { value: (), status, headers, body, latency })
+    }
+
+    /// Create a Webpay Plus transaction. See the async `WebpayClient::wp_create`.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - A `CreateRequest` struct with the transaction details.
+    pub fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+        Ok(self.wp_create_raw(req)?.value)
+    }
+
+    /// Like [`wp_create`](Self::wp_create), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub fn wp_create_raw(&self, req: &CreateRequest) -> Result<RawResponse<CreateResponse>, WebpayError> {
+        self.wp_call(Operation::Create, Method::POST, "/transactions", Some(req))
+    }
+
+    /// Commit (confirm) a Webpay Plus transaction. See the async `WebpayClient::wp_commit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ws` - The token received in the `CreateResponse`.
+    pub fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        Ok(self.wp_commit_raw(token_ws)?.value)
+    }
+
+    /// Like [`wp_commit`](Self::wp_commit), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub fn wp_commit_raw(&self, token_ws: &TokenWs) -> Result<RawResponse<CommitResponse>, WebpayError> {
+        let path = format!("/transactions/{}", token_ws);
+        let mut commit: RawResponse<CommitResponse> = self.wp_call(Operation::Commit, Method::PUT, &path, None::<&()>)?;
+        gate_fields(self.api_version(Product::WebpayPlus), &mut commit.value);
+        Ok(commit)
+    }
+
+    /// Get the status of a Webpay Plus transaction. See the async `WebpayClient::wp_status`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ws` - The token received in the `CreateResponse`.
+    pub fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        Ok(self.wp_status_raw(token_ws)?.value)
+    }
+
+    /// Like [`wp_status`](Self::wp_status), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub fn wp_status_raw(&self, token_ws: &TokenWs) -> Result<RawResponse<StatusResponse>, WebpayError> {
+        let path = format!("/transactions/{}", token_ws);
+        let mut status: RawResponse<StatusResponse> = self.wp_call(Operation::Status, Method::GET, &path, None::<&()>)?;
+        gate_fields(self.api_version(Product::WebpayPlus), &mut status.value);
+        Ok(status)
+    }
+
+    /// Refund a Webpay Plus transaction. See the async `WebpayClient::wp_refund`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ws` - The token of the transaction to refund.
+    /// * `amount` - The amount to refund.
+    pub fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        Ok(self.wp_refund_raw(token_ws, amount)?.value)
+    }
+
+    /// Like [`wp_refund`](Self::wp_refund), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub fn wp_refund_raw(&self, token_ws: &TokenWs, amount: i64) -> Result<RawResponse<RefundResponse>, WebpayError> {
+        let path = format!("/transactions/{}/refunds", token_ws);
+        let req = RefundRequest { amount };
+        self.wp_call(Operation::Refund, Method::POST, &path, Some(&req))
+    }
+
+    /// Capture a Webpay Plus transaction. See the async `WebpayClient::wp_capture`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ws` - The token of the transaction to capture.
+    /// * `buy_order` - The buy order of the transaction.
+    /// * `authorization_code` - The authorization code returned by `wp_commit`.
+    /// * `capture_amount` - The amount to capture (up to the authorized amount).
+    pub fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        Ok(self.wp_capture_raw(token_ws, buy_order, authorization_code, capture_amount)?.value)
+    }
+
+    /// Like [`wp_capture`](Self::wp_capture), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub fn wp_capture_raw(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<RawResponse<CaptureResponse>, WebpayError> {
+        let path = format!("/transactions/{}/capture", token_ws);
+        let req = CaptureRequest {
+            buy_order: buy_order.clone(),
+            authorization_code: authorization_code.into(),
+            capture_amount,
+        };
+        self.wp_call(Operation::Capture, Method::PUT, &path, Some(&req))
+    }
+}
@@ -0,0 +1,426 @@
+//! Batch operations over many transactions.
+//!
+//! [`RefundRunner`] refunds the rows of a CSV file (see [`parse_refunds`]) with bounded
+//! concurrency and an optional [`RateLimiter`], and writes the outcome of each row with
+//! [`refund_results_csv`]. With a [`RefundJournal`], a batch can be re-run after a crash
+//! or a partial failure: rows already refunded are skipped, and rows whose refund was
+//! started but never recorded are reported instead of being refunded twice.
+//!
+//! [`status_stream`] (or `WebpayClient::wp_status_many`) looks up many tokens, streaming
+//! results as they complete.
+
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::client::WebpayClient;
+use crate::csv;
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+
+/// A row of a batch file that could not be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Every invalid row of a batch file. Nothing is processed if any row is invalid.
+#[derive(Clone, Debug, thiserror::Error)]
+#[error("{} invalid row(s), first: {}", .0.len(), .0[0])]
+pub struct InvalidBatch(pub Vec<RowError>);
+
+/// One refund to make.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RefundRow {
+    /// The line of the batch file the row comes from.
+    pub line: usize,
+    pub token: TokenWs,
+    pub amount: i64,
+}
+
+/// Parses refund rows from CSV with a header naming a `token` (or `token_ws`) column and an
+/// `amount` column; other columns are ignored. Amounts must be positive integers and
+/// each token may appear only once.
+pub fn parse_refunds(input: &str) -> Result<Vec<RefundRow>, InvalidBatch> {
+    let invalid = |line, reason: &str| InvalidBatch(vec![RowError { line, reason: reason.into() }]);
+    let records = csv::read(input, ',').map_err(|e| invalid(e.line, e.reason))?;
+    let mut records = records.into_iter();
+    let (header_line, header) = records.next().ok_or_else(|| invalid(1, "empty file"))?;
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.trim().to_ascii_lowercase().as_str()));
+    let (token_col, amount_col) = match (column(&["token", "token_ws"]), column(&["amount"])) {
+        (Some(t), Some(a)) => (t, a),
+        _ => return Err(invalid(header_line, "header must have `token` and `amount` columns")),
+    };
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for (line, fields) in records {
+        let field = |i: usize| fields.get(i).map(|f| f.trim()).unwrap_or("");
+        let token = match field(token_col).parse::<TokenWs>() {
+            Ok(token) => token,
+            Err(e) => {
+                errors.push(RowError { line, reason: e.to_string() });
+                continue;
+            }
+        };
+        match field(amount_col).parse::<i64>() {
+            Ok(amount) if amount > 0 => {
+                if seen.insert(token.clone()) {
+                    rows.push(RefundRow { line, token, amount });
+                } else {
+                    errors.push(RowError { line, reason: format!("token {} appears more than once", token) });
+                }
+            }
+            _ => errors.push(RowError { line, reason: format!("invalid amount {:?}", field(amount_col)) }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(rows)
+    } else {
+        Err(InvalidBatch(errors))
+    }
+}
+
+/// Spaces calls out evenly, shared by every task of a batch.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Allows `calls` calls per second.
+    pub fn per_second(calls: u32) -> Self {
+        Self::every(Duration::from_secs(1) / calls.max(1))
+    }
+
+    /// Allows one call per `interval`.
+    pub fn every(interval: Duration) -> Self {
+        Self { interval, next: tokio::sync::Mutex::new(Instant::now()) }
+    }
+
+    /// Waits for the next free slot.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next = self.next.lock().await;
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum JournalEntry {
+    Started { token: TokenWs, amount: i64 },
+    Failed {
+        token: TokenWs,
+        amount: i64,
+        /// Transbank's answer, when it declined the refund.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        response: Option<RefundResponse>,
+    },
+    Refunded { token: TokenWs, amount: i64, response: RefundResponse },
+}
+
+/// An append-only record of a batch's refunds, one JSON object per line.
+///
+/// Each refund is journaled as `started` before calling Transbank, then as `refunded`,
+/// or as `failed` when Transbank rejected or declined it. A `started` entry with neither follow-up
+/// (the process died, or the call failed without a Transbank answer) means the refund
+/// may or may not have happened.
+pub struct RefundJournal {
+    file: Mutex<File>,
+    refunded: HashMap<TokenWs, RefundResponse>,
+    in_doubt: HashSet<TokenWs>,
+}
+
+impl RefundJournal {
+    /// Opens (or creates) the journal at `path` and loads its entries.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = match std::fs::read_to_string(path.as_ref()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut refunded = HashMap::new();
+        let mut in_doubt = HashSet::new();
+        for (n, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let entry: JournalEntry = serde_json::from_str(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("journal line {}: {}", n + 1, e)))?;
+            match entry {
+                JournalEntry::Started { token, .. } => {
+                    in_doubt.insert(token);
+                }
+                JournalEntry::Failed { token, .. } => {
+                    in_doubt.remove(&token);
+                }
+                JournalEntry::Refunded { token, response, .. } => {
+                    in_doubt.remove(&token);
+                    refunded.insert(token, response);
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file), refunded, in_doubt })
+    }
+
+    fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+}
+
+/// What happened to a [`RefundRow`].
+#[derive(Debug)]
+pub enum RefundOutcome {
+    Refunded(RefundResponse),
+    /// Transbank answered but didn't approve the refund (see
+    /// [`RefundResponse::is_approved`]). Journaled as failed, so a re-run retries it.
+    Declined(RefundResponse),
+    /// Refunded by an earlier run, according to the journal.
+    Skipped(RefundResponse),
+    /// An earlier run started this refund but never recorded its result. It is not
+    /// retried; check it with `wp_status` and fix the journal by hand.
+    InDoubt,
+    /// The journal couldn't record the start of the refund (see
+    /// [`RefundResult::journal_error`]), so it wasn't attempted.
+    NotStarted,
+    Failed(WebpayError),
+}
+
+/// A row and its outcome.
+#[derive(Debug)]
+pub struct RefundResult {
+    pub row: RefundRow,
+    pub outcome: RefundOutcome,
+    /// Why the journal couldn't record this row. If the refund was attempted, the next
+    /// run reports it as in doubt.
+    pub journal_error: Option<io::Error>,
+}
+
+/// Runs refund batches. See the [module documentation](self).
+pub struct RefundRunner<G> {
+    gateway: G,
+    concurrency: usize,
+    limiter: Option<RateLimiter>,
+    journal: Option<RefundJournal>,
+}
+
+impl<G: PaymentGateway> RefundRunner<G> {
+    /// Creates a runner making up to 4 refunds at a time, without rate limit or journal.
+    pub fn new(gateway: G) -> Self {
+        Self { gateway, concurrency: 4, limiter: None, journal: None }
+    }
+
+    /// Sets how many refunds may be in flight at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Limits how often refunds start.
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Journals refunds, and skips those the journal already has.
+    pub fn journal(mut self, journal: RefundJournal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Returns the wrapped gateway.
+    pub fn gateway(&self) -> &G { &self.gateway }
+
+    /// Refunds every row and returns the results in row order.
+    ///
+    /// A journal write failure only affects its row: see [`RefundResult::journal_error`].
+    pub async fn run(&self, rows: Vec<RefundRow>) -> Vec<RefundResult> {
+        let mut done: Vec<_> = stream::iter(rows)
+            .map(|row| self.refund(row))
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+        done.sort_by_key(|r| r.row.line);
+        done
+    }
+
+    async fn refund(&self, row: RefundRow) -> RefundResult {
+        let result = |row, outcome, journal_error| RefundResult { row, outcome, journal_error };
+        if let Some(journal) = &self.journal {
+            if let Some(response) = journal.refunded.get(&row.token) {
+                return result(row, RefundOutcome::Skipped(response.clone()), None);
+            }
+            if journal.in_doubt.contains(&row.token) {
+                return result(row, RefundOutcome::InDoubt, None);
+            }
+        }
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+
+        let (token, amount) = (row.token.clone(), row.amount);
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.append(&JournalEntry::Started { token: token.clone(), amount }) {
+                return result(row, RefundOutcome::NotStarted, Some(e));
+            }
+        }
+        let (outcome, entry) = match self.gateway.wp_refund(&row.token, row.amount).await {
+            Ok(response) if response.is_approved() => {
+                let entry = JournalEntry::Refunded { token, amount, response: response.clone() };
+                (RefundOutcome::Refunded(response), Some(entry))
+            }
+            Ok(response) => {
+                let entry = JournalEntry::Failed { token, amount, response: Some(response.clone()) };
+                (RefundOutcome::Declined(response), Some(entry))
+            }
+            Err(e) => {
+                // Only a rejection by Transbank proves the refund didn't happen; after a
+                // network error it stays in doubt.
+                let rejected = matches!(e, WebpayError::Status(_) | WebpayError::Unsupported { .. });
+                let entry = rejected.then_some(JournalEntry::Failed { token, amount, response: None });
+                (RefundOutcome::Failed(e), entry)
+            }
+        };
+        let journal_error = match (&self.journal, entry) {
+            (Some(journal), Some(entry)) => journal.append(&entry).err(),
+            _ => None,
+        };
+        result(row, outcome, journal_error)
+    }
+}
+
+/// Renders results as CSV: the row, its outcome and the `RefundResponse` fields.
+pub fn refund_results_csv(results: &[RefundResult]) -> String {
+    let mut out = String::new();
+    csv::write(&mut out, &[
+        "line", "token", "amount", "outcome", "type", "authorization_code", "authorization_date",
+        "nullified_amount", "balance", "response_code", "error",
+    ]);
+    for result in results {
+        let (outcome, response, mut error) = match &result.outcome {
+            RefundOutcome::Refunded(r) => ("refunded", Some(r), String::new()),
+            RefundOutcome::Declined(r) => ("declined", Some(r), "refund declined by Transbank".into()),
+            RefundOutcome::Skipped(r) => ("skipped", Some(r), String::new()),
+            RefundOutcome::InDoubt => ("in_doubt", None, "refund started by an earlier run, result unknown".into()),
+            RefundOutcome::NotStarted => ("not_started", None, String::new()),
+            RefundOutcome::Failed(e) => ("failed", None, e.to_string()),
+        };
+        if let Some(e) = &result.journal_error {
+            let separator = if error.is_empty() { "" } else { "; " };
+            error = format!("{}{}journal: {}", error, separator, e);
+        }
+        let field = |f: fn(&RefundResponse) -> Option<String>| response.and_then(f).unwrap_or_default();
+        csv::write(&mut out, &[
+            &result.row.line.to_string(),
+            result.row.token.as_str(),
+            &result.row.amount.to_string(),
+            outcome,
+            &field(|r| r.type_.clone()),
+            &field(|r| r.authorization_code.clone()),
+            &field(|r| r.authorization_date.map(|d| d.to_rfc3339())),
+            &field(|r| r.nullified_amount.map(|v| v.to_string())),
+            &field(|r| r.balance.map(|v| v.to_string())),
+            &field(|r| r.response_code.map(|v| v.to_string())),
+            &error,
+        ]);
+    }
+    out
+}
+
+/// Limits for [`status_stream`].
+#[derive(Clone, Copy, Debug)]
+pub struct BulkOptions {
+    /// How many requests may be in flight at once. Defaults to 8.
+    pub concurrency: usize,
+    /// How many requests may start per second. Unlimited by default.
+    pub per_second: Option<u32>,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self { concurrency: 8, per_second: None }
+    }
+}
+
+/// Calls `wp_status` for every token and yields each result as it completes, in no
+/// particular order.
+///
+/// Transactions older than 7 days fail with `WebpayError::StatusExpired`, every other
+/// failure is a real error worth retrying or investigating.
+pub fn status_stream<'a, G, I>(gateway: &'a G, tokens: I, options: BulkOptions) -> impl Stream<Item = (TokenWs, Result<StatusResponse, WebpayError>)> + Send + 'a
+where
+    G: PaymentGateway + ?Sized,
+    I: IntoIterator<Item = TokenWs>,
+    I::IntoIter: Send + 'a,
+{
+    let limiter = options.per_second.map(|n| Arc::new(RateLimiter::per_second(n)));
+    stream::iter(tokens)
+        .map(move |token| {
+            let limiter = limiter.clone();
+            async move {
+                if let Some(limiter) = limiter {
+                    limiter.acquire().await;
+                }
+                let result = gateway.wp_status(&token).await;
+                (token, result)
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+}
+
+impl WebpayClient {
+    /// Get the status of many Webpay Plus transactions. See [`status_stream`].
+    ///
+    /// ```no_run
+    /// # async fn f(client: webpay::client::WebpayClient, tokens: Vec<webpay::types::TokenWs>) {
+    /// use futures_util::StreamExt;
+    /// use webpay::bulk::BulkOptions;
+    /// use webpay::types::WebpayError;
+    ///
+    /// let options = BulkOptions { concurrency: 8, per_second: Some(20) };
+    /// let mut results = client.wp_status_many(tokens, options);
+    /// while let Some((token, result)) = results.next().await {
+    ///     match result {
+    ///         Ok(status) => println!("{} {}", token, status.status),
+    ///         Err(WebpayError::StatusExpired(_)) => println!("{} is older than 7 days", token),
+    ///         Err(e) => eprintln!("{} failed: {}", token, e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - The tokens to look up.
+    /// * `options` - Concurrency and rate limits.
+    pub fn wp_status_many<'a, I>(&'a self, tokens: I, options: BulkOptions) -> impl Stream<Item = (TokenWs, Result<StatusResponse, WebpayError>)> + Send + 'a
+    where
+        I: IntoIterator<Item = TokenWs>,
+        I::IntoIter: Send + 'a,
+    {
+        status_stream(self, tokens, options)
+    }
+}
@@ -0,0 +1,145 @@
+use serde::Deserialize;
+
+use crate::gateway::PaymentGateway;
+use crate::lifecycle::{AuthorizedTransaction, CommitOutcome, RejectedTransaction};
+use crate::types::*;
+use crate::verify::{commit_verified, ExpectedOrder};
+
+/// The parameters Webpay sends to the `return_url`, as a POST form or a GET query.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ReturnParams {
+    /// Present when the cardholder finished the payment form (authorized or rejected).
+    pub token_ws: Option<String>,
+    /// Present when the cardholder aborted the payment.
+    #[serde(rename = "TBK_TOKEN")]
+    pub tbk_token: Option<String>,
+    /// Echo of the buy order, present on abort and timeout.
+    #[serde(rename = "TBK_ORDEN_COMPRA")]
+    pub tbk_orden_compra: Option<String>,
+    /// Echo of the session id, present on abort and timeout.
+    #[serde(rename = "TBK_ID_SESION")]
+    pub tbk_id_sesion: Option<String>,
+}
+
+/// Why a return callback could not be understood.
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidReturn {
+    #[error("no token_ws, TBK_TOKEN or TBK_ORDEN_COMPRA received")]
+    Missing,
+    #[error(transparent)]
+    InvalidId(#[from] InvalidId),
+}
+
+/// A Webpay return callback, classified by which parameters it carries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WebpayReturn {
+    /// `token_ws` only: the payment form was completed and the transaction must be committed.
+    Completed { token: TokenWs },
+    /// `TBK_TOKEN`, `TBK_ORDEN_COMPRA` and `TBK_ID_SESION`: the cardholder aborted the payment.
+    Aborted { token: TokenWs, buy_order: BuyOrder, session_id: SessionId },
+    /// `TBK_ORDEN_COMPRA` and `TBK_ID_SESION` only: the payment form timed out.
+    TimedOut { buy_order: BuyOrder, session_id: SessionId },
+    /// Both `token_ws` and `TBK_TOKEN`: an error occurred in the payment form.
+    FormError { token: TokenWs, buy_order: Option<BuyOrder>, session_id: Option<SessionId> },
+}
+
+impl ReturnParams {
+    /// Classifies the callback. Empty values are treated as missing.
+    ///
+    /// The echoed `TBK_ORDEN_COMPRA` and `TBK_ID_SESION` are only validated when the case
+    /// uses them, so a completed payment is never refused because of them.
+    pub fn classify(&self) -> Result<WebpayReturn, InvalidReturn> {
+        fn field(v: &Option<String>) -> Option<&str> {
+            v.as_deref().map(str::trim).filter(|s| !s.is_empty())
+        }
+        let echoes = || -> Result<(Option<BuyOrder>, Option<SessionId>), InvalidId> {
+            Ok((
+                field(&self.tbk_orden_compra).map(BuyOrder::new).transpose()?,
+                field(&self.tbk_id_sesion).map(SessionId::new).transpose()?,
+            ))
+        };
+
+        match (field(&self.token_ws), field(&self.tbk_token)) {
+            (Some(token), None) => Ok(WebpayReturn::Completed { token: token.parse()? }),
+            (Some(token), Some(_)) => {
+                let (buy_order, session_id) = echoes()?;
+                Ok(WebpayReturn::FormError { token: token.parse()?, buy_order, session_id })
+            }
+            (None, Some(token)) => match echoes()? {
+                (Some(buy_order), Some(session_id)) => Ok(WebpayReturn::Aborted { token: token.parse()?, buy_order, session_id }),
+                _ => Err(InvalidReturn::Missing),
+            },
+            (None, None) => match echoes()? {
+                (Some(buy_order), Some(session_id)) => Ok(WebpayReturn::TimedOut { buy_order, session_id }),
+                _ => Err(InvalidReturn::Missing),
+            },
+        }
+    }
+}
+
+/// What happened to a payment, once its return callback has been handled.
+#[derive(Debug)]
+pub enum PaymentOutcome {
+    /// Committed and authorized.
+    Authorized(AuthorizedTransaction),
+    /// Committed but not authorized (e.g., insufficient funds or rejected by the bank).
+    Rejected(RejectedTransaction),
+    /// The cardholder aborted the payment.
+    Aborted { token: TokenWs, buy_order: BuyOrder, session_id: SessionId },
+    /// The payment form timed out before the cardholder finished.
+    TimedOut { buy_order: BuyOrder, session_id: SessionId },
+    /// An error occurred in the payment form.
+    FormError { token: TokenWs, buy_order: Option<BuyOrder>, session_id: Option<SessionId> },
+    /// The commit call itself failed; the payment state is unknown until `wp_status` says otherwise.
+    ///
+    /// From [`WebpayReturn::process_verified`], `error` may also be `WebpayError::Mismatch`:
+    /// the token was committed, but for another order.
+    CommitFailed { token: TokenWs, error: WebpayError },
+}
+
+impl WebpayReturn {
+    /// Commits completed payments and classifies the result.
+    ///
+    /// Only [`WebpayReturn::Completed`] calls the gateway; every other case is final.
+    pub async fn process<G: PaymentGateway + ?Sized>(self, gateway: &G) -> PaymentOutcome {
+        let token = match self.final_outcome() {
+            Ok(outcome) => return outcome,
+            Err(token) => token,
+        };
+        let result = gateway.wp_commit(&token).await;
+        PaymentOutcome::committed(token, result)
+    }
+
+    /// Like [`process`](Self::process), but commits through [`commit_verified`], so a
+    /// token replayed from another order ends in `CommitFailed` with `WebpayError::Mismatch`.
+    pub async fn process_verified<G: PaymentGateway + ?Sized>(self, gateway: &G, expected: &ExpectedOrder) -> PaymentOutcome {
+        let token = match self.final_outcome() {
+            Ok(outcome) => return outcome,
+            Err(token) => token,
+        };
+        let result = commit_verified(gateway, &token, expected).await;
+        PaymentOutcome::committed(token, result)
+    }
+
+    /// The outcome of every return but a completed one, whose token still needs a commit.
+    fn final_outcome(self) -> Result<PaymentOutcome, TokenWs> {
+        match self {
+            WebpayReturn::Completed { token } => Err(token),
+            WebpayReturn::Aborted { token, buy_order, session_id } => Ok(PaymentOutcome::Aborted { token, buy_order, session_id }),
+            WebpayReturn::TimedOut { buy_order, session_id } => Ok(PaymentOutcome::TimedOut { buy_order, session_id }),
+            WebpayReturn::FormError { token, buy_order, session_id } => Ok(PaymentOutcome::FormError { token, buy_order, session_id }),
+        }
+    }
+}
+
+impl PaymentOutcome {
+    fn committed(token: TokenWs, result: Result<CommitResponse, WebpayError>) -> Self {
+        match result {
+            Ok(commit) => match CommitOutcome::from_response(token, commit) {
+                CommitOutcome::Authorized(t) => PaymentOutcome::Authorized(t),
+                CommitOutcome::Rejected(t) => PaymentOutcome::Rejected(t),
+            },
+            Err(error) => PaymentOutcome::CommitFailed { token, error },
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Record-and-replay HTTP cassettes for tests (feature `testing`).
+//!
+//! A [`Recorder`] wraps a real [`Transport`] and keeps every request/response pair;
+//! [`Recorder::save`] writes them to a cassette file. A [`Replayer`] serves a cassette
+//! back without network, matching requests on method, path and body.
+//!
+//! Cassettes never contain secrets: request headers (including the API key) are not
+//! recorded, and every `token_ws` is replaced with a placeholder, consistently across
+//! requests and responses, so replayed flows still line up.
+//!
+//! ```no_run
+//! # async fn f(client: webpay::client::WebpayClient) -> Result<(), Box<dyn std::error::Error>> {
+//! use webpay::cassette::{Recorder, Replayer};
+//!
+//! // Once, against the integration environment:
+//! let recorder = Recorder::new(webpay::transport::ReqwestTransport::new(reqwest::Client::new()));
+//! let recording = client.clone().with_transport(recorder.clone());
+//! // ... exercise `recording` ...
+//! recorder.save("tests/cassettes/create.json")?;
+//!
+//! // In CI:
+//! let replaying = client.with_transport(Replayer::load("tests/cassettes/create.json")?);
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::transport::Transport;
+use crate::types::WebpayError;
+
+/// A recorded request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// The path and query, without scheme and host, so cassettes replay against any
+    /// environment.
+    pub path: String,
+    /// The JSON body, or `null` for requests without one.
+    #[serde(default)]
+    pub body: Value,
+}
+
+/// A recorded response.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    /// The body as JSON, or as a string if it wasn't JSON.
+    #[serde(default)]
+    pub body: Value,
+}
+
+/// A request and the response it got.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// The contents of a cassette file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Reads a cassette file.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the cassette as pretty JSON, creating parent directories as needed.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut contents = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        contents.push('\n');
+        std::fs::write(path, contents)
+    }
+}
+
+#[derive(Default)]
+struct Recording {
+    cassette: Cassette,
+    /// Real token → placeholder.
+    tokens: HashMap<String, String>,
+}
+
+/// A [`Transport`] that forwards requests to another transport and records them.
+///
+/// Clones share the same recording, so keep one to [`save`](Self::save) after handing
+/// the other to `WebpayClient::with_transport`.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<dyn Transport>,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Recorder {
+    pub fn new(inner: impl Transport + 'static) -> Self {
+        Self { inner: Arc::new(inner), recording: Arc::default() }
+    }
+
+    /// Returns what has been recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.recording.lock().unwrap_or_else(|e| e.into_inner()).cassette.clone()
+    }
+
+    /// Writes what has been recorded so far to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.cassette().save(path)
+    }
+}
+
+#[async_trait]
+impl Transport for Recorder {
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        let method = req.method().to_string();
+        let path = path_of(req.uri());
+        let body = body_value(req.body());
+        let res = self.inner.send(req).await?;
+
+        let mut recording = self.recording.lock().unwrap_or_else(|e| e.into_inner());
+        let Recording { cassette, tokens } = &mut *recording;
+        let interaction = Interaction {
+            request: RecordedRequest { method, path: scrub_str(&path, tokens), body: scrub(body, tokens) },
+            response: RecordedResponse { status: res.status().as_u16(), body: scrub(body_value(res.body()), tokens) },
+        };
+        cassette.interactions.push(interaction);
+        Ok(res)
+    }
+}
+
+/// A [`Transport`] serving the responses of a cassette.
+///
+/// Each request gets the response of the first unused interaction with the same method,
+/// path and (JSON-equal) body. A request with no match fails with
+/// `WebpayError::Transport`.
+pub struct Replayer {
+    interactions: Mutex<Vec<Option<Interaction>>>,
+}
+
+impl Replayer {
+    pub fn new(cassette: Cassette) -> Self {
+        Self { interactions: Mutex::new(cassette.interactions.into_iter().map(Some).collect()) }
+    }
+
+    /// Reads a cassette file and replays it.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(Cassette::load(path)?))
+    }
+
+    /// Returns how many interactions have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.interactions.lock().unwrap_or_else(|e| e.into_inner()).iter().flatten().count()
+    }
+}
+
+#[async_trait]
+impl Transport for Replayer {
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        let request = RecordedRequest { method: req.method().to_string(), path: path_of(req.uri()), body: body_value(req.body()) };
+        let mut interactions = self.interactions.lock().unwrap_or_else(|e| e.into_inner());
+        let interaction = interactions
+            .iter_mut()
+            .find(|i| i.as_ref().is_some_and(|i| i.request == request))
+            .and_then(Option::take)
+            .ok_or_else(|| WebpayError::Transport(format!("no recorded interaction for {} {}", request.method, request.path).into()))?;
+
+        let body = match interaction.response.body {
+            Value::Null => Bytes::new(),
+            Value::String(s) => Bytes::from(s),
+            json => Bytes::from(json.to_string()),
+        };
+        http::Response::builder()
+            .status(interaction.response.status)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .map_err(|e| WebpayError::Transport(e.into()))
+    }
+}
+
+fn path_of(uri: &http::Uri) -> String {
+    uri.path_and_query().map(|p| p.as_str().to_string()).unwrap_or_else(|| "/".into())
+}
+
+fn body_value(body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(body).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+fn scrub(value: Value, tokens: &mut HashMap<String, String>) -> Value {
+    match value {
+        Value::String(s) => Value::String(scrub_str(&s, tokens)),
+        Value::Array(items) => Value::Array(items.into_iter().map(|v| scrub(v, tokens)).collect()),
+        Value::Object(fields) => Value::Object(fields.into_iter().map(|(k, v)| (k, scrub(v, tokens))).collect()),
+        other => other,
+    }
+}
+
+/// Replaces every run of exactly 64 hex characters (a `token_ws`) with a placeholder.
+fn scrub_str(s: &str, tokens: &mut HashMap<String, String>) -> String {
+    let bytes = s.as_bytes();
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let run = bytes[i..].iter().take_while(|b| b.is_ascii_hexdigit()).count();
+        if run == 64 {
+            let next = tokens.len() + 1;
+            out.push_str(tokens.entry(s[i..i + 64].to_string()).or_insert_with(|| format!("{:064x}", next)));
+            i += 64;
+        } else if run > 0 {
+            out.push_str(&s[i..i + run]);
+            i += run;
+        } else {
+            let c = s[i..].chars().next().expect("i is a char boundary");
+            out.push(c);
+            i += c.len_utf8();
+        }
+    }
+    out
+}
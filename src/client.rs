@@ -1,7 +1,13 @@
+use bytes::Bytes;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, ACCEPT};
-use reqwest::Client as HttpClient;
+use reqwest::{Client as HttpClient, Method};
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::transport::{ReqwestTransport, Transport};
+use crate::types::WebpayError;
+
 /// The Transbank environment to use.
 #[derive(Clone, Debug)]
 pub enum Environment {
@@ -9,14 +15,105 @@ pub enum Environment {
     Integration,
     /// Production environment for real transactions.
     Production,
+    /// Any other host (e.g., a local mock server), without a trailing slash.
+    Custom(String),
 }
 
 impl Environment {
     /// Returns the base URL for the environment.
-    pub fn base_url(&self) -> &'static str {
+    pub fn base_url(&self) -> &str {
         match self {
             Environment::Integration => "https://webpay3gint.transbank.cl",
             Environment::Production => "https://webpay3g.transbank.cl",
+            Environment::Custom(url) => url,
+        }
+    }
+}
+
+/// A Transbank product whose REST API is versioned independently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Product {
+    /// Webpay Plus (`/rswebpaytransaction/api/webpay/...`).
+    WebpayPlus,
+}
+
+impl fmt::Display for Product {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Product::WebpayPlus => f.write_str("Webpay Plus"),
+        }
+    }
+}
+
+/// A published version of a Transbank REST API.
+///
+/// Versions are ordered, so `version >= ApiVersion::V1_2` reads as "v1.2 or newer".
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ApiVersion {
+    V1_0,
+    V1_1,
+    V1_2,
+    V1_3,
+}
+
+impl ApiVersion {
+    /// Every version known to this crate, oldest first.
+    pub const ALL: [ApiVersion; 4] = [ApiVersion::V1_0, ApiVersion::V1_1, ApiVersion::V1_2, ApiVersion::V1_3];
+
+    /// Returns the version as it appears in the URL path (e.g., `"v1.2"`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1_0 => "v1.0",
+            ApiVersion::V1_1 => "v1.1",
+            ApiVersion::V1_2 => "v1.2",
+            ApiVersion::V1_3 => "v1.3",
+        }
+    }
+}
+
+impl Default for ApiVersion {
+    /// v1.2, the version this crate has always targeted.
+    fn default() -> Self { ApiVersion::V1_2 }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for ApiVersion {
+    type Err = String;
+
+    /// Parses `"1.3"` or `"v1.3"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix('v').unwrap_or(s);
+        ApiVersion::ALL
+            .into_iter()
+            .find(|v| &v.as_str()[1..] == s)
+            .ok_or_else(|| format!("unknown API version: {}", s))
+    }
+}
+
+/// The API version used for each product.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApiVersions {
+    pub webpay_plus: ApiVersion,
+}
+
+impl ApiVersions {
+    /// Returns the version configured for `product`.
+    pub fn get(&self, product: Product) -> ApiVersion {
+        match product {
+            Product::WebpayPlus => self.webpay_plus,
+        }
+    }
+
+    /// Sets the version used for `product`.
+    pub fn set(&mut self, product: Product, version: ApiVersion) {
+        match product {
+            Product::WebpayPlus => self.webpay_plus = version,
         }
     }
 }
@@ -30,12 +127,33 @@ pub struct Credentials {
     pub api_key: String,
 }
 
+impl Credentials {
+    /// Returns the `Tbk-Api-Key-Id` and `Tbk-Api-Key-Secret` headers.
+    pub fn auth_headers(&self) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert("Tbk-Api-Key-Id", HeaderValue::from_str(&self.commerce_code).unwrap());
+        h.insert("Tbk-Api-Key-Secret", HeaderValue::from_str(&self.api_key).unwrap());
+        h
+    }
+
+    /// Returns the auth headers plus the JSON `Content-Type` and `Accept` headers.
+    pub(crate) fn request_headers(&self) -> HeaderMap {
+        let mut h = self.auth_headers();
+        h.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        h.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        h
+    }
+}
+
 /// The Webpay client.
 #[derive(Clone)]
 pub struct WebpayClient {
     pub env: Environment,
     pub creds: Credentials,
+    /// API version used for each product; defaults to v1.2 everywhere.
+    pub versions: ApiVersions,
     http: HttpClient,
+    transport: Arc<dyn Transport>,
 }
 
 impl WebpayClient {
@@ -50,21 +168,57 @@ impl WebpayClient {
             .timeout(timeout)
             .build()
             .expect("reqwest client");
-        Self { env, creds, http }
+        let transport = Arc::new(ReqwestTransport::new(http.clone()));
+        Self { env, creds, versions: ApiVersions::default(), http, transport }
+    }
+
+    /// Sends every request through `transport` instead of the built-in `reqwest` client.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Uses `version` for every call made to `product`.
+    ///
+    /// ```
+    /// # use webpay::client::{ApiVersion, Credentials, Environment, Product, WebpayClient};
+    /// # let creds = Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() };
+    /// let client = WebpayClient::new(Environment::Integration, creds)
+    ///     .with_api_version(Product::WebpayPlus, ApiVersion::V1_3);
+    /// assert_eq!(client.api_version(Product::WebpayPlus), ApiVersion::V1_3);
+    /// ```
+    pub fn with_api_version(mut self, product: Product, version: ApiVersion) -> Self {
+        self.versions.set(product, version);
+        self
+    }
+
+    /// Returns the API version used for `product`.
+    pub fn api_version(&self, product: Product) -> ApiVersion {
+        self.versions.get(product)
     }
 
     fn headers(&self) -> HeaderMap {
-        let mut h = HeaderMap::new();
-        h.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        h.insert(ACCEPT, HeaderValue::from_static("application/json"));
-        h.insert("Tbk-Api-Key-Id", HeaderValue::from_str(&self.creds.commerce_code).unwrap());
-        h.insert("Tbk-Api-Key-Secret", HeaderValue::from_str(&self.creds.api_key).unwrap());
-        h
+        self.creds.request_headers()
     }
 
-    /// Returns a reference to the underlying HTTP client.
+    /// Returns a reference to the `reqwest` client built by the constructor.
+    ///
+    /// Requests are sent through the configured [`Transport`], which only uses this
+    /// client unless it was replaced with `with_transport`.
     pub fn http(&self) -> &HttpClient { &self.http }
 
+    /// Sends a request to `url` with the Transbank headers and an optional JSON body.
+    pub(crate) async fn execute(&self, method: Method, url: &str, body: Option<Vec<u8>>) -> Result<http::Response<Bytes>, WebpayError> {
+        let mut req = http::Request::builder().method(method).uri(url);
+        if let Some(headers) = req.headers_mut() {
+            *headers = self.headers();
+        }
+        let req = req
+            .body(body.map(Bytes::from).unwrap_or_default())
+            .map_err(|e| WebpayError::Transport(e.into()))?;
+        self.transport.send(req).await
+    }
+
     /// Returns the full URL for a given path.
     pub fn endpoint(&self, path: &str) -> String {
         format!("{}{}", self.env.base_url(), path)
@@ -0,0 +1,200 @@
+//! Exactly-once commits for return callbacks that arrive more than once.
+//!
+//! Browsers double-submit the return form and users hit refresh, but a transaction can
+//! only be committed once: the second `wp_commit` fails with a 422. [`CommitCoordinator`]
+//! makes concurrent commits of the same token share a single call, caches the result,
+//! coordinates with other processes through a [`CommitLock`], and when Transbank reports
+//! the transaction as already committed or locked, answers with `wp_status` instead.
+//!
+//! Results are cached for a while (10 minutes by default); a duplicate arriving after
+//! that is answered by Transbank's conflict and `wp_status`, like one from another process.
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::time::Instant;
+
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+
+/// A lock shared by every process that may commit the same token (e.g., a Redis `SET NX`
+/// or a database advisory lock).
+///
+/// Locks must expire on their own after the `ttl` given to `try_acquire`: `release` is
+/// not called when the holder crashes or the commit future is dropped, and its failures
+/// are only logged.
+#[async_trait]
+pub trait CommitLock: Send + Sync {
+    /// Tries to take the lock for `token`, to expire after `ttl` (e.g., Redis `SET NX PX`).
+    /// Returns `false` if another holder has it.
+    async fn try_acquire(&self, token: &TokenWs, ttl: Duration) -> Result<bool, WebpayError>;
+
+    /// Releases a lock taken with `try_acquire`.
+    async fn release(&self, token: &TokenWs) -> Result<(), WebpayError>;
+}
+
+/// A [`CommitLock`] that always succeeds, for single-process deployments.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalOnly;
+
+#[async_trait]
+impl CommitLock for LocalOnly {
+    async fn try_acquire(&self, _token: &TokenWs, _ttl: Duration) -> Result<bool, WebpayError> { Ok(true) }
+
+    async fn release(&self, _token: &TokenWs) -> Result<(), WebpayError> { Ok(()) }
+}
+
+/// Returns `true` if `error` says the transaction was already committed, or is being
+/// committed by someone else: a 422 whose message mentions an invalid status, a lock or
+/// a previous commit.
+pub fn is_commit_conflict(error: &WebpayError) -> bool {
+    let WebpayError::Status(e) = error else { return false };
+    if e.status.as_u16() != 422 {
+        return false;
+    }
+    let message = e.message().unwrap_or_else(|| e.body.clone()).to_lowercase();
+    ["invalid status", "locked", "already"].iter().any(|m| message.contains(m))
+}
+
+/// Deduplicates commits per token. See the [module documentation](self).
+///
+/// It is also a [`PaymentGateway`], whose `wp_commit` goes through [`commit`](Self::commit)
+/// and whose other calls go straight to the wrapped gateway, so it can replace the
+/// client wherever a gateway is expected (e.g., the axum router).
+pub struct CommitCoordinator<G, L = LocalOnly> {
+    gateway: G,
+    lock: L,
+    commits: Mutex<HashMap<TokenWs, Entry>>,
+    cache_ttl: Duration,
+    lock_ttl: Duration,
+    status_attempts: u32,
+    status_interval: Duration,
+}
+
+struct Entry {
+    cell: Arc<OnceCell<CommitResponse>>,
+    created: Instant,
+}
+
+impl<G: PaymentGateway> CommitCoordinator<G> {
+    /// Coordinates commits within this process only.
+    pub fn new(gateway: G) -> Self {
+        Self::with_lock(gateway, LocalOnly)
+    }
+}
+
+impl<G: PaymentGateway, L: CommitLock> CommitCoordinator<G, L> {
+    /// Coordinates commits across processes with `lock`.
+    pub fn with_lock(gateway: G, lock: L) -> Self {
+        Self {
+            gateway,
+            lock,
+            commits: Mutex::new(HashMap::new()),
+            cache_ttl: Duration::from_secs(600),
+            lock_ttl: Duration::from_secs(120),
+            status_attempts: 5,
+            status_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// How many times, and how often, `wp_status` is polled while another process is
+    /// still committing the token. Defaults to 5 times, once per second.
+    pub fn status_polling(mut self, attempts: u32, interval: Duration) -> Self {
+        self.status_attempts = attempts.max(1);
+        self.status_interval = interval;
+        self
+    }
+
+    /// How long results are cached. Defaults to 10 minutes.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// How long a [`CommitLock`] is held at most, which should exceed the commit timeout.
+    /// Defaults to 2 minutes.
+    pub fn lock_ttl(mut self, ttl: Duration) -> Self {
+        self.lock_ttl = ttl;
+        self
+    }
+
+    /// Returns the wrapped gateway.
+    pub fn gateway(&self) -> &G { &self.gateway }
+
+    /// Commits `token_ws` at most once and returns its result.
+    ///
+    /// Concurrent calls for the same token wait for the first one and get its result;
+    /// later calls get the cached result. If the commit fails, the next call tries again.
+    pub async fn commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        let cell = {
+            let mut commits = self.commits.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            commits.retain(|_, entry| now.duration_since(entry.created) < self.cache_ttl);
+            commits
+                .entry(token_ws.clone())
+                .or_insert_with(|| Entry { cell: Arc::default(), created: now })
+                .cell
+                .clone()
+        };
+        cell.get_or_try_init(|| self.commit_once(token_ws)).await.cloned()
+    }
+
+    /// Drops the cached result of `token_ws`.
+    pub fn forget(&self, token_ws: &TokenWs) {
+        self.commits.lock().unwrap_or_else(|e| e.into_inner()).remove(token_ws);
+    }
+
+    async fn commit_once(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        if !self.lock.try_acquire(token_ws, self.lock_ttl).await? {
+            return self.resolve_via_status(token_ws).await;
+        }
+        let result = self.gateway.wp_commit(token_ws).await;
+        // The commit already happened: a lock that isn't released just expires.
+        if let Err(e) = self.lock.release(token_ws).await {
+            log::warn!("failed to release the commit lock of {}: {}", token_ws, e);
+        }
+        match result {
+            Err(e) if is_commit_conflict(&e) => self.resolve_via_status(token_ws).await,
+            other => other,
+        }
+    }
+
+    /// Waits until `wp_status` shows the transaction is no longer `INITIALIZED`.
+    async fn resolve_via_status(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        for attempt in 0..self.status_attempts {
+            if attempt > 0 {
+                tokio::time::sleep(self.status_interval).await;
+            }
+            let status = self.gateway.wp_status(token_ws).await?;
+            if status.status != "INITIALIZED" {
+                return Ok(status);
+            }
+        }
+        Err(WebpayError::CommitInProgress { token: token_ws.clone() })
+    }
+}
+
+#[async_trait]
+impl<G: PaymentGateway, L: CommitLock> PaymentGateway for CommitCoordinator<G, L> {
+    async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+        self.gateway.wp_create(req).await
+    }
+
+    async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        self.commit(token_ws).await
+    }
+
+    async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        self.gateway.wp_status(token_ws).await
+    }
+
+    async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        self.gateway.wp_refund(token_ws, amount).await
+    }
+
+    async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        self.gateway.wp_capture(token_ws, buy_order, authorization_code, capture_amount).await
+    }
+}
@@ -0,0 +1,77 @@
+//! Minimal RFC 4180 CSV reading and writing, for batch files and reports.
+
+/// A malformed CSV line.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}: {reason}")]
+pub(crate) struct CsvError {
+    pub(crate) line: usize,
+    pub(crate) reason: &'static str,
+}
+
+/// Splits `input` into records of fields, with the 1-based line each record starts on.
+/// Fields may be quoted (`"a ""b"", c"`), quoted fields may span lines, and blank lines
+/// are skipped.
+pub(crate) fn read(input: &str, delimiter: char) -> Result<Vec<(usize, Vec<String>)>, CsvError> {
+    let mut records = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            match chars.next() {
+                None => {
+                    if quoted {
+                        return Err(CsvError { line: start, reason: "unterminated quoted field" });
+                    }
+                    break;
+                }
+                Some('"') if quoted => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                Some('"') if field.is_empty() => quoted = true,
+                Some('\n') if !quoted => {
+                    line += 1;
+                    break;
+                }
+                Some('\r') if !quoted && chars.peek() == Some(&'\n') => {}
+                Some(c) if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+                Some(c) => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+        }
+        fields.push(field);
+        if !(fields.len() == 1 && fields[0].trim().is_empty()) {
+            records.push((start, fields));
+        }
+    }
+    Ok(records)
+}
+
+/// Appends one record to `out`, quoting fields when needed.
+pub(crate) fn write(out: &mut String, fields: &[&str]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push('\n');
+}
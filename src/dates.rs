@@ -0,0 +1,134 @@
+//! Dates and times as Transbank sends them.
+//!
+//! Timestamps such as `transaction_date` are parsed leniently by [`parse_timestamp`]:
+//! RFC 3339 with or without an offset, a space instead of the `T`, offsets without a
+//! colon, and Unix epochs. The response types use it, so an unexpected format leaves the
+//! field `None` instead of failing the commit, and the original value goes to `extra`.
+//!
+//! `accounting_date` comes as `"MMDD"`; [`accounting_date`] infers its year from a
+//! nearby timestamp. [`to_santiago`] converts timestamps to Chilean local time for
+//! receipts.
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::{America::Santiago, Tz};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+/// Parses a timestamp in any format Transbank has been seen to send. Timestamps without
+/// an offset are taken as UTC, and numbers as seconds (or milliseconds, if too large to
+/// be seconds) since the Unix epoch.
+///
+/// ```
+/// # use webpay::dates::parse_timestamp;
+/// let expected = parse_timestamp("2019-05-22T16:41:21.063Z").unwrap();
+/// assert_eq!(parse_timestamp("2019-05-22T16:41:21.063"), Some(expected));
+/// assert_eq!(parse_timestamp("2019-05-22 12:41:21.063-0400"), Some(expected));
+/// assert_eq!(parse_timestamp("1558543281063"), Some(expected));
+/// ```
+pub fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+        return from_epoch(s.parse().ok()?);
+    }
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    let s = s.replacen(' ', "T", 1);
+    for format in ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%dT%H:%M%z"] {
+        if let Ok(t) = DateTime::parse_from_str(&s, format) {
+            return Some(t.with_timezone(&Utc));
+        }
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(&s, format) {
+            return Some(t.and_utc());
+        }
+    }
+    NaiveDate::parse_from_str(&s, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)).map(|t| t.and_utc())
+}
+
+fn from_epoch(n: i64) -> Option<DateTime<Utc>> {
+    // Seconds reach 10^11 in the year 5138; milliseconds passed it in 1973.
+    if n.abs() >= 100_000_000_000 {
+        DateTime::from_timestamp_millis(n)
+    } else {
+        DateTime::from_timestamp(n, 0)
+    }
+}
+
+/// Deserializes an optional timestamp with [`parse_timestamp`]. Missing, null, empty and
+/// unrecognized values become `None`.
+pub fn deserialize_timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    Ok(match Option::<serde_json::Value>::deserialize(deserializer)? {
+        Some(serde_json::Value::String(s)) => parse_timestamp(&s),
+        Some(serde_json::Value::Number(n)) => n.as_i64().and_then(from_epoch),
+        _ => None,
+    })
+}
+
+/// A response type with a timestamp read by [`deserialize_timestamp`] and an `extra` map.
+pub(crate) trait Timestamped: Sized {
+    /// The JSON key of the timestamp.
+    const TIMESTAMP: &'static str;
+
+    /// Deserializes with the derived implementation.
+    fn from_json(value: Value) -> Result<Self, serde_json::Error>;
+
+    fn timestamp(&self) -> Option<DateTime<Utc>>;
+
+    fn extra_mut(&mut self) -> &mut Map<String, Value>;
+}
+
+/// Deserializes `T`, moving a timestamp that didn't parse to `extra` as it was sent.
+pub(crate) fn deserialize_timestamped<'de, D: Deserializer<'de>, T: Timestamped>(deserializer: D) -> Result<T, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let original = value.get(T::TIMESTAMP).filter(|v| !v.is_null()).cloned();
+    let mut this = T::from_json(value).map_err(D::Error::custom)?;
+    if let (Some(original), None) = (original, this.timestamp()) {
+        this.extra_mut().insert(T::TIMESTAMP.into(), original);
+    }
+    Ok(this)
+}
+
+/// Returns the date of an `"MMDD"` accounting date, taking the year that puts it closest
+/// to `near` (usually the transaction date), so a `"1231"` accounting date near January 1st
+/// falls in the previous year and a `"0101"` near December 31st in the next.
+///
+/// ```
+/// # use webpay::dates::accounting_date;
+/// # use chrono::NaiveDate;
+/// let near = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+/// assert_eq!(accounting_date("1231", near), NaiveDate::from_ymd_opt(2023, 12, 31));
+/// assert_eq!(accounting_date("0102", near), NaiveDate::from_ymd_opt(2024, 1, 2));
+/// ```
+pub fn accounting_date(mmdd: &str, near: NaiveDate) -> Option<NaiveDate> {
+    let mmdd = mmdd.trim();
+    if mmdd.len() != 4 || !mmdd.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (month, day) = (mmdd[..2].parse().ok()?, mmdd[2..].parse().ok()?);
+    [near.year() - 1, near.year(), near.year() + 1]
+        .into_iter()
+        .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+        .min_by_key(|date| (*date - near).num_days().abs())
+}
+
+/// Converts `at` to Santiago local time, following the tz database's `America/Santiago`.
+///
+/// ```
+/// # use webpay::dates::{parse_timestamp, to_santiago};
+/// let at = parse_timestamp("2024-01-15T15:00:00Z").unwrap();
+/// assert_eq!(to_santiago(at).to_rfc3339(), "2024-01-15T12:00:00-03:00");
+/// ```
+pub fn to_santiago(at: DateTime<Utc>) -> DateTime<Tz> {
+    at.with_timezone(&Santiago)
+}
+
+/// Returns today's date in Santiago.
+pub fn santiago_today() -> NaiveDate {
+    to_santiago(Utc::now()).date_naive()
+}
@@ -0,0 +1,316 @@
+//! Payment lifecycle events.
+//!
+//! Wrap a gateway in [`EventGateway`] to have every call Transbank answered reported to an
+//! [`EventSink`] as a [`PaymentEvent`], so order, fulfilment and accounting code can react
+//! to payments in one place. [`BroadcastSink`] fans events out to in-process subscribers;
+//! [`EventEnvelope::to_outbox`] gives a flat shape for a transactional outbox table.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+use crate::callback::{PaymentOutcome, WebpayReturn};
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+use crate::webpay_plus::is_authorized;
+
+/// The error type of an [`EventSink`].
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Something that happened to a payment, with the Transbank responses involved.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PaymentEvent {
+    TransactionCreated { request: CreateRequest, response: CreateResponse },
+    TransactionAuthorized { token: TokenWs, response: CommitResponse },
+    TransactionRejected { token: TokenWs, response: CommitResponse },
+    /// The cardholder aborted the payment, the form timed out or failed. Only the fields
+    /// Webpay sent back are set, and they come from the browser.
+    TransactionAborted { reason: AbortReason, token: Option<TokenWs>, buy_order: Option<BuyOrder>, session_id: Option<SessionId> },
+    RefundIssued { token: TokenWs, amount: i64, response: RefundResponse },
+    /// Transbank answered the refund without approving it (see
+    /// [`RefundResponse::is_approved`]).
+    RefundDeclined { token: TokenWs, amount: i64, response: RefundResponse },
+    CaptureCompleted { token: TokenWs, amount: i64, response: CaptureResponse },
+    /// Transbank answered the capture with a non-zero `response_code`.
+    CaptureDeclined { token: TokenWs, amount: i64, response: CaptureResponse },
+}
+
+/// Why a payment ended without a commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbortReason {
+    Aborted,
+    TimedOut,
+    FormError,
+}
+
+impl PaymentEvent {
+    /// Returns the `event` tag of the serialized event, e.g., `"transaction_created"`.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            PaymentEvent::TransactionCreated { .. } => "transaction_created",
+            PaymentEvent::TransactionAuthorized { .. } => "transaction_authorized",
+            PaymentEvent::TransactionRejected { .. } => "transaction_rejected",
+            PaymentEvent::TransactionAborted { .. } => "transaction_aborted",
+            PaymentEvent::RefundIssued { .. } => "refund_issued",
+            PaymentEvent::RefundDeclined { .. } => "refund_declined",
+            PaymentEvent::CaptureCompleted { .. } => "capture_completed",
+            PaymentEvent::CaptureDeclined { .. } => "capture_declined",
+        }
+    }
+
+    /// Returns the token of the transaction, if known.
+    pub fn token(&self) -> Option<&TokenWs> {
+        match self {
+            PaymentEvent::TransactionCreated { response, .. } => Some(&response.token),
+            PaymentEvent::TransactionAborted { token, .. } => token.as_ref(),
+            PaymentEvent::TransactionAuthorized { token, .. }
+            | PaymentEvent::TransactionRejected { token, .. }
+            | PaymentEvent::RefundIssued { token, .. }
+            | PaymentEvent::RefundDeclined { token, .. }
+            | PaymentEvent::CaptureCompleted { token, .. }
+            | PaymentEvent::CaptureDeclined { token, .. } => Some(token),
+        }
+    }
+
+    /// Returns the buy order of the transaction, if the event carries it.
+    pub fn buy_order(&self) -> Option<&BuyOrder> {
+        match self {
+            PaymentEvent::TransactionCreated { request, .. } => Some(&request.buy_order),
+            PaymentEvent::TransactionAuthorized { response, .. } | PaymentEvent::TransactionRejected { response, .. } => Some(&response.buy_order),
+            PaymentEvent::TransactionAborted { buy_order, .. } => buy_order.as_ref(),
+            PaymentEvent::RefundIssued { .. }
+            | PaymentEvent::RefundDeclined { .. }
+            | PaymentEvent::CaptureCompleted { .. }
+            | PaymentEvent::CaptureDeclined { .. } => None,
+        }
+    }
+
+    /// Tells apart events of the same type and transaction: the abort reason, or for
+    /// refunds and captures the amount and what identifies the attempt. Empty for the
+    /// others, which happen once per transaction.
+    ///
+    /// An approved refund or capture is identified by its authorization code (or date),
+    /// which Transbank issues once per operation. Declined ones, and approved ones with
+    /// neither, use `at` instead, so two attempts with the same amount get distinct ids.
+    fn discriminator(&self, at: &DateTime<Utc>) -> String {
+        let attempt = || at.format("%Y%m%dT%H%M%S%.fZ").to_string();
+        let unique = |code: &Option<String>, date: &Option<DateTime<Utc>>| {
+            code.clone().or_else(|| date.map(|d| d.format("%Y%m%dT%H%M%S%.fZ").to_string())).unwrap_or_else(attempt)
+        };
+        let balance = |b: Option<i64>| b.map(|b| b.to_string()).unwrap_or_default();
+        match self {
+            PaymentEvent::TransactionAborted { reason, .. } => {
+                serde_json::to_value(reason).ok().and_then(|v| v.as_str().map(Into::into)).unwrap_or_default()
+            }
+            PaymentEvent::RefundIssued { amount, response, .. } => {
+                format!("{}:{}:{}", amount, unique(&response.authorization_code, &response.authorization_date), balance(response.balance))
+            }
+            PaymentEvent::RefundDeclined { amount, response, .. } => format!("{}:{}:{}", amount, attempt(), balance(response.balance)),
+            PaymentEvent::CaptureCompleted { amount, response, .. } => {
+                format!("{}:{}", amount, unique(&response.authorization_code, &response.authorization_date))
+            }
+            PaymentEvent::CaptureDeclined { amount, .. } => format!("{}:{}", amount, attempt()),
+            _ => String::new(),
+        }
+    }
+}
+
+/// A [`PaymentEvent`] with when it happened.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: PaymentEvent,
+}
+
+/// An [`EventEnvelope`] flattened for an outbox table.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    /// Deterministic key for deduplication: `{aggregate_id}:{event_type}`, followed for
+    /// aborts by the reason and for refunds and captures by the amount, the authorization
+    /// code, or for declined attempts the time of the envelope (and balance, for
+    /// refunds). Emitting the same envelope twice gives the same id.
+    pub id: String,
+    /// The token, or the buy order for timeouts, which have no token.
+    pub aggregate_id: String,
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+    /// The whole envelope as JSON.
+    pub payload: String,
+}
+
+impl EventEnvelope {
+    /// Wraps an event that happened now.
+    pub fn now(event: PaymentEvent) -> Self {
+        Self { at: Utc::now(), event }
+    }
+
+    /// Converts the envelope into an outbox row.
+    pub fn to_outbox(&self) -> Result<OutboxMessage, serde_json::Error> {
+        let aggregate_id = match (self.event.token(), self.event.buy_order()) {
+            (Some(token), _) => token.to_string(),
+            (None, Some(buy_order)) => buy_order.to_string(),
+            (None, None) => String::new(),
+        };
+        let mut id = format!("{}:{}", aggregate_id, self.event.event_type());
+        let discriminator = self.event.discriminator(&self.at);
+        if !discriminator.is_empty() {
+            id = format!("{}:{}", id, discriminator);
+        }
+        Ok(OutboxMessage {
+            id,
+            aggregate_id,
+            event_type: self.event.event_type().into(),
+            occurred_at: self.at,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+}
+
+/// Receives payment events.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn emit(&self, event: EventEnvelope) -> Result<(), SinkError>;
+}
+
+#[async_trait]
+impl<E: EventSink + ?Sized> EventSink for std::sync::Arc<E> {
+    async fn emit(&self, event: EventEnvelope) -> Result<(), SinkError> {
+        (**self).emit(event).await
+    }
+}
+
+/// An [`EventSink`] sending events to every current subscriber.
+///
+/// Events emitted while nobody is subscribed are dropped, and slow subscribers miss
+/// events once `capacity` is exceeded (see `tokio::sync::broadcast`).
+#[derive(Clone, Debug)]
+pub struct BroadcastSink {
+    tx: broadcast::Sender<EventEnvelope>,
+}
+
+impl BroadcastSink {
+    pub fn new(capacity: usize) -> Self {
+        Self { tx: broadcast::channel(capacity).0 }
+    }
+
+    /// Returns a receiver of every event emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+}
+
+#[async_trait]
+impl EventSink for BroadcastSink {
+    async fn emit(&self, event: EventEnvelope) -> Result<(), SinkError> {
+        let _ = self.tx.send(event);
+        Ok(())
+    }
+}
+
+/// A [`PaymentGateway`] that emits an event after every call Transbank answered.
+///
+/// `wp_status` emits nothing. A sink failure never fails the call, since Transbank
+/// already processed it: the failure is logged and the event kept for
+/// [`take_undelivered`](Self::take_undelivered).
+pub struct EventGateway<G, E> {
+    gateway: G,
+    sink: E,
+    undelivered: Mutex<Vec<EventEnvelope>>,
+}
+
+impl<G: PaymentGateway, E: EventSink> EventGateway<G, E> {
+    pub fn new(gateway: G, sink: E) -> Self { Self { gateway, sink, undelivered: Mutex::default() } }
+
+    /// Returns the wrapped gateway.
+    pub fn gateway(&self) -> &G { &self.gateway }
+
+    /// Returns the sink.
+    pub fn sink(&self) -> &E { &self.sink }
+
+    /// Returns the events the sink failed to take, oldest first, and forgets them.
+    /// Emit them again (or store them in an outbox) once the sink recovers.
+    pub fn take_undelivered(&self) -> Vec<EventEnvelope> {
+        std::mem::take(&mut *self.undelivered.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Handles a return callback like `WebpayReturn::process`, also emitting
+    /// `TransactionAborted` for aborts, timeouts and form errors.
+    pub async fn process_return(&self, callback: WebpayReturn) -> PaymentOutcome {
+        let outcome = callback.process(self).await;
+        let aborted = match &outcome {
+            PaymentOutcome::Aborted { token, buy_order, session_id } => {
+                Some((AbortReason::Aborted, Some(token.clone()), Some(buy_order.clone()), Some(session_id.clone())))
+            }
+            PaymentOutcome::TimedOut { buy_order, session_id } => {
+                Some((AbortReason::TimedOut, None, Some(buy_order.clone()), Some(session_id.clone())))
+            }
+            PaymentOutcome::FormError { token, buy_order, session_id } => {
+                Some((AbortReason::FormError, Some(token.clone()), buy_order.clone(), session_id.clone()))
+            }
+            _ => None,
+        };
+        if let Some((reason, token, buy_order, session_id)) = aborted {
+            self.emit(PaymentEvent::TransactionAborted { reason, token, buy_order, session_id }).await;
+        }
+        outcome
+    }
+
+    async fn emit(&self, event: PaymentEvent) {
+        let envelope = EventEnvelope::now(event);
+        if let Err(e) = self.sink.emit(envelope.clone()).await {
+            log::error!("failed to emit {} event: {}", envelope.event.event_type(), e);
+            self.undelivered.lock().unwrap_or_else(|e| e.into_inner()).push(envelope);
+        }
+    }
+}
+
+#[async_trait]
+impl<G: PaymentGateway, E: EventSink> PaymentGateway for EventGateway<G, E> {
+    async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+        let response = self.gateway.wp_create(req).await?;
+        self.emit(PaymentEvent::TransactionCreated { request: req.clone(), response: response.clone() }).await;
+        Ok(response)
+    }
+
+    async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        let response = self.gateway.wp_commit(token_ws).await?;
+        let (token, event) = (token_ws.clone(), response.clone());
+        self.emit(if is_authorized(&response) {
+            PaymentEvent::TransactionAuthorized { token, response: event }
+        } else {
+            PaymentEvent::TransactionRejected { token, response: event }
+        }).await;
+        Ok(response)
+    }
+
+    async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        self.gateway.wp_status(token_ws).await
+    }
+
+    async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        let response = self.gateway.wp_refund(token_ws, amount).await?;
+        let (token, event) = (token_ws.clone(), response.clone());
+        self.emit(if response.is_approved() {
+            PaymentEvent::RefundIssued { token, amount, response: event }
+        } else {
+            PaymentEvent::RefundDeclined { token, amount, response: event }
+        }).await;
+        Ok(response)
+    }
+
+    async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        let response = self.gateway.wp_capture(token_ws, buy_order, authorization_code, capture_amount).await?;
+        let (token, amount, event) = (token_ws.clone(), capture_amount, response.clone());
+        self.emit(if response.response_code == Some(0) {
+            PaymentEvent::CaptureCompleted { token, amount, response: event }
+        } else {
+            PaymentEvent::CaptureDeclined { token, amount, response: event }
+        }).await;
+        Ok(response)
+    }
+}
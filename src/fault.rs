@@ -0,0 +1,227 @@
+//! Fault injection for resilience tests (feature `testing`).
+//!
+//! [`FaultyTransport`] wraps another [`Transport`] and, following a schedule, delays
+//! requests or makes them fail the way a flaky network or a Webpay outage would. Pass it
+//! to `WebpayClient::with_transport` to exercise retries, timeouts and the ambiguous
+//! commit without a live host.
+//!
+//! ```
+//! use std::time::Duration;
+//! use webpay::fault::{Fault, FaultyTransport, Schedule};
+//! use webpay::transport::ReqwestTransport;
+//!
+//! let transport = FaultyTransport::new(ReqwestTransport::new(reqwest::Client::new()))
+//!     // The first three requests get a 503.
+//!     .inject(Fault::ServerError(503), Schedule::burst(0, 3))
+//!     // The first commit reaches Transbank, but its response is lost.
+//!     .inject(Fault::TimeoutAfterSend, Schedule::nth(0).method(http::Method::PUT))
+//!     // Every request takes at least 200ms.
+//!     .inject(Fault::Latency(Duration::from_millis(200)), Schedule::always());
+//! ```
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::Method;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::transport::Transport;
+use crate::types::WebpayError;
+
+/// A failure to inject into a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Waits, then sends the request normally. Combines with the other faults.
+    Latency(Duration),
+    /// Fails with a `ConnectionReset` I/O error without sending the request.
+    ConnectionReset,
+    /// Sends the request, then drops the response and fails with a `TimedOut` I/O error,
+    /// as when a timeout fires after Transbank processed the call.
+    TimeoutAfterSend,
+    /// Answers with this HTTP status and an HTML error page without sending the request.
+    ServerError(u16),
+    /// Sends the request, then replaces the response body with invalid JSON.
+    MalformedJson,
+    /// Sends the request, then cuts the response body in half.
+    TruncatedBody,
+}
+
+/// Which requests a [`Fault`] applies to.
+///
+/// Requests are counted from 0, and only requests matching the method and path filters
+/// count.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    start: usize,
+    len: Option<usize>,
+    every: usize,
+    method: Option<Method>,
+    path: Option<String>,
+}
+
+impl Schedule {
+    /// Every request.
+    pub fn always() -> Self {
+        Self { start: 0, len: None, every: 1, method: None, path: None }
+    }
+
+    /// Only the `n`th request.
+    pub fn nth(n: usize) -> Self {
+        Self::burst(n, 1)
+    }
+
+    /// `len` consecutive requests, starting with the `start`th.
+    pub fn burst(start: usize, len: usize) -> Self {
+        Self { start, len: Some(len), ..Self::always() }
+    }
+
+    /// Every `n`th request: the `n - 1`th, the `2n - 1`th, and so on.
+    pub fn every(n: usize) -> Self {
+        let n = n.max(1);
+        Self { start: n - 1, every: n, ..Self::always() }
+    }
+
+    /// Only counts requests with this method (`PUT` for commits, for instance).
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Only counts requests whose path contains `path` (`"/refunds"`, for instance).
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    fn matches(&self, req: &http::Request<Bytes>) -> bool {
+        self.method.as_ref().is_none_or(|m| m == req.method()) && self.path.as_deref().is_none_or(|p| req.uri().path().contains(p))
+    }
+
+    fn fires(&self, n: usize) -> bool {
+        n >= self.start && self.len.is_none_or(|len| n < self.start + len) && (n - self.start) % self.every == 0
+    }
+}
+
+/// A fault injected into a request, as reported by [`FaultyTransport::injected`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Injected {
+    /// The index of the request among all requests sent through the transport.
+    pub request: usize,
+    pub method: Method,
+    pub path: String,
+    pub fault: Fault,
+}
+
+struct Rule {
+    fault: Fault,
+    schedule: Schedule,
+    seen: usize,
+}
+
+#[derive(Default)]
+struct State {
+    rules: Vec<Rule>,
+    requests: usize,
+    injected: Vec<Injected>,
+}
+
+/// A [`Transport`] injecting faults into the requests it forwards.
+/// See the [module documentation](self).
+///
+/// When several rules fire for a request, every `Latency` applies, followed by the first
+/// other fault in the order the rules were added.
+pub struct FaultyTransport<T> {
+    inner: T,
+    state: Mutex<State>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    /// Wraps `inner`, injecting nothing until rules are added.
+    pub fn new(inner: T) -> Self {
+        Self { inner, state: Mutex::default() }
+    }
+
+    /// Injects `fault` into the requests `schedule` selects.
+    pub fn inject(self, fault: Fault, schedule: Schedule) -> Self {
+        self.lock().rules.push(Rule { fault, schedule, seen: 0 });
+        self
+    }
+
+    /// Returns the wrapped transport.
+    pub fn inner(&self) -> &T { &self.inner }
+
+    /// Returns how many requests went through the transport.
+    pub fn requests(&self) -> usize {
+        self.lock().requests
+    }
+
+    /// Returns every fault injected so far, in order.
+    pub fn injected(&self) -> Vec<Injected> {
+        self.lock().injected.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Advances the schedules and returns the latency and fault for `req`.
+    fn plan(&self, req: &http::Request<Bytes>) -> (Duration, Option<Fault>) {
+        let mut state = self.lock();
+        let State { rules, requests, injected } = &mut *state;
+        let request = *requests;
+        *requests += 1;
+
+        let mut latency = Duration::ZERO;
+        let mut fault = None;
+        for rule in rules.iter_mut().filter(|r| r.schedule.matches(req)) {
+            let n = rule.seen;
+            rule.seen += 1;
+            if !rule.schedule.fires(n) {
+                continue;
+            }
+            match &rule.fault {
+                Fault::Latency(d) => latency += *d,
+                f if fault.is_none() => fault = Some(f.clone()),
+                _ => continue,
+            }
+            injected.push(Injected { request, method: req.method().clone(), path: req.uri().path().into(), fault: rule.fault.clone() });
+        }
+        (latency, fault)
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for FaultyTransport<T> {
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        let (latency, fault) = self.plan(&req);
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        match fault {
+            None | Some(Fault::Latency(_)) => self.inner.send(req).await,
+            Some(Fault::ConnectionReset) => Err(io_error(io::ErrorKind::ConnectionReset, "connection reset by peer (injected)")),
+            Some(Fault::TimeoutAfterSend) => {
+                let _ = self.inner.send(req).await;
+                Err(io_error(io::ErrorKind::TimedOut, "timed out waiting for the response (injected)"))
+            }
+            Some(Fault::ServerError(status)) => http::Response::builder()
+                .status(status)
+                .header(http::header::CONTENT_TYPE, "text/html")
+                .body(Bytes::from_static(b"<html><body><h1>Service Unavailable</h1></body></html>"))
+                .map_err(|e| WebpayError::Transport(e.into())),
+            Some(Fault::MalformedJson) => {
+                let res = self.inner.send(req).await?;
+                Ok(res.map(|_| Bytes::from_static(b"{\"token\": \"")))
+            }
+            Some(Fault::TruncatedBody) => {
+                let res = self.inner.send(req).await?;
+                Ok(res.map(|body| body.slice(..body.len() / 2)))
+            }
+        }
+    }
+}
+
+fn io_error(kind: io::ErrorKind, message: &str) -> WebpayError {
+    WebpayError::Transport(Box::new(io::Error::new(kind, message)))
+}
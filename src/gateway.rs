@@ -0,0 +1,216 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::client::WebpayClient;
+use crate::types::*;
+
+/// The Webpay Plus operations, as a trait.
+///
+/// Application code should depend on `PaymentGateway` instead of `WebpayClient` so that
+/// tests can swap in [`fake::FakeGateway`] and run without network.
+#[async_trait]
+pub trait PaymentGateway: Send + Sync {
+    /// See [`WebpayClient::wp_create`].
+    async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError>;
+
+    /// See [`WebpayClient::wp_commit`].
+    async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError>;
+
+    /// See [`WebpayClient::wp_status`].
+    async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError>;
+
+    /// See [`WebpayClient::wp_refund`].
+    async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError>;
+
+    /// See [`WebpayClient::wp_capture`].
+    async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError>;
+}
+
+#[async_trait]
+impl PaymentGateway for WebpayClient {
+    async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+        WebpayClient::wp_create(self, req).await
+    }
+
+    async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        WebpayClient::wp_commit(self, token_ws).await
+    }
+
+    async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        WebpayClient::wp_status(self, token_ws).await
+    }
+
+    async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        WebpayClient::wp_refund(self, token_ws, amount).await
+    }
+
+    async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        WebpayClient::wp_capture(self, token_ws, buy_order, authorization_code, capture_amount).await
+    }
+}
+
+#[async_trait]
+impl<G: PaymentGateway + ?Sized> PaymentGateway for Arc<G> {
+    async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+        (**self).wp_create(req).await
+    }
+
+    async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        (**self).wp_commit(token_ws).await
+    }
+
+    async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        (**self).wp_status(token_ws).await
+    }
+
+    async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        (**self).wp_refund(token_ws, amount).await
+    }
+
+    async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        (**self).wp_capture(token_ws, buy_order, authorization_code, capture_amount).await
+    }
+}
+
+/// An in-memory [`PaymentGateway`] for tests.
+pub mod fake {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// A call received by [`FakeGateway`], with its arguments.
+    #[derive(Clone, Debug)]
+    pub enum GatewayCall {
+        Create(CreateRequest),
+        Commit { token_ws: TokenWs },
+        Status { token_ws: TokenWs },
+        Refund { token_ws: TokenWs, amount: i64 },
+        Capture { token_ws: TokenWs, buy_order: BuyOrder, authorization_code: String, capture_amount: i64 },
+    }
+
+    #[derive(Default)]
+    struct Script {
+        create: VecDeque<Result<CreateResponse, WebpayError>>,
+        commit: VecDeque<Result<CommitResponse, WebpayError>>,
+        status: VecDeque<Result<StatusResponse, WebpayError>>,
+        refund: VecDeque<Result<RefundResponse, WebpayError>>,
+        capture: VecDeque<Result<CaptureResponse, WebpayError>>,
+        calls: Vec<GatewayCall>,
+    }
+
+    /// A [`PaymentGateway`] that replies with scripted responses and records every call.
+    ///
+    /// Responses are queued per operation and returned in order. A call with nothing
+    /// queued fails with a `500` `WebpayError::Status`.
+    ///
+    /// ```
+    /// # use webpay::gateway::fake::FakeGateway;
+    /// # use webpay::types::{ApiError, CreateResponse, WebpayError};
+    /// let fake = FakeGateway::new();
+    /// let token = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap();
+    /// fake.push_create(Ok(CreateResponse { token, url: "https://example.test".into(), extra: Default::default() }))
+    ///     .push_commit(Err(WebpayError::Status(ApiError {
+    ///         operation: "commit",
+    ///         status: reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+    ///         body: r#"{"error_message":"Invalid status"}"#.into(),
+    ///     })));
+    /// assert!(fake.calls().is_empty());
+    /// ```
+    #[derive(Default)]
+    pub struct FakeGateway {
+        script: Mutex<Script>,
+    }
+
+    impl FakeGateway {
+        /// Creates a fake with no scripted responses.
+        pub fn new() -> Self { Self::default() }
+
+        /// Queues the result of the next `wp_create` call.
+        pub fn push_create(&self, r: Result<CreateResponse, WebpayError>) -> &Self {
+            self.lock().create.push_back(r);
+            self
+        }
+
+        /// Queues the result of the next `wp_commit` call.
+        pub fn push_commit(&self, r: Result<CommitResponse, WebpayError>) -> &Self {
+            self.lock().commit.push_back(r);
+            self
+        }
+
+        /// Queues the result of the next `wp_status` call.
+        pub fn push_status(&self, r: Result<StatusResponse, WebpayError>) -> &Self {
+            self.lock().status.push_back(r);
+            self
+        }
+
+        /// Queues the result of the next `wp_refund` call.
+        pub fn push_refund(&self, r: Result<RefundResponse, WebpayError>) -> &Self {
+            self.lock().refund.push_back(r);
+            self
+        }
+
+        /// Queues the result of the next `wp_capture` call.
+        pub fn push_capture(&self, r: Result<CaptureResponse, WebpayError>) -> &Self {
+            self.lock().capture.push_back(r);
+            self
+        }
+
+        /// Returns every call received so far, oldest first.
+        pub fn calls(&self) -> Vec<GatewayCall> {
+            self.lock().calls.clone()
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, Script> {
+            // A panicking test must not poison the fake for the assertions that follow.
+            self.script.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    fn next<T>(queue: &mut VecDeque<Result<T, WebpayError>>, operation: &'static str) -> Result<T, WebpayError> {
+        queue.pop_front().unwrap_or_else(|| {
+            Err(WebpayError::Status(ApiError {
+                operation,
+                status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                body: r#"{"error_message":"no scripted response"}"#.into(),
+            }))
+        })
+    }
+
+    #[async_trait]
+    impl PaymentGateway for FakeGateway {
+        async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+            let mut s = self.lock();
+            s.calls.push(GatewayCall::Create(req.clone()));
+            next(&mut s.create, "create")
+        }
+
+        async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+            let mut s = self.lock();
+            s.calls.push(GatewayCall::Commit { token_ws: token_ws.clone() });
+            next(&mut s.commit, "commit")
+        }
+
+        async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+            let mut s = self.lock();
+            s.calls.push(GatewayCall::Status { token_ws: token_ws.clone() });
+            next(&mut s.status, "status")
+        }
+
+        async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+            let mut s = self.lock();
+            s.calls.push(GatewayCall::Refund { token_ws: token_ws.clone(), amount });
+            next(&mut s.refund, "refund")
+        }
+
+        async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+            let mut s = self.lock();
+            s.calls.push(GatewayCall::Capture {
+                token_ws: token_ws.clone(),
+                buy_order: buy_order.clone(),
+                authorization_code: authorization_code.into(),
+                capture_amount,
+            });
+            next(&mut s.capture, "capture")
+        }
+    }
+}
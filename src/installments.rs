@@ -0,0 +1,276 @@
+//! Installments (cuotas) of Webpay Plus payments.
+//!
+//! [`CommitResponse::installments`] reads the plan of a committed payment from its
+//! `payment_type_code`, `installments_number` and `installments_amount`. The
+//! [`InstallmentPlan`] constructors simulate a plan before payment, so a checkout can show
+//! "3 cuotas sin interés de $3.330" before redirecting:
+//!
+//! ```
+//! use webpay::installments::InstallmentPlan;
+//!
+//! let plan = InstallmentPlan::interest_free(9_990, 3).unwrap();
+//! assert_eq!(plan.to_string(), "3 cuotas sin interés de $3.330");
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::CommitResponse;
+
+/// The most installments Webpay offers.
+pub const MAX_INSTALLMENTS: u32 = 48;
+
+/// The kind of payment, from `payment_type_code`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PaymentType {
+    /// `VD`: debit card.
+    Debit,
+    /// `VN`: credit card, single payment.
+    Normal,
+    /// `VP`: prepaid card.
+    Prepaid,
+    /// `VC`: installments with interest set by the issuer (cuotas normales).
+    Installments,
+    /// `SI`: 3 interest-free installments.
+    ThreeInterestFree,
+    /// `S2`: 2 interest-free installments.
+    TwoInterestFree,
+    /// `NC`: N interest-free installments.
+    InterestFree,
+}
+
+impl PaymentType {
+    /// Returns the `payment_type_code`, e.g., `"SI"`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PaymentType::Debit => "VD",
+            PaymentType::Normal => "VN",
+            PaymentType::Prepaid => "VP",
+            PaymentType::Installments => "VC",
+            PaymentType::ThreeInterestFree => "SI",
+            PaymentType::TwoInterestFree => "S2",
+            PaymentType::InterestFree => "NC",
+        }
+    }
+
+    /// Returns `true` for payments in installments.
+    pub fn is_installments(&self) -> bool {
+        !matches!(self, PaymentType::Debit | PaymentType::Normal | PaymentType::Prepaid)
+    }
+
+    /// Returns `true` for interest-free installments.
+    pub fn is_interest_free(&self) -> bool {
+        matches!(self, PaymentType::ThreeInterestFree | PaymentType::TwoInterestFree | PaymentType::InterestFree)
+    }
+
+    /// Returns the number of installments the type implies, if fixed.
+    pub fn fixed_count(&self) -> Option<u32> {
+        match self {
+            PaymentType::ThreeInterestFree => Some(3),
+            PaymentType::TwoInterestFree => Some(2),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for PaymentType {
+    type Err = InstallmentsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "VD" => PaymentType::Debit,
+            "VN" => PaymentType::Normal,
+            "VP" => PaymentType::Prepaid,
+            "VC" => PaymentType::Installments,
+            "SI" => PaymentType::ThreeInterestFree,
+            "S2" => PaymentType::TwoInterestFree,
+            "NC" => PaymentType::InterestFree,
+            other => return Err(InstallmentsError::UnknownPaymentType(other.into())),
+        })
+    }
+}
+
+impl fmt::Display for PaymentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Why a plan could not be built.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum InstallmentsError {
+    #[error("unknown payment type code: {0:?}")]
+    UnknownPaymentType(String),
+    #[error("{payment_type} payments can't be split in {count} installments")]
+    InvalidCount { payment_type: PaymentType, count: u32 },
+    #[error("amount must be positive: {0}")]
+    InvalidAmount(i64),
+    #[error("amount {amount} is too small for {count} installments")]
+    AmountTooSmall { amount: i64, count: u32 },
+    #[error("monthly interest rate must be a non-negative number: {0}")]
+    InvalidRate(f64),
+    #[error("amount {amount} in {count} installments is too large to compute")]
+    Overflow { amount: i64, count: u32 },
+}
+
+/// How a payment is split in installments.
+///
+/// Amounts are whole pesos. Every installment is `installment_amount` except the last,
+/// which absorbs the rounding so the installments add up to `total`. Simulated plans round
+/// down, so the last installment is at most `count - 1` pesos larger than the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstallmentPlan {
+    pub payment_type: PaymentType,
+    pub count: u32,
+    pub installment_amount: i64,
+    pub last_installment_amount: i64,
+    pub interest_free: bool,
+    /// What the cardholder pays in total; more than the purchase if there is interest.
+    pub total: i64,
+}
+
+impl InstallmentPlan {
+    /// Simulates `amount` split in `count` interest-free installments: `SI` for 3, `S2`
+    /// for 2, `NC` otherwise.
+    pub fn interest_free(amount: i64, count: u32) -> Result<Self, InstallmentsError> {
+        let payment_type = match count {
+            3 => PaymentType::ThreeInterestFree,
+            2 => PaymentType::TwoInterestFree,
+            _ => PaymentType::InterestFree,
+        };
+        Self::split(payment_type, amount, count)
+    }
+
+    /// Simulates `amount` in `count` installments at a fixed `monthly_rate` (e.g., `0.0199`
+    /// for 1.99%), with equal installments (French amortization).
+    ///
+    /// This is an estimate for display: the issuer sets the actual rate of `VC` payments.
+    pub fn with_interest(amount: i64, count: u32, monthly_rate: f64) -> Result<Self, InstallmentsError> {
+        check(PaymentType::Installments, amount, count)?;
+        if !monthly_rate.is_finite() || monthly_rate < 0.0 {
+            return Err(InstallmentsError::InvalidRate(monthly_rate));
+        }
+        if monthly_rate == 0.0 {
+            return Self::split(PaymentType::Installments, amount, count).map(|plan| Self { interest_free: false, ..plan });
+        }
+        let n = f64::from(count);
+        // 1 - (1 + rate)^-n, without losing tiny rates to rounding.
+        let discount = -(-n * monthly_rate.ln_1p()).exp_m1();
+        let installment = (amount as f64 * monthly_rate / discount).round();
+        // `i64::MAX as f64` rounds up to 2^63, which is already out of range.
+        if installment.is_nan() || installment >= i64::MAX as f64 {
+            return Err(InstallmentsError::Overflow { amount, count });
+        }
+        let installment = installment as i64;
+        let total = installment.checked_mul(i64::from(count)).ok_or(InstallmentsError::Overflow { amount, count })?;
+        Ok(Self {
+            payment_type: PaymentType::Installments,
+            count,
+            installment_amount: installment,
+            last_installment_amount: installment,
+            interest_free: false,
+            total,
+        })
+    }
+
+    /// Splits `amount` in `count` installments rounded down, the last one taking the
+    /// remainder. Fails only if there isn't a peso for every installment.
+    fn split(payment_type: PaymentType, amount: i64, count: u32) -> Result<Self, InstallmentsError> {
+        check(payment_type, amount, count)?;
+        let n = i64::from(count);
+        if amount < n {
+            return Err(InstallmentsError::AmountTooSmall { amount, count });
+        }
+        let installment = amount / n;
+        let last = installment + amount % n;
+        Ok(Self {
+            payment_type,
+            count,
+            installment_amount: installment,
+            last_installment_amount: last,
+            interest_free: payment_type.is_interest_free(),
+            total: amount,
+        })
+    }
+}
+
+fn check(payment_type: PaymentType, amount: i64, count: u32) -> Result<(), InstallmentsError> {
+    if amount <= 0 {
+        return Err(InstallmentsError::InvalidAmount(amount));
+    }
+    let valid = match payment_type.fixed_count() {
+        Some(fixed) => count == fixed,
+        None => payment_type.is_installments() && (2..=MAX_INSTALLMENTS).contains(&count),
+    };
+    if !valid {
+        return Err(InstallmentsError::InvalidCount { payment_type, count });
+    }
+    Ok(())
+}
+
+/// Describes the plan in Spanish, as shown to cardholders, e.g., "3 cuotas sin interés de
+/// $3.330" or "12 cuotas de $9.990 (total $119.880)".
+impl fmt::Display for InstallmentPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.interest_free {
+            write!(f, "{} cuotas sin interés de {}", self.count, format_clp(self.installment_amount))
+        } else {
+            write!(f, "{} cuotas de {} (total {})", self.count, format_clp(self.installment_amount), format_clp(self.total))
+        }
+    }
+}
+
+/// Formats whole pesos the Chilean way, e.g., `$1.234.567`.
+pub fn format_clp(amount: i64) -> String {
+    let digits = amount.unsigned_abs().to_string();
+    // Groups of three from the right; only the first may be shorter.
+    let groups: Vec<&str> = digits.as_bytes().rchunks(3).rev().map(|g| std::str::from_utf8(g).unwrap_or_default()).collect();
+    format!("{}${}", if amount < 0 { "-" } else { "" }, groups.join("."))
+}
+
+impl CommitResponse {
+    /// Returns the kind of payment, if `payment_type_code` is set and known.
+    pub fn payment_type(&self) -> Option<PaymentType> {
+        self.payment_type_code.as_deref()?.parse().ok()
+    }
+
+    /// Returns the installments of the payment, or `None` if it wasn't paid in
+    /// installments.
+    ///
+    /// Interest-free plans missing `installments_amount`, or with one that doesn't add up
+    /// to `amount` (the last installment must be positive and differ from the others by
+    /// less than `count` pesos, however they were rounded), are completed as
+    /// [`InstallmentPlan::interest_free`] would; `VC` plans need it, since the interest is
+    /// up to the issuer.
+    pub fn installments(&self) -> Option<InstallmentPlan> {
+        let payment_type = self.payment_type().filter(PaymentType::is_installments)?;
+        let count = payment_type
+            .fixed_count()
+            .or_else(|| self.installments_number.and_then(|n| u32::try_from(n).ok()))
+            .filter(|n| *n > 0)?;
+        let reported = self.installments_amount.filter(|a| *a > 0);
+        if payment_type.is_interest_free() {
+            let plan = InstallmentPlan::split(payment_type, self.amount, count).ok()?;
+            let n = i64::from(count);
+            let last = |installment: i64| installment.checked_mul(n - 1).and_then(|paid| self.amount.checked_sub(paid));
+            let adds_up = |installment: i64| last(installment).is_some_and(|last| last >= 1 && (last - installment).abs() < n);
+            return Some(match reported {
+                Some(installment) if adds_up(installment) => InstallmentPlan {
+                    installment_amount: installment,
+                    last_installment_amount: self.amount - installment * (n - 1),
+                    ..plan
+                },
+                _ => plan,
+            });
+        }
+        let installment = reported?;
+        Some(InstallmentPlan {
+            payment_type,
+            count,
+            installment_amount: installment,
+            last_installment_amount: installment,
+            interest_free: false,
+            total: installment.checked_mul(i64::from(count))?,
+        })
+    }
+}
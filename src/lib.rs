@@ -1,3 +1,34 @@
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod bulk;
+pub mod callback;
+#[cfg(feature = "testing")]
+pub mod cassette;
 pub mod client;
+pub mod commit_guard;
+mod csv;
+pub mod dates;
+pub mod events;
+#[cfg(feature = "testing")]
+pub mod fault;
+pub mod gateway;
+pub mod installments;
+pub mod lifecycle;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod reconcile;
+pub mod redirect;
+pub mod settlement;
+#[cfg(feature = "signed-state")]
+pub mod signed_state;
+pub mod store;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod transport;
 pub mod types;
+pub mod verify;
 pub mod webpay_plus;
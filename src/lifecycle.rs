@@ -0,0 +1,189 @@
+//! A type-state API over the Webpay Plus transaction lifecycle.
+//!
+//! Each state only exposes the operations Transbank accepts for it: a
+//! [`PendingTransaction`] can be committed or queried, and only an
+//! [`AuthorizedTransaction`] can be refunded or captured.
+//!
+//! ```compile_fail
+//! # async fn f(client: webpay::client::WebpayClient, pending: webpay::lifecycle::PendingTransaction) {
+//! pending.refund(&client, 500).await; // refunds need an `AuthorizedTransaction`
+//! # }
+//! ```
+//!
+//! Every state is `Serialize + Deserialize`, so the pending transaction can be stored
+//! between the redirect to Webpay and the return callback. Transitions consume the state
+//! they leave, so a committed transaction can't be committed again:
+//!
+//! ```compile_fail
+//! # async fn f(client: webpay::client::WebpayClient, pending: webpay::lifecycle::PendingTransaction) {
+//! let _ = pending.commit(&client).await;
+//! let _ = pending.commit(&client).await; // `pending` was moved by the first commit
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::client::WebpayClient;
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+use crate::verify::{commit_verified, ExpectedOrder};
+use crate::webpay_plus::is_authorized;
+
+/// A created transaction, waiting for the cardholder to complete the Webpay form.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingTransaction {
+    request: CreateRequest,
+    token: TokenWs,
+    url: String,
+}
+
+/// The result of committing a [`PendingTransaction`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum CommitOutcome {
+    Authorized(AuthorizedTransaction),
+    Rejected(RejectedTransaction),
+}
+
+/// A committed transaction that Transbank authorized.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthorizedTransaction {
+    token: TokenWs,
+    commit: CommitResponse,
+}
+
+/// A committed transaction that was not authorized.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RejectedTransaction {
+    token: TokenWs,
+    commit: CommitResponse,
+}
+
+/// A failed [`PendingTransaction::commit`], handing the pending transaction back.
+#[derive(Debug)]
+pub struct CommitError {
+    /// The transaction, still pending as far as this crate knows.
+    pub transaction: PendingTransaction,
+    pub error: WebpayError,
+}
+
+impl fmt::Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "commit failed: {}", self.error)
+    }
+}
+
+impl std::error::Error for CommitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl WebpayClient {
+    /// Create a Webpay Plus transaction and return it as a [`PendingTransaction`].
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - A `CreateRequest` struct with the transaction details.
+    pub async fn create(&self, req: CreateRequest) -> Result<PendingTransaction, WebpayError> {
+        PendingTransaction::create(self, req).await
+    }
+}
+
+impl PendingTransaction {
+    /// Creates a transaction through any [`PaymentGateway`].
+    pub async fn create<G: PaymentGateway + ?Sized>(gateway: &G, req: CreateRequest) -> Result<Self, WebpayError> {
+        let created = gateway.wp_create(&req).await?;
+        Ok(Self { request: req, token: created.token, url: created.url })
+    }
+
+    /// Returns the token identifying the transaction.
+    pub fn token(&self) -> &TokenWs { &self.token }
+
+    /// Returns the Webpay URL the cardholder must be sent to (POSTing `token_ws`).
+    pub fn url(&self) -> &str { &self.url }
+
+    /// Returns the request the transaction was created with.
+    pub fn request(&self) -> &CreateRequest { &self.request }
+
+    /// Get the current status of the transaction.
+    pub async fn status<G: PaymentGateway + ?Sized>(&self, gateway: &G) -> Result<StatusResponse, WebpayError> {
+        gateway.wp_status(&self.token).await
+    }
+
+    /// Commit the transaction, after the cardholder returned from Webpay with its `token_ws`.
+    ///
+    /// The result is checked against the stored request, so a commit whose buy order,
+    /// session or amount differ fails with `WebpayError::Mismatch`. On failure the
+    /// [`CommitError`] hands the pending transaction back, so the commit can be followed
+    /// by [`status`](Self::status) to find out what happened.
+    pub async fn commit<G: PaymentGateway + ?Sized>(self, gateway: &G) -> Result<CommitOutcome, Box<CommitError>> {
+        match commit_verified(gateway, &self.token, &ExpectedOrder::from(&self.request)).await {
+            Ok(commit) => Ok(CommitOutcome::from_response(self.token, commit)),
+            Err(error) => Err(Box::new(CommitError { transaction: self, error })),
+        }
+    }
+}
+
+impl CommitOutcome {
+    /// Classifies a commit (or status) response with [`is_authorized`].
+    pub fn from_response(token: TokenWs, commit: CommitResponse) -> Self {
+        if is_authorized(&commit) {
+            CommitOutcome::Authorized(AuthorizedTransaction { token, commit })
+        } else {
+            CommitOutcome::Rejected(RejectedTransaction { token, commit })
+        }
+    }
+
+    /// Returns the commit response, whatever the outcome.
+    pub fn response(&self) -> &CommitResponse {
+        match self {
+            CommitOutcome::Authorized(t) => &t.commit,
+            CommitOutcome::Rejected(t) => &t.commit,
+        }
+    }
+
+    /// Returns the authorized transaction, if any.
+    pub fn authorized(self) -> Option<AuthorizedTransaction> {
+        match self {
+            CommitOutcome::Authorized(t) => Some(t),
+            CommitOutcome::Rejected(_) => None,
+        }
+    }
+}
+
+impl AuthorizedTransaction {
+    /// Returns the token identifying the transaction.
+    pub fn token(&self) -> &TokenWs { &self.token }
+
+    /// Returns the commit response.
+    pub fn commit(&self) -> &CommitResponse { &self.commit }
+
+    /// Refund (part of) the transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount` - The amount to refund.
+    pub async fn refund<G: PaymentGateway + ?Sized>(&self, gateway: &G, amount: i64) -> Result<RefundResponse, WebpayError> {
+        gateway.wp_refund(&self.token, amount).await
+    }
+
+    /// Capture (part of) the transaction. Only for deferred-capture commerce codes.
+    ///
+    /// # Arguments
+    ///
+    /// * `capture_amount` - The amount to capture, up to the authorized amount.
+    pub async fn capture<G: PaymentGateway + ?Sized>(&self, gateway: &G, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        let authorization_code = self.commit.authorization_code.as_deref().ok_or(WebpayError::Unexpected)?;
+        gateway.wp_capture(&self.token, &self.commit.buy_order, authorization_code, capture_amount).await
+    }
+}
+
+impl RejectedTransaction {
+    /// Returns the token identifying the transaction.
+    pub fn token(&self) -> &TokenWs { &self.token }
+
+    /// Returns the commit response, with the rejection `status` and `response_code`.
+    pub fn commit(&self) -> &CommitResponse { &self.commit }
+}
@@ -0,0 +1,541 @@
+//! A local stand-in for Webpay Plus (feature `mock`).
+//!
+//! [`MockWebpay`] serves the Webpay Plus REST API and the payment form on a local port.
+//! Transactions created against it get a `url` pointing at the mock form, which asks for
+//! a card number and the cardholder's RUT and password, approves or rejects the payment,
+//! and sends the cardholder back to the `return_url` with `token_ws` (or the `TBK_*`
+//! fields on abort and timeout), like the real one.
+//!
+//! The form works in a browser, and [`Cardholder`] drives it without one:
+//!
+//! ```no_run
+//! # async fn f() -> Result<(), webpay::types::WebpayError> {
+//! use webpay::client::{Credentials, WebpayClient};
+//! use webpay::mock::{Cardholder, MockWebpay, TestCard};
+//! # let req: webpay::types::CreateRequest = todo!();
+//!
+//! let mock = MockWebpay::start().await.expect("bind");
+//! let creds = Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() };
+//! let client = WebpayClient::new(mock.environment(), creds);
+//!
+//! let created = client.wp_create(&req).await?;
+//! let callback = Cardholder::new().pay(&created, TestCard::VISA.number).await?;
+//! let outcome = callback.params().classify().expect("valid return").process(&client).await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The mock keeps transactions in memory and does not support deferred capture.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ::axum::{
+    extract::{Form, Path, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use bytes::Bytes;
+use chrono::Utc;
+use serde_json::json;
+
+use crate::callback::ReturnParams;
+use crate::client::Environment;
+use crate::redirect::escape_html;
+use crate::types::*;
+
+/// The RUT the mock form accepts, as published by Transbank for the integration environment.
+pub const TEST_RUT: &str = "11.111.111-1";
+
+/// The password the mock form accepts.
+pub const TEST_PASSWORD: &str = "123";
+
+const FORM_PATH: &str = "/webpayserver/initTransaction";
+const PAY_PATH: &str = "/webpayserver/pay";
+
+/// The kind of a [`TestCard`], which sets the `payment_type_code` of the commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardKind {
+    Credit,
+    Debit,
+    Prepaid,
+}
+
+/// A test card published by Transbank for the integration environment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TestCard {
+    pub brand: &'static str,
+    /// The card number, without spaces.
+    pub number: &'static str,
+    pub kind: CardKind,
+    /// Whether payments with the card are authorized.
+    pub approved: bool,
+}
+
+impl TestCard {
+    pub const VISA: TestCard = TestCard { brand: "VISA", number: "4051885600446623", kind: CardKind::Credit, approved: true };
+    pub const AMEX: TestCard = TestCard { brand: "AMEX", number: "370000000002032", kind: CardKind::Credit, approved: true };
+    pub const MASTERCARD: TestCard = TestCard { brand: "MASTERCARD", number: "5186059559590568", kind: CardKind::Credit, approved: false };
+    pub const REDCOMPRA: TestCard = TestCard { brand: "Redcompra", number: "4051884239937763", kind: CardKind::Debit, approved: true };
+    pub const REDCOMPRA_REJECTED: TestCard = TestCard { brand: "Redcompra", number: "4511346660037060", kind: CardKind::Debit, approved: false };
+    pub const PREPAID_VISA: TestCard = TestCard { brand: "Prepago VISA", number: "4051886000056590", kind: CardKind::Prepaid, approved: true };
+    pub const PREPAID_MASTERCARD: TestCard = TestCard { brand: "Prepago MASTERCARD", number: "5186174110629480", kind: CardKind::Prepaid, approved: false };
+
+    /// Every test card.
+    pub const ALL: [TestCard; 7] = [
+        TestCard::VISA,
+        TestCard::AMEX,
+        TestCard::MASTERCARD,
+        TestCard::REDCOMPRA,
+        TestCard::REDCOMPRA_REJECTED,
+        TestCard::PREPAID_VISA,
+        TestCard::PREPAID_MASTERCARD,
+    ];
+
+    /// Looks up a test card by number, ignoring spaces and dashes.
+    pub fn find(number: &str) -> Option<TestCard> {
+        let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+        TestCard::ALL.into_iter().find(|c| c.number == digits)
+    }
+
+    fn payment_type_code(&self) -> &'static str {
+        match self.kind {
+            CardKind::Credit => "VN",
+            CardKind::Debit => "VD",
+            CardKind::Prepaid => "VP",
+        }
+    }
+}
+
+/// Where a mock transaction is in its lifecycle.
+enum Phase {
+    /// Created, and the form has not been completed.
+    Initialized,
+    /// The form was completed; `card` is `None` if authentication failed.
+    Paid { card: Option<TestCard> },
+    /// The cardholder aborted the payment or the form timed out.
+    Abandoned,
+    Committed(Box<CommitResponse>),
+}
+
+struct MockTransaction {
+    request: CreateRequest,
+    phase: Phase,
+}
+
+struct Shared {
+    base_url: String,
+    transactions: Mutex<HashMap<String, MockTransaction>>,
+    seed: u64,
+    next: AtomicU64,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, MockTransaction>> {
+        self.transactions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A mock Webpay Plus server. See the [module documentation](self).
+///
+/// The server stops when the `MockWebpay` is dropped.
+pub struct MockWebpay {
+    shared: Arc<Shared>,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl MockWebpay {
+    /// Starts the server on a free port of `127.0.0.1`.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let shared = Arc::new(Shared {
+            base_url: format!("http://{}", listener.local_addr()?),
+            transactions: Mutex::default(),
+            seed: Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64,
+            next: AtomicU64::new(1),
+        });
+        let app = router(shared.clone());
+        let server = tokio::spawn(async move {
+            let _ = ::axum::serve(listener, app).await;
+        });
+        Ok(Self { shared, server })
+    }
+
+    /// Returns the URL of the server, e.g., `http://127.0.0.1:49152`.
+    pub fn base_url(&self) -> &str { &self.shared.base_url }
+
+    /// Returns the environment pointing a `WebpayClient` at the server.
+    pub fn environment(&self) -> Environment {
+        Environment::Custom(self.shared.base_url.clone())
+    }
+
+    /// Returns the status a `wp_status` call would report for `token`.
+    pub fn status(&self, token: &TokenWs) -> Option<String> {
+        self.shared.lock().get(token.as_str()).map(|tx| status_response(tx).status)
+    }
+}
+
+impl Drop for MockWebpay {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn router(shared: Arc<Shared>) -> Router {
+    let api = Router::new()
+        .route("/:version/transactions", post(create))
+        .route("/:version/transactions/:token", put(commit).get(status))
+        .route("/:version/transactions/:token/refunds", post(refund))
+        .route("/:version/transactions/:token/capture", put(capture))
+        .layer(middleware::from_fn(authenticate));
+    Router::new()
+        .nest("/rswebpaytransaction/api/webpay", api)
+        .route(FORM_PATH, get(payment_form).post(payment_form))
+        .route(PAY_PATH, post(submit_payment))
+        .with_state(shared)
+}
+
+fn api_error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(json!({ "error_message": message.into() }))).into_response()
+}
+
+async fn authenticate(headers: HeaderMap, req: Request, next: Next) -> Response {
+    let present = |name: &str| headers.get(name).is_some_and(|v| !v.is_empty());
+    if present("Tbk-Api-Key-Id") && present("Tbk-Api-Key-Secret") {
+        next.run(req).await
+    } else {
+        api_error(StatusCode::UNAUTHORIZED, "Not Authorized")
+    }
+}
+
+fn invalid_token() -> Response {
+    api_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid value for parameter: token")
+}
+
+async fn create(State(shared): State<Arc<Shared>>, Json(request): Json<CreateRequest>) -> Response {
+    if request.amount <= 0 {
+        return api_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid value for parameter: amount");
+    }
+    let n = shared.next.fetch_add(1, Ordering::Relaxed);
+    let token = format!("{:016x}{:048x}", shared.seed, n);
+    shared.lock().insert(token.clone(), MockTransaction { request, phase: Phase::Initialized });
+    Json(json!({ "token": token, "url": format!("{}{}", shared.base_url, FORM_PATH) })).into_response()
+}
+
+fn base_response(request: &CreateRequest, status: &str) -> CommitResponse {
+    CommitResponse {
+        vci: None,
+        amount: request.amount,
+        status: status.into(),
+        buy_order: request.buy_order.clone(),
+        session_id: request.session_id.clone(),
+        card_detail: None,
+        accounting_date: None,
+        transaction_date: None,
+        authorization_code: None,
+        payment_type_code: None,
+        response_code: None,
+        installments_number: None,
+        installments_amount: None,
+        balance: None,
+        prepaid_balance: None,
+        extra: Default::default(),
+    }
+}
+
+fn status_response(tx: &MockTransaction) -> CommitResponse {
+    match &tx.phase {
+        Phase::Committed(commit) => (**commit).clone(),
+        _ => base_response(&tx.request, "INITIALIZED"),
+    }
+}
+
+async fn commit(State(shared): State<Arc<Shared>>, Path((_, token)): Path<(String, String)>) -> Response {
+    let mut transactions = shared.lock();
+    let Some(tx) = transactions.get_mut(&token) else { return invalid_token() };
+    let card = match &tx.phase {
+        Phase::Paid { card } => *card,
+        Phase::Initialized | Phase::Abandoned => {
+            return api_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid status '0' for transaction while authorizing. Commit is not allowed")
+        }
+        Phase::Committed(_) => {
+            return api_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid status '1' for transaction while authorizing. Commit is not allowed")
+        }
+    };
+    let now = Utc::now();
+    let approved = card.is_some_and(|c| c.approved);
+    let response = CommitResponse {
+        vci: Some(if card.is_some() { "TSY" } else { "TSN" }.into()),
+        card_detail: card.map(|c| CardDetail { card_number: Some(c.number[c.number.len() - 4..].into()), extra: Default::default() }),
+        accounting_date: Some(crate::dates::to_santiago(now).format("%m%d").to_string()),
+        transaction_date: Some(now),
+        authorization_code: Some(if approved { "1213" } else { "000000" }.into()),
+        payment_type_code: card.map(|c| c.payment_type_code().into()),
+        response_code: Some(if approved { 0 } else { -1 }),
+        installments_number: Some(0),
+        balance: approved.then_some(tx.request.amount),
+        ..base_response(&tx.request, if approved { "AUTHORIZED" } else { "FAILED" })
+    };
+    tx.phase = Phase::Committed(Box::new(response.clone()));
+    Json(response).into_response()
+}
+
+async fn status(State(shared): State<Arc<Shared>>, Path((_, token)): Path<(String, String)>) -> Response {
+    match shared.lock().get(&token) {
+        Some(tx) => Json(status_response(tx)).into_response(),
+        None => invalid_token(),
+    }
+}
+
+async fn refund(State(shared): State<Arc<Shared>>, Path((_, token)): Path<(String, String)>, Json(req): Json<RefundRequest>) -> Response {
+    let mut transactions = shared.lock();
+    let Some(tx) = transactions.get_mut(&token) else { return invalid_token() };
+    let commit = match &mut tx.phase {
+        Phase::Committed(commit) if matches!(commit.status.as_str(), "AUTHORIZED" | "PARTIALLY_NULLIFIED") => commit,
+        _ => {
+            let status = status_response(tx).status;
+            return api_error(StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid status '{}' for transaction while authorizing the refund", status));
+        }
+    };
+    let balance = commit.balance.unwrap_or(commit.amount);
+    if req.amount <= 0 || req.amount > balance {
+        return api_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid value for parameter: amount");
+    }
+
+    // A full refund of an untouched transaction is a reversal; anything else a nullification.
+    if req.amount == commit.amount && balance == commit.amount {
+        commit.status = "REVERSED".into();
+        commit.balance = Some(0);
+        return Json(json!({ "type": "REVERSED" })).into_response();
+    }
+    let balance = balance - req.amount;
+    commit.status = if balance == 0 { "NULLIFIED" } else { "PARTIALLY_NULLIFIED" }.into();
+    commit.balance = Some(balance);
+    Json(RefundResponse {
+        type_: Some("NULLIFIED".into()),
+        authorization_code: Some("123456".into()),
+        authorization_date: Some(Utc::now()),
+        nullified_amount: Some(req.amount),
+        balance: Some(balance),
+        response_code: Some(0),
+        extra: Default::default(),
+    })
+    .into_response()
+}
+
+async fn capture() -> Response {
+    api_error(StatusCode::UNPROCESSABLE_ENTITY, "Operation not allowed: the mock does not support deferred capture")
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        body
+    ))
+}
+
+fn hidden(name: &str, value: &str) -> String {
+    format!("<input type=\"hidden\" name=\"{}\" value=\"{}\">\n", escape_html(name), escape_html(value))
+}
+
+/// The card form, opened with `token_ws` as a POST field or a GET query parameter.
+async fn payment_form(State(shared): State<Arc<Shared>>, Form(fields): Form<HashMap<String, String>>) -> Response {
+    let token = fields.get("token_ws").map(String::as_str).unwrap_or_default();
+    let transactions = shared.lock();
+    let Some(tx) = transactions.get(token).filter(|tx| matches!(tx.phase, Phase::Initialized)) else {
+        return (StatusCode::BAD_REQUEST, page("Webpay", "<p>Invalid or expired transaction.</p>\n")).into_response();
+    };
+    let cards: String = TestCard::ALL
+        .iter()
+        .map(|c| format!("<li>{} {}: {}</li>\n", escape_html(c.brand), c.number, if c.approved { "approved" } else { "rejected" }))
+        .collect();
+    let body = format!(
+        "<h1>Webpay (mock)</h1>\n\
+         <p>Order {} for ${}</p>\n\
+         <form method=\"post\" action=\"{}\">\n\
+         {}\
+         <label>Card number <input name=\"card_number\" autocomplete=\"off\"></label>\n\
+         <label>RUT <input name=\"rut\" value=\"{}\"></label>\n\
+         <label>Password <input name=\"password\" type=\"password\"></label>\n\
+         <button name=\"action\" value=\"pay\">Pay</button>\n\
+         <button name=\"action\" value=\"abort\">Cancel</button>\n\
+         <button name=\"action\" value=\"timeout\">Let the form time out</button>\n\
+         </form>\n\
+         <p>Test cards:</p>\n<ul>\n{}</ul>\n",
+        escape_html(tx.request.buy_order.as_str()),
+        tx.request.amount,
+        PAY_PATH,
+        hidden("token_ws", token),
+        escape_html(TEST_RUT),
+        cards
+    );
+    page("Webpay", &body).into_response()
+}
+
+/// Completes the card form and sends the cardholder back to the `return_url`.
+async fn submit_payment(State(shared): State<Arc<Shared>>, Form(fields): Form<HashMap<String, String>>) -> Response {
+    let field = |name: &str| fields.get(name).map(String::as_str).unwrap_or_default();
+    let token = field("token_ws");
+    let mut transactions = shared.lock();
+    let Some(tx) = transactions.get_mut(token).filter(|tx| matches!(tx.phase, Phase::Initialized)) else {
+        return (StatusCode::BAD_REQUEST, page("Webpay", "<p>Invalid or expired transaction.</p>\n")).into_response();
+    };
+    let (buy_order, session_id) = (tx.request.buy_order.as_str(), tx.request.session_id.as_str());
+    let return_fields = match field("action") {
+        "abort" => {
+            tx.phase = Phase::Abandoned;
+            hidden("TBK_TOKEN", token) + &hidden("TBK_ORDEN_COMPRA", buy_order) + &hidden("TBK_ID_SESION", session_id)
+        }
+        "timeout" => {
+            tx.phase = Phase::Abandoned;
+            hidden("TBK_ORDEN_COMPRA", buy_order) + &hidden("TBK_ID_SESION", session_id)
+        }
+        _ => {
+            let authenticated = normalize_rut(field("rut")) == normalize_rut(TEST_RUT) && field("password") == TEST_PASSWORD;
+            let card = TestCard::find(field("card_number")).filter(|_| authenticated);
+            tx.phase = Phase::Paid { card };
+            hidden("token_ws", token)
+        }
+    };
+    let body = format!(
+        "<form id=\"webpay-return\" method=\"post\" action=\"{}\">\n{}<button type=\"submit\">Return to the store</button>\n</form>\n\
+         <script>document.getElementById('webpay-return').submit();</script>\n",
+        escape_html(&tx.request.return_url),
+        return_fields
+    );
+    page("Returning to the store...", &body).into_response()
+}
+
+fn normalize_rut(rut: &str) -> String {
+    rut.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+/// A form found on a page of the mock: where it posts to and its hidden fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReturnForm {
+    /// The absolute URL the form posts to; for the return form, the `return_url`.
+    pub action: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl ReturnForm {
+    /// Returns the fields as the `return_url` receives them.
+    pub fn params(&self) -> ReturnParams {
+        let field = |name: &str| self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+        ReturnParams {
+            token_ws: field("token_ws"),
+            tbk_token: field("TBK_TOKEN"),
+            tbk_orden_compra: field("TBK_ORDEN_COMPRA"),
+            tbk_id_sesion: field("TBK_ID_SESION"),
+        }
+    }
+
+    /// Posts the form to the `return_url`, as the browser would, and returns the response.
+    pub async fn submit(&self) -> Result<http::Response<Bytes>, WebpayError> {
+        let res = reqwest::Client::new().post(&self.action).form(&self.fields).send().await?;
+        let status = res.status();
+        let body = res.bytes().await?;
+        let mut response = http::Response::new(body);
+        *response.status_mut() = status;
+        Ok(response)
+    }
+
+    /// Parses the first form of a page, resolving its action against `page_url`.
+    fn parse(html: &str, page_url: &str) -> Result<Self, WebpayError> {
+        let form = html.find("<form").map(|i| &html[i..]).ok_or(WebpayError::Unexpected)?;
+        let form = &form[..form.find("</form>").ok_or(WebpayError::Unexpected)?];
+        let action = attribute(form, "action").ok_or(WebpayError::Unexpected)?;
+        let action = url::Url::parse(page_url).and_then(|u| u.join(&action)).map_err(|_| WebpayError::Unexpected)?;
+        let fields = form
+            .split("<input")
+            .skip(1)
+            .filter(|input| attribute(input, "type").as_deref() == Some("hidden"))
+            .filter_map(|input| Some((attribute(input, "name")?, attribute(input, "value").unwrap_or_default())))
+            .collect();
+        Ok(Self { action: action.into(), fields })
+    }
+}
+
+/// Reads a double-quoted attribute of the first tag in `tag`.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let tag = &tag[..tag.find('>')?];
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(unescape_html(&tag[start..start + len]))
+}
+
+fn unescape_html(s: &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#x27;", "'").replace("&amp;", "&")
+}
+
+/// Drives the mock payment form over HTTP, as a cardholder with a browser would.
+/// See the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct Cardholder {
+    http: reqwest::Client,
+    rut: String,
+    password: String,
+}
+
+impl Default for Cardholder {
+    fn default() -> Self { Self::new() }
+}
+
+impl Cardholder {
+    /// A cardholder logging in with [`TEST_RUT`] and [`TEST_PASSWORD`].
+    pub fn new() -> Self {
+        Self { http: reqwest::Client::new(), rut: TEST_RUT.into(), password: TEST_PASSWORD.into() }
+    }
+
+    /// Logs in with other credentials. The mock rejects payments made with any other than
+    /// [`TEST_RUT`] and [`TEST_PASSWORD`].
+    pub fn login(mut self, rut: impl Into<String>, password: impl Into<String>) -> Self {
+        self.rut = rut.into();
+        self.password = password.into();
+        self
+    }
+
+    /// Opens the form of `created` and pays with `card_number`. Returns the form that
+    /// takes the cardholder back to the `return_url`, carrying `token_ws`.
+    ///
+    /// The commit is authorized if the card is an approved [`TestCard`] and the login is
+    /// right, and rejected otherwise.
+    pub async fn pay(&self, created: &CreateResponse, card_number: &str) -> Result<ReturnForm, WebpayError> {
+        let form = [("card_number", card_number), ("rut", &self.rut), ("password", &self.password), ("action", "pay")];
+        self.complete(created, &form).await
+    }
+
+    /// Opens the form of `created` and cancels the payment. The returned form carries
+    /// `TBK_TOKEN`, `TBK_ORDEN_COMPRA` and `TBK_ID_SESION`.
+    pub async fn abort(&self, created: &CreateResponse) -> Result<ReturnForm, WebpayError> {
+        self.complete(created, &[("action", "abort")]).await
+    }
+
+    /// Opens the form of `created` and lets it time out. The returned form carries
+    /// `TBK_ORDEN_COMPRA` and `TBK_ID_SESION` only.
+    pub async fn time_out(&self, created: &CreateResponse) -> Result<ReturnForm, WebpayError> {
+        self.complete(created, &[("action", "timeout")]).await
+    }
+
+    async fn complete(&self, created: &CreateResponse, input: &[(&str, &str)]) -> Result<ReturnForm, WebpayError> {
+        let card_form = self.post(&created.url, &[("token_ws".into(), created.token.as_str().into())]).await?;
+        let mut fields = card_form.fields;
+        fields.extend(input.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        self.post(&card_form.action, &fields).await
+    }
+
+    async fn post(&self, url: &str, fields: &[(String, String)]) -> Result<ReturnForm, WebpayError> {
+        let res = self.http.post(url).form(fields).send().await?;
+        let status = res.status();
+        let body = res.text().await?;
+        if !status.is_success() {
+            return Err(WebpayError::Status(ApiError { operation: "payment form", status, body }));
+        }
+        ReturnForm::parse(&body, url)
+    }
+}
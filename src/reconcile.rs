@@ -0,0 +1,170 @@
+//! Reconciliation of stale pending transactions.
+//!
+//! Users abandon the Webpay form and the return callback never arrives, leaving records
+//! pending in the [`TransactionStore`]. A [`Reconciler`] periodically checks them with
+//! `wp_status` and reports each one as [`Reconciliation::Authorized`], `Rejected`,
+//! `Abandoned` or `Expired`. Transactions younger than the token lifetime are skipped, as
+//! the user may still be paying, and transactions older than the status window are not
+//! queried, as Transbank only answers `wp_status` for 7 days.
+//!
+//! The reconciler only reads the store. To record the statuses it fetches, pass a
+//! `StoreGateway` as the gateway.
+
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::gateway::PaymentGateway;
+use crate::store::{TransactionRecord, TransactionStore};
+use crate::types::*;
+
+/// How long Webpay keeps a token usable before the payment form times out.
+pub const TOKEN_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+/// How long Transbank answers `wp_status` for a transaction.
+pub const STATUS_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// What a pending transaction turned out to be.
+#[derive(Debug)]
+pub enum Reconciliation {
+    /// Authorized: the return callback was lost after the payment went through.
+    Authorized(StatusResponse),
+    /// Committed and rejected by the issuer, or otherwise not authorized.
+    Rejected(StatusResponse),
+    /// Still `INITIALIZED` after the token lifetime: the user left the form.
+    Abandoned(StatusResponse),
+    /// Older than the status window, so Transbank can no longer be asked about it (or
+    /// Transbank refused to report on it for that reason).
+    Expired,
+    /// `wp_status` failed; the transaction is reconciled again on the next sweep.
+    Failed(WebpayError),
+}
+
+/// A pending record and what it turned out to be.
+#[derive(Debug)]
+pub struct Reconciled {
+    pub record: TransactionRecord,
+    pub outcome: Reconciliation,
+}
+
+/// Checks pending transactions. See the [module documentation](self).
+pub struct Reconciler<G, S> {
+    gateway: G,
+    store: S,
+    concurrency: usize,
+    token_lifetime: Duration,
+    status_window: Duration,
+}
+
+impl<G: PaymentGateway, S: TransactionStore> Reconciler<G, S> {
+    /// Creates a reconciler making up to 4 `wp_status` calls at a time.
+    pub fn new(gateway: G, store: S) -> Self {
+        Self { gateway, store, concurrency: 4, token_lifetime: TOKEN_LIFETIME, status_window: STATUS_WINDOW }
+    }
+
+    /// Sets how many `wp_status` calls may be in flight at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets how long after creation a transaction may still be in progress. Defaults to
+    /// [`TOKEN_LIFETIME`].
+    pub fn token_lifetime(mut self, token_lifetime: Duration) -> Self {
+        self.token_lifetime = token_lifetime;
+        self
+    }
+
+    /// Sets how long after creation `wp_status` still answers. Defaults to [`STATUS_WINDOW`].
+    pub fn status_window(mut self, status_window: Duration) -> Self {
+        self.status_window = status_window;
+        self
+    }
+
+    /// Returns the wrapped gateway.
+    pub fn gateway(&self) -> &G { &self.gateway }
+
+    /// Returns the store.
+    pub fn store(&self) -> &S { &self.store }
+
+    /// Reconciles every pending record older than the token lifetime, as of `now`, and
+    /// passes each result to `on_result` as it completes. Returns how many were reconciled.
+    pub async fn sweep_at(&self, now: DateTime<Utc>, mut on_result: impl FnMut(Reconciled)) -> Result<usize, WebpayError> {
+        let due = self.due(now).await?;
+        let count = due.len();
+        let mut results = self.reconcile_all(due, now);
+        while let Some(result) = results.next().await {
+            on_result(result);
+        }
+        Ok(count)
+    }
+
+    /// Like [`sweep_at`](Self::sweep_at), as of now.
+    pub async fn sweep(&self, on_result: impl FnMut(Reconciled)) -> Result<usize, WebpayError> {
+        self.sweep_at(Utc::now(), on_result).await
+    }
+
+    /// Sweeps every `interval`, sending results to `tx`, until the receiver is dropped.
+    ///
+    /// Each result is sent as soon as it completes. A sweep that fails to read the store
+    /// is logged, skipped and tried again after `interval`. Pending records stay pending in
+    /// the store unless the gateway records statuses, so the receiver gets `Expired` and
+    /// `Failed` records again on every sweep.
+    pub async fn run(&self, interval: Duration, tx: mpsc::Sender<Reconciled>) {
+        while !tx.is_closed() {
+            let now = Utc::now();
+            match self.due(now).await {
+                Ok(due) => {
+                    let mut results = self.reconcile_all(due, now);
+                    while let Some(result) = results.next().await {
+                        if tx.send(result).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => log::error!("reconciliation sweep skipped, can't read pending transactions: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Returns the pending records older than the token lifetime.
+    async fn due(&self, now: DateTime<Utc>) -> Result<Vec<TransactionRecord>, WebpayError> {
+        let pending = self.store.pending().await?;
+        Ok(pending.into_iter().filter(|r| age(r, now) >= self.token_lifetime).collect())
+    }
+
+    fn reconcile_all(&self, due: Vec<TransactionRecord>, now: DateTime<Utc>) -> impl Stream<Item = Reconciled> + '_ {
+        stream::iter(due)
+            .map(move |record| self.reconcile(record, now))
+            .buffer_unordered(self.concurrency)
+    }
+
+    async fn reconcile(&self, record: TransactionRecord, now: DateTime<Utc>) -> Reconciled {
+        let outcome = if age(&record, now) >= self.status_window {
+            Reconciliation::Expired
+        } else {
+            match self.gateway.wp_status(&record.token).await {
+                Ok(status) => classify(status),
+                Err(WebpayError::StatusExpired(_)) => Reconciliation::Expired,
+                Err(e) => Reconciliation::Failed(e),
+            }
+        };
+        Reconciled { record, outcome }
+    }
+}
+
+fn classify(status: StatusResponse) -> Reconciliation {
+    if crate::webpay_plus::is_authorized(&status) {
+        Reconciliation::Authorized(status)
+    } else if status.status == "INITIALIZED" {
+        Reconciliation::Abandoned(status)
+    } else {
+        Reconciliation::Rejected(status)
+    }
+}
+
+fn age(record: &TransactionRecord, now: DateTime<Utc>) -> Duration {
+    (now - record.created_at).to_std().unwrap_or_default()
+}
@@ -0,0 +1,153 @@
+use url::Url;
+
+use crate::types::CreateResponse;
+
+/// The page rendered by [`CreateResponse::redirect_form`].
+///
+/// Templates may use the following placeholders, which are replaced with HTML-escaped
+/// values: `{{action}}` (the Webpay URL), `{{token}}` (the `token_ws`), `{{nonce_attr}}`
+/// (` nonce="..."` when a CSP nonce is set, empty otherwise) and `{{button_label}}`.
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>Redirecting to Webpay...</title>
+</head>
+<body>
+    <form id="webpay-form" action="{{action}}" method="POST">
+        <input type="hidden" name="token_ws" value="{{token}}"/>
+        <noscript>
+            <p>JavaScript is disabled. Press the button to continue to Webpay.</p>
+            <button type="submit">{{button_label}}</button>
+        </noscript>
+    </form>
+    <script{{nonce_attr}}>document.getElementById('webpay-form').submit();</script>
+</body>
+</html>
+"#;
+
+/// Options for [`CreateResponse::render_redirect_form`].
+#[derive(Clone, Debug)]
+pub struct RedirectForm {
+    /// CSP nonce added to the auto-submit `<script>` tag.
+    pub nonce: Option<String>,
+    /// Page template; see [`DEFAULT_TEMPLATE`] for the available placeholders.
+    pub template: String,
+    /// Label of the fallback button shown when JavaScript is disabled.
+    pub button_label: String,
+}
+
+impl Default for RedirectForm {
+    fn default() -> Self {
+        Self {
+            nonce: None,
+            template: DEFAULT_TEMPLATE.into(),
+            button_label: "Continue to Webpay".into(),
+        }
+    }
+}
+
+impl RedirectForm {
+    /// Sets the CSP nonce of the auto-submit script.
+    pub fn nonce(mut self, nonce: impl Into<String>) -> Self {
+        self.nonce = Some(nonce.into());
+        self
+    }
+
+    /// Replaces the page template.
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Sets the label of the fallback button.
+    pub fn button_label(mut self, label: impl Into<String>) -> Self {
+        self.button_label = label.into();
+        self
+    }
+}
+
+impl CreateResponse {
+    /// Renders an HTML page that POSTs `token_ws` to `url` as soon as it loads.
+    ///
+    /// This is the standard way to send the cardholder to Webpay. Every value is
+    /// HTML-escaped, and a button is shown instead when JavaScript is disabled.
+    pub fn redirect_form(&self) -> String {
+        self.render_redirect_form(&RedirectForm::default())
+    }
+
+    /// Renders the redirect page with custom options.
+    ///
+    /// ```
+    /// # use webpay::redirect::RedirectForm;
+    /// # use webpay::types::CreateResponse;
+    /// # let token = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap();
+    /// # let created = CreateResponse { token, url: "https://webpay3gint.transbank.cl/webpayserver/initTransaction".into(), extra: Default::default() };
+    /// let html = created.render_redirect_form(&RedirectForm::default().nonce("r4nd0m"));
+    /// assert!(html.contains(r#"<script nonce="r4nd0m">"#));
+    /// ```
+    pub fn render_redirect_form(&self, form: &RedirectForm) -> String {
+        let nonce_attr = form
+            .nonce
+            .as_deref()
+            .map(|n| format!(r#" nonce="{}""#, escape_html(n)))
+            .unwrap_or_default();
+        let values = [
+            ("action", escape_html(&self.url)),
+            ("token", escape_html(self.token.as_str())),
+            ("nonce_attr", nonce_attr),
+            ("button_label", escape_html(&form.button_label)),
+        ];
+        substitute(&form.template, &values)
+    }
+
+    /// Returns `url` with `token_ws` added to its query, for redirecting with a GET
+    /// (e.g., a `303 See Other`) instead of a form.
+    pub fn redirect_url(&self) -> Result<Url, url::ParseError> {
+        let mut url = Url::parse(&self.url)?;
+        url.query_pairs_mut().append_pair("token_ws", self.token.as_str());
+        Ok(url)
+    }
+}
+
+/// Replaces each `{{name}}` of `template` with its value in a single pass, so values are
+/// never scanned for placeholders. Unknown placeholders are left as they are.
+fn substitute(template: &str, values: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after
+            .find("}}")
+            .and_then(|end| values.iter().find(|(name, _)| *name == &after[..end]).map(|(_, v)| (v, end)));
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Escapes `s` for use in HTML text and double- or single-quoted attributes.
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,312 @@
+//! Settlement report (liquidación) parsing and reconciliation.
+//!
+//! The merchant portal exports sales and settlement reports as delimited text or
+//! fixed-width files. [`parse_report`] reads them into [`SettlementRow`]s according to a
+//! [`ReportLayout`], and [`reconcile`] matches the rows against the commits and refunds
+//! of stored [`TransactionRecord`]s by buy order and authorization code.
+//!
+//! ```no_run
+//! # async fn f(store: webpay::store::MemoryStore, report: String) -> Result<(), Box<dyn std::error::Error>> {
+//! use chrono::{TimeZone, Utc};
+//! use webpay::settlement::{parse_report, reconcile, ReportLayout};
+//! use webpay::store::TransactionStore;
+//!
+//! let rows = parse_report(&report, &ReportLayout::portal_csv())?;
+//! let records = store.created_between(Utc.with_ymd_and_hms(2026, 9, 1, 0, 0, 0).unwrap(), Utc::now()).await?;
+//! let result = reconcile(&rows, &records);
+//! println!("{} matched, {} missing in report", result.matched.len(), result.missing_in_report.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::bulk::RowError;
+use crate::csv;
+use crate::store::{EventKind, TransactionRecord};
+use crate::types::*;
+
+/// A field of a settlement report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    BuyOrder,
+    AuthorizationCode,
+    Amount,
+    Commission,
+    /// Sale or refund; rows without it are refunds when their amount is negative.
+    Kind,
+    TransactionDate,
+    PayoutDate,
+}
+
+/// How a report file is laid out.
+#[derive(Clone, Debug)]
+pub enum ReportLayout {
+    /// Delimited text with a header row. Each field is found by the first header that
+    /// matches one of its names, ignoring case and accents.
+    Delimited { delimiter: char, columns: Vec<(Field, Vec<String>)> },
+    /// Fixed-width lines: each field is a character range. The first `skip_lines` lines
+    /// (headers) are ignored.
+    FixedWidth { skip_lines: usize, fields: Vec<(Field, Range<usize>)> },
+}
+
+impl ReportLayout {
+    /// The `;`-separated CSV of the merchant portal, with its Spanish headers (English
+    /// names are accepted as well). Adjust the names if your export differs.
+    pub fn portal_csv() -> Self {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        ReportLayout::Delimited {
+            delimiter: ';',
+            columns: vec![
+                (Field::BuyOrder, names(&["orden de compra", "orden compra", "buy_order", "buy order"])),
+                (Field::AuthorizationCode, names(&["codigo autorizacion", "codigo de autorizacion", "authorization_code", "authorization code"])),
+                (Field::Amount, names(&["monto", "monto transaccion", "amount"])),
+                (Field::Commission, names(&["comision", "monto comision", "commission"])),
+                (Field::Kind, names(&["tipo transaccion", "tipo de transaccion", "tipo", "type"])),
+                (Field::TransactionDate, names(&["fecha transaccion", "fecha venta", "transaction_date"])),
+                (Field::PayoutDate, names(&["fecha abono", "fecha de abono", "payout_date"])),
+            ],
+        }
+    }
+}
+
+/// Whether a report row or a stored entry is a sale or a refund.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    Sale,
+    Refund,
+}
+
+/// A row of a settlement report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettlementRow {
+    pub line: usize,
+    pub kind: EntryKind,
+    pub buy_order: String,
+    pub authorization_code: String,
+    /// Absolute amount in CLP.
+    pub amount: i64,
+    pub commission: Option<i64>,
+    pub transaction_date: Option<NaiveDate>,
+    pub payout_date: Option<NaiveDate>,
+}
+
+/// Every invalid row of a report. Nothing is returned if any row is invalid.
+#[derive(Clone, Debug, thiserror::Error)]
+#[error("{} invalid row(s), first: {}", .0.len(), .0[0])]
+pub struct InvalidReport(pub Vec<RowError>);
+
+/// Parses a report file.
+pub fn parse_report(input: &str, layout: &ReportLayout) -> Result<Vec<SettlementRow>, InvalidReport> {
+    let records = match layout {
+        ReportLayout::Delimited { delimiter, columns } => delimited(input, *delimiter, columns)?,
+        ReportLayout::FixedWidth { skip_lines, fields } => input
+            .lines()
+            .enumerate()
+            .skip(*skip_lines)
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(n, l)| {
+                let chars: Vec<char> = l.chars().collect();
+                let values = fields
+                    .iter()
+                    .map(|(field, range)| {
+                        let end = range.end.min(chars.len());
+                        (*field, chars[range.start.min(end)..end].iter().collect::<String>())
+                    })
+                    .collect();
+                (n + 1, values)
+            })
+            .collect(),
+    };
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (line, values) in records {
+        match row(line, &values) {
+            Ok(row) => rows.push(row),
+            Err(reason) => errors.push(RowError { line, reason }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(rows)
+    } else {
+        Err(InvalidReport(errors))
+    }
+}
+
+type Values = HashMap<Field, String>;
+
+fn delimited(input: &str, delimiter: char, columns: &[(Field, Vec<String>)]) -> Result<Vec<(usize, Values)>, InvalidReport> {
+    let invalid = |line, reason: String| InvalidReport(vec![RowError { line, reason }]);
+    let mut records = csv::read(input, delimiter).map_err(|e| invalid(e.line, e.reason.into()))?.into_iter();
+    let (header_line, header) = records.next().ok_or_else(|| invalid(1, "empty report".into()))?;
+    let header: Vec<String> = header.iter().map(|h| normalize(h)).collect();
+    let positions: Vec<(Field, usize)> = columns
+        .iter()
+        .filter_map(|(field, names)| {
+            let position = header.iter().position(|h| names.iter().any(|n| normalize(n) == *h))?;
+            Some((*field, position))
+        })
+        .collect();
+    for required in [Field::BuyOrder, Field::AuthorizationCode, Field::Amount] {
+        if !positions.iter().any(|(f, _)| *f == required) {
+            return Err(invalid(header_line, format!("no column for {:?}", required)));
+        }
+    }
+    Ok(records
+        .map(|(line, fields)| {
+            let values = positions.iter().filter_map(|(f, i)| Some((*f, fields.get(*i)?.clone()))).collect();
+            (line, values)
+        })
+        .collect())
+}
+
+fn row(line: usize, values: &Values) -> Result<SettlementRow, String> {
+    let get = |field| values.get(&field).map(|v| v.trim()).filter(|v| !v.is_empty());
+    let required = |field| get(field).ok_or_else(|| format!("missing {:?}", field));
+    let date = |field| get(field).map(|v| parse_date(v).ok_or_else(|| format!("invalid date {:?}", v))).transpose();
+
+    let amount = parse_amount(required(Field::Amount)?).ok_or_else(|| format!("invalid amount {:?}", required(Field::Amount).unwrap_or("")))?;
+    let commission = get(Field::Commission).map(|v| parse_amount(v).ok_or_else(|| format!("invalid commission {:?}", v))).transpose()?;
+    let kind = match get(Field::Kind).map(normalize) {
+        Some(k) if ["anul", "devol", "reembolso", "refund", "nullif", "reversa"].iter().any(|w| k.contains(w)) => EntryKind::Refund,
+        Some(_) => EntryKind::Sale,
+        None if amount < 0 => EntryKind::Refund,
+        None => EntryKind::Sale,
+    };
+    Ok(SettlementRow {
+        line,
+        kind,
+        buy_order: required(Field::BuyOrder)?.into(),
+        // Codes are sometimes exported as numbers, losing their leading zeros.
+        authorization_code: required(Field::AuthorizationCode)?.trim_start_matches('0').into(),
+        amount: amount.abs(),
+        commission: commission.map(i64::abs),
+        transaction_date: date(Field::TransactionDate)?,
+        payout_date: date(Field::PayoutDate)?,
+    })
+}
+
+/// Parses CLP amounts such as `1000`, `1.000`, `$ 1.000`, `-1.000` or `1.000,00`.
+///
+/// `.` is only accepted as a thousands separator between groups of three digits and `,`
+/// only before `0` or `00` decimals, so `1.5`, `1,000` or `1.000,50` are rejected rather
+/// than guessed at.
+fn parse_amount(s: &str) -> Option<i64> {
+    let s: String = s.chars().filter(|c| !matches!(c, '$' | ' ')).collect();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.as_str()),
+    };
+    let (integer, decimals) = s.split_once(',').unwrap_or((s, ""));
+    if !matches!(decimals, "" | "0" | "00") || (decimals.is_empty() && s.ends_with(',')) {
+        return None;
+    }
+    let mut groups = integer.split('.');
+    let first = groups.next().filter(|g| !g.is_empty() && g.chars().all(|c| c.is_ascii_digit()))?;
+    let mut digits = first.to_string();
+    for group in groups {
+        if first.len() > 3 || group.len() != 3 || !group.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.push_str(group);
+    }
+    let amount: i64 = digits.parse().ok()?;
+    Some(if negative { -amount } else { amount })
+}
+
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    ["%d/%m/%Y", "%d-%m-%Y", "%Y-%m-%d", "%Y%m%d", "%d/%m/%y"]
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(s, f).ok())
+}
+
+/// Lowercases and strips accents and surrounding whitespace, for header comparisons.
+fn normalize(s: &str) -> String {
+    s.trim()
+        .chars()
+        .map(|c| match c {
+            'á' | 'Á' => 'a',
+            'é' | 'É' => 'e',
+            'í' | 'Í' => 'i',
+            'ó' | 'Ó' => 'o',
+            'ú' | 'Ú' | 'ü' | 'Ü' => 'u',
+            'ñ' | 'Ñ' => 'n',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// A sale or refund the store knows about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredEntry {
+    pub kind: EntryKind,
+    pub token: TokenWs,
+    pub buy_order: BuyOrder,
+    pub authorization_code: String,
+    pub amount: i64,
+}
+
+impl StoredEntry {
+    /// Lists the authorized commit and the successful refunds of each record. Reversed
+    /// transactions are left out, as they never reach settlement.
+    pub fn from_records<'a>(records: impl IntoIterator<Item = &'a TransactionRecord>) -> Vec<Self> {
+        let mut entries = Vec::new();
+        for record in records.into_iter().filter(|r| r.status != "REVERSED") {
+            let Some(commit) = record.commit.as_ref().filter(|c| crate::webpay_plus::is_authorized(c)) else { continue };
+            let entry = |kind, code: &str, amount| StoredEntry {
+                kind,
+                token: record.token.clone(),
+                buy_order: record.buy_order.clone(),
+                authorization_code: code.trim_start_matches('0').into(),
+                amount,
+            };
+            entries.push(entry(EntryKind::Sale, commit.authorization_code.as_deref().unwrap_or(""), commit.amount));
+            for event in &record.events {
+                if let EventKind::Refunded { amount, response } = &event.kind {
+                    if response.is_approved() && response.type_.as_deref() != Some("REVERSED") {
+                        entries.push(entry(EntryKind::Refund, response.authorization_code.as_deref().unwrap_or(""), *amount));
+                    }
+                }
+            }
+        }
+        entries
+    }
+}
+
+/// The result of [`reconcile`].
+#[derive(Clone, Debug, Default)]
+pub struct SettlementReconciliation {
+    pub matched: Vec<(SettlementRow, StoredEntry)>,
+    /// Stored sales and refunds the report does not list.
+    pub missing_in_report: Vec<StoredEntry>,
+    /// Report rows the store knows nothing about.
+    pub missing_in_store: Vec<SettlementRow>,
+    /// Rows found in both with different amounts.
+    pub amount_mismatch: Vec<(SettlementRow, StoredEntry)>,
+}
+
+/// Matches report rows with the commits and refunds of `records` by kind, buy order and
+/// authorization code (ignoring leading zeros). Each stored entry matches at most one row.
+pub fn reconcile<'a>(rows: &[SettlementRow], records: impl IntoIterator<Item = &'a TransactionRecord>) -> SettlementReconciliation {
+    let mut stored: HashMap<(EntryKind, String, String), Vec<StoredEntry>> = HashMap::new();
+    for entry in StoredEntry::from_records(records) {
+        let key = (entry.kind, entry.buy_order.to_string(), entry.authorization_code.clone());
+        stored.entry(key).or_default().push(entry);
+    }
+
+    let mut result = SettlementReconciliation::default();
+    for row in rows {
+        let key = (row.kind, row.buy_order.clone(), row.authorization_code.clone());
+        match stored.get_mut(&key).and_then(|entries| (!entries.is_empty()).then(|| entries.remove(0))) {
+            Some(entry) if entry.amount == row.amount => result.matched.push((row.clone(), entry)),
+            Some(entry) => result.amount_mismatch.push((row.clone(), entry)),
+            None => result.missing_in_store.push(row.clone()),
+        }
+    }
+    result.missing_in_report = stored.into_values().flatten().collect();
+    result.missing_in_report.sort_by(|a, b| (a.buy_order.as_str(), a.kind == EntryKind::Refund).cmp(&(b.buy_order.as_str(), b.kind == EntryKind::Refund)));
+    result
+}
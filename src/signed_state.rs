@@ -0,0 +1,153 @@
+//! HMAC-signed application state (feature `signed-state`).
+//!
+//! Webpay echoes `TBK_ORDEN_COMPRA` and `TBK_ID_SESION` back through the cardholder's
+//! browser, and the `return_url` query string is just as easy to edit, so state carried
+//! there is attacker-controlled. A [`StateSigner`] signs that state with HMAC-SHA256:
+//!
+//! * [`session_id`](StateSigner::session_id) builds a compact `session_id` of the form
+//!   `{state}.{tag}`, bound to the buy order, that fits the 61 character limit.
+//! * [`sign_return_url`](StateSigner::sign_return_url) adds query parameters and a `sig`
+//!   parameter covering them.
+//!
+//! Both are verified when the return callback arrives, with
+//! [`verify_return`](StateSigner::verify_return) and
+//! [`verify_return_url`](StateSigner::verify_return_url).
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+use url::Url;
+
+use crate::callback::WebpayReturn;
+use crate::types::*;
+
+/// Length of the tag appended to signed session ids (16 bytes of HMAC, base64url).
+pub const TAG_LEN: usize = 22;
+
+/// The longest state that fits in a signed session id.
+pub const MAX_STATE_LEN: usize = SessionId::MAX_LEN - TAG_LEN - 1;
+
+/// The query parameter holding the signature of a signed `return_url`.
+pub const SIGNATURE_PARAM: &str = "sig";
+
+/// Why signed state could not be produced or verified.
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("state is {0} characters long, at most {MAX_STATE_LEN} fit in a signed session_id")]
+    TooLong(usize),
+    #[error("state is not signed")]
+    Unsigned,
+    #[error("state signature does not match")]
+    BadSignature,
+    #[error(transparent)]
+    InvalidId(#[from] InvalidId),
+    #[error("invalid return_url: {0}")]
+    Url(#[from] url::ParseError),
+}
+
+/// Signs and verifies state with a secret key. See the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct StateSigner {
+    key: hmac::Key,
+}
+
+impl StateSigner {
+    /// Creates a signer. Use a random secret of at least 32 bytes, kept server-side.
+    pub fn new(secret: &[u8]) -> Self {
+        Self { key: hmac::Key::new(hmac::HMAC_SHA256, secret) }
+    }
+
+    /// Builds a `session_id` carrying `state` (at most [`MAX_STATE_LEN`] characters),
+    /// signed together with `buy_order` so it can't be moved to another order.
+    pub fn session_id(&self, buy_order: &BuyOrder, state: &str) -> Result<SessionId, StateError> {
+        if state.len() > MAX_STATE_LEN {
+            return Err(StateError::TooLong(state.len()));
+        }
+        Ok(SessionId::new(format!("{}.{}", state, self.tag(&["session", buy_order.as_str(), state])))?)
+    }
+
+    /// Checks a `session_id` built by [`session_id`](Self::session_id) for `buy_order`
+    /// and returns its state.
+    pub fn verify_session_id<'a>(&self, buy_order: &BuyOrder, session_id: &'a SessionId) -> Result<&'a str, StateError> {
+        let (state, tag) = session_id.as_str().rsplit_once('.').ok_or(StateError::Unsigned)?;
+        self.check(&["session", buy_order.as_str(), state], tag)?;
+        Ok(state)
+    }
+
+    /// Builds a `CreateRequest` whose `session_id` carries `state`.
+    pub fn create_request(&self, buy_order: BuyOrder, state: &str, amount: i64, return_url: impl Into<String>) -> Result<CreateRequest, StateError> {
+        let session_id = self.session_id(&buy_order, state)?;
+        Ok(CreateRequest { buy_order, session_id, amount, return_url: return_url.into() })
+    }
+
+    /// Appends `params` to `return_url`, followed by a `sig` parameter covering them.
+    pub fn sign_return_url(&self, return_url: &str, params: &[(&str, &str)]) -> Result<String, StateError> {
+        let mut url = Url::parse(return_url)?;
+        // Re-encode the existing query so it is signed the way it will be verified.
+        let existing: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        url.query_pairs_mut().clear().extend_pairs(existing).extend_pairs(params);
+        let sig = self.tag(&["return_url", &signed_query(&url)]);
+        url.query_pairs_mut().append_pair(SIGNATURE_PARAM, &sig);
+        Ok(url.into())
+    }
+
+    /// Checks the query of a URL signed by [`sign_return_url`](Self::sign_return_url)
+    /// (e.g., the request URI of the return callback) and returns its parameters, minus
+    /// `sig`. Parameters added after signing make the check fail.
+    pub fn verify_return_url(&self, url: &str) -> Result<Vec<(String, String)>, StateError> {
+        // Request URIs are usually relative; only the query matters.
+        let url = Url::parse("http://localhost/")?.join(url)?;
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        let (sig, params) = match pairs.split_last() {
+            Some(((name, sig), params)) if name == SIGNATURE_PARAM => (sig, params),
+            _ => return Err(StateError::Unsigned),
+        };
+        let mut unsigned = url.clone();
+        unsigned.query_pairs_mut().clear().extend_pairs(params);
+        self.check(&["return_url", &signed_query(&unsigned)], sig)?;
+        Ok(params.to_vec())
+    }
+
+    /// Checks the session id echoed on the abort, timeout and form error paths, and
+    /// returns its state.
+    ///
+    /// `Completed` callbacks only carry `token_ws`: verify the `buy_order` and
+    /// `session_id` of the commit response instead. This returns `Ok(None)` for them,
+    /// and for form errors without an echoed session.
+    pub fn verify_return<'a>(&self, callback: &'a WebpayReturn) -> Result<Option<&'a str>, StateError> {
+        match callback {
+            WebpayReturn::Completed { .. } => Ok(None),
+            WebpayReturn::Aborted { buy_order, session_id, .. } | WebpayReturn::TimedOut { buy_order, session_id } => {
+                self.verify_session_id(buy_order, session_id).map(Some)
+            }
+            WebpayReturn::FormError { buy_order: Some(buy_order), session_id: Some(session_id), .. } => {
+                self.verify_session_id(buy_order, session_id).map(Some)
+            }
+            WebpayReturn::FormError { .. } => Ok(None),
+        }
+    }
+
+    fn tag(&self, parts: &[&str]) -> String {
+        let mut ctx = hmac::Context::with_key(&self.key);
+        for part in parts {
+            ctx.update(&(part.len() as u64).to_be_bytes());
+            ctx.update(part.as_bytes());
+        }
+        URL_SAFE_NO_PAD.encode(&ctx.sign().as_ref()[..16])
+    }
+
+    fn check(&self, parts: &[&str], tag: &str) -> Result<(), StateError> {
+        let expected = self.tag(parts);
+        let diff = expected.bytes().zip(tag.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b));
+        if expected.len() == tag.len() && diff == 0 {
+            Ok(())
+        } else {
+            Err(StateError::BadSignature)
+        }
+    }
+}
+
+/// The part of a URL covered by its signature: the path and query.
+fn signed_query(url: &Url) -> String {
+    format!("{}?{}", url.path(), url.query().unwrap_or(""))
+}
@@ -0,0 +1,303 @@
+//! Transaction persistence.
+//!
+//! A [`TransactionStore`] keeps one [`TransactionRecord`] per token, built from the
+//! [`TransactionEvent`]s of its lifecycle: created, committed, status-checked, refunded
+//! and captured. [`MemoryStore`] is always available; `SqliteStore` requires the
+//! `sqlite` feature. Wrap a gateway in [`StoreGateway`] to record every call automatically.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Errors returned by a [`TransactionStore`].
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("transaction {0} not found")]
+    NotFound(TokenWs),
+    #[error("transaction {0} already exists")]
+    Duplicate(TokenWs),
+    #[error("store serialization: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Something that happened to a transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionEvent {
+    pub token: TokenWs,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// The kind of a [`TransactionEvent`], with the request and response involved.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    Created { request: CreateRequest, response: CreateResponse },
+    Committed { response: CommitResponse },
+    StatusChecked { response: StatusResponse },
+    Refunded { amount: i64, response: RefundResponse },
+    Captured { amount: i64, response: CaptureResponse },
+}
+
+impl TransactionEvent {
+    /// Creates an event that happened now.
+    pub fn now(token: TokenWs, kind: EventKind) -> Self {
+        Self { token, at: Utc::now(), kind }
+    }
+}
+
+/// Everything known about a transaction, folded from its events.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub token: TokenWs,
+    pub buy_order: BuyOrder,
+    pub session_id: SessionId,
+    pub amount: i64,
+    pub return_url: String,
+    /// Last known Transbank status: `INITIALIZED` until committed, then e.g. `AUTHORIZED`,
+    /// `FAILED`, `NULLIFIED`, `PARTIALLY_NULLIFIED`, `REVERSED` or `CAPTURED`.
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// The commit response, once committed.
+    pub commit: Option<CommitResponse>,
+    /// Every event, oldest first.
+    pub events: Vec<TransactionEvent>,
+}
+
+impl TransactionRecord {
+    /// Starts a record from its `Created` event.
+    pub fn from_created(event: TransactionEvent) -> Option<Self> {
+        let EventKind::Created { request, .. } = &event.kind else { return None };
+        Some(Self {
+            token: event.token.clone(),
+            buy_order: request.buy_order.clone(),
+            session_id: request.session_id.clone(),
+            amount: request.amount,
+            return_url: request.return_url.clone(),
+            status: "INITIALIZED".into(),
+            created_at: event.at,
+            updated_at: event.at,
+            commit: None,
+            events: vec![event],
+        })
+    }
+
+    /// Rebuilds a record from all of its events, oldest first.
+    pub fn from_events(events: impl IntoIterator<Item = TransactionEvent>) -> Option<Self> {
+        let mut events = events.into_iter();
+        let mut record = Self::from_created(events.next()?)?;
+        events.for_each(|e| record.apply(e));
+        Some(record)
+    }
+
+    /// Updates the record with a later event.
+    pub fn apply(&mut self, event: TransactionEvent) {
+        match &event.kind {
+            EventKind::Created { .. } => {}
+            EventKind::Committed { response } => {
+                self.status = response.status.clone();
+                self.commit = Some(response.clone());
+            }
+            EventKind::StatusChecked { response } => self.status = response.status.clone(),
+            EventKind::Refunded { response, .. } if response.is_approved() => {
+                self.status = match (response.type_.as_deref(), response.balance) {
+                    (Some("REVERSED"), _) => "REVERSED",
+                    (_, Some(0)) => "NULLIFIED",
+                    _ => "PARTIALLY_NULLIFIED",
+                }.into();
+            }
+            EventKind::Refunded { .. } => {}
+            EventKind::Captured { .. } => self.status = "CAPTURED".into(),
+        }
+        self.updated_at = self.updated_at.max(event.at);
+        self.events.push(event);
+    }
+
+    /// Returns `true` until the transaction is committed (or found committed by a status check).
+    pub fn is_pending(&self) -> bool {
+        self.status == "INITIALIZED"
+    }
+
+    /// Returns the sum of all approved refunds (see [`RefundResponse::is_approved`]).
+    pub fn refunded_amount(&self) -> i64 {
+        self.events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Refunded { amount, response } if response.is_approved() => Some(*amount),
+                _ => None,
+            })
+            .sum()
+    }
+}
+
+/// Persists transaction records.
+#[async_trait]
+pub trait TransactionStore: Send + Sync {
+    /// Records an event. `Created` events start a new record; any other event fails with
+    /// `StoreError::NotFound` if its token has no record yet.
+    async fn record(&self, event: TransactionEvent) -> Result<(), StoreError>;
+
+    /// Returns the record of a token.
+    async fn get(&self, token: &TokenWs) -> Result<Option<TransactionRecord>, StoreError>;
+
+    /// Returns every record created with `buy_order`, oldest first.
+    async fn find_by_buy_order(&self, buy_order: &BuyOrder) -> Result<Vec<TransactionRecord>, StoreError>;
+
+    /// Returns every record that is still pending, oldest first.
+    async fn pending(&self) -> Result<Vec<TransactionRecord>, StoreError>;
+
+    /// Returns every record created in `[from, to)`, oldest first.
+    async fn created_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<TransactionRecord>, StoreError>;
+}
+
+/// A [`TransactionStore`] in memory, for tests and single-process deployments.
+#[derive(Default)]
+pub struct MemoryStore {
+    records: Mutex<HashMap<TokenWs, TransactionRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self { Self::default() }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<TokenWs, TransactionRecord>> {
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn select(&self, filter: impl Fn(&TransactionRecord) -> bool) -> Vec<TransactionRecord> {
+        let mut records: Vec<_> = self.lock().values().filter(|r| filter(r)).cloned().collect();
+        records.sort_by_key(|r| r.created_at);
+        records
+    }
+}
+
+#[async_trait]
+impl TransactionStore for MemoryStore {
+    async fn record(&self, event: TransactionEvent) -> Result<(), StoreError> {
+        let mut records = self.lock();
+        match (records.get_mut(&event.token), &event.kind) {
+            (Some(_), EventKind::Created { .. }) => Err(StoreError::Duplicate(event.token)),
+            (Some(record), _) => {
+                record.apply(event);
+                Ok(())
+            }
+            (None, EventKind::Created { .. }) => {
+                let token = event.token.clone();
+                let record = TransactionRecord::from_created(event).expect("created event");
+                records.insert(token, record);
+                Ok(())
+            }
+            (None, _) => Err(StoreError::NotFound(event.token)),
+        }
+    }
+
+    async fn get(&self, token: &TokenWs) -> Result<Option<TransactionRecord>, StoreError> {
+        Ok(self.lock().get(token).cloned())
+    }
+
+    async fn find_by_buy_order(&self, buy_order: &BuyOrder) -> Result<Vec<TransactionRecord>, StoreError> {
+        Ok(self.select(|r| r.buy_order == *buy_order))
+    }
+
+    async fn pending(&self) -> Result<Vec<TransactionRecord>, StoreError> {
+        Ok(self.select(TransactionRecord::is_pending))
+    }
+
+    async fn created_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<TransactionRecord>, StoreError> {
+        Ok(self.select(|r| r.created_at >= from && r.created_at < to))
+    }
+}
+
+#[async_trait]
+impl<S: TransactionStore + ?Sized> TransactionStore for std::sync::Arc<S> {
+    async fn record(&self, event: TransactionEvent) -> Result<(), StoreError> {
+        (**self).record(event).await
+    }
+
+    async fn get(&self, token: &TokenWs) -> Result<Option<TransactionRecord>, StoreError> {
+        (**self).get(token).await
+    }
+
+    async fn find_by_buy_order(&self, buy_order: &BuyOrder) -> Result<Vec<TransactionRecord>, StoreError> {
+        (**self).find_by_buy_order(buy_order).await
+    }
+
+    async fn pending(&self) -> Result<Vec<TransactionRecord>, StoreError> {
+        (**self).pending().await
+    }
+
+    async fn created_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<TransactionRecord>, StoreError> {
+        (**self).created_between(from, to).await
+    }
+}
+
+/// A [`PaymentGateway`] that records every successful call in a [`TransactionStore`].
+///
+/// Failed calls are not recorded. If the call succeeds but recording fails, the store
+/// error is returned as `WebpayError::Store`, even though Transbank processed the call.
+pub struct StoreGateway<G, S> {
+    gateway: G,
+    store: S,
+}
+
+impl<G, S> StoreGateway<G, S> {
+    pub fn new(gateway: G, store: S) -> Self { Self { gateway, store } }
+
+    /// Returns the wrapped gateway.
+    pub fn gateway(&self) -> &G { &self.gateway }
+
+    /// Returns the store.
+    pub fn store(&self) -> &S { &self.store }
+}
+
+#[async_trait]
+impl<G: PaymentGateway, S: TransactionStore> PaymentGateway for StoreGateway<G, S> {
+    async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
+        let response = self.gateway.wp_create(req).await?;
+        let kind = EventKind::Created { request: req.clone(), response: response.clone() };
+        self.store.record(TransactionEvent::now(response.token.clone(), kind)).await?;
+        Ok(response)
+    }
+
+    async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        let response = self.gateway.wp_commit(token_ws).await?;
+        let kind = EventKind::Committed { response: response.clone() };
+        self.store.record(TransactionEvent::now(token_ws.clone(), kind)).await?;
+        Ok(response)
+    }
+
+    async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        let response = self.gateway.wp_status(token_ws).await?;
+        let kind = EventKind::StatusChecked { response: response.clone() };
+        self.store.record(TransactionEvent::now(token_ws.clone(), kind)).await?;
+        Ok(response)
+    }
+
+    async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        let response = self.gateway.wp_refund(token_ws, amount).await?;
+        let kind = EventKind::Refunded { amount, response: response.clone() };
+        self.store.record(TransactionEvent::now(token_ws.clone(), kind)).await?;
+        Ok(response)
+    }
+
+    async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        let response = self.gateway.wp_capture(token_ws, buy_order, authorization_code, capture_amount).await?;
+        let kind = EventKind::Captured { amount: capture_amount, response: response.clone() };
+        self.store.record(TransactionEvent::now(token_ws.clone(), kind)).await?;
+        Ok(response)
+    }
+}
@@ -0,0 +1,126 @@
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::*;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS webpay_transactions (
+    token      TEXT PRIMARY KEY,
+    buy_order  TEXT NOT NULL,
+    status     TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS webpay_transactions_buy_order ON webpay_transactions (buy_order);
+CREATE INDEX IF NOT EXISTS webpay_transactions_status ON webpay_transactions (status);
+CREATE TABLE IF NOT EXISTS webpay_transaction_events (
+    id      INTEGER PRIMARY KEY AUTOINCREMENT,
+    token   TEXT NOT NULL REFERENCES webpay_transactions (token),
+    at      TEXT NOT NULL,
+    payload TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS webpay_transaction_events_token ON webpay_transaction_events (token);
+";
+
+/// A [`TransactionStore`] backed by SQLite (feature `sqlite`).
+///
+/// Events are stored as JSON in `webpay_transaction_events`, and records are rebuilt from
+/// them on read; `webpay_transactions` only indexes tokens by buy order and status.
+/// Queries run on the calling task while holding the connection lock, which is fine for
+/// the small, indexed statements involved.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and creates the tables if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens a private in-memory database.
+    pub fn open_in_memory() -> Result<Self, StoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Uses an existing connection, creating the tables if needed.
+    pub fn from_connection(conn: Connection) -> Result<Self, StoreError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn load(conn: &Connection, token: &str) -> Result<Option<TransactionRecord>, StoreError> {
+        let mut stmt = conn.prepare_cached("SELECT payload FROM webpay_transaction_events WHERE token = ?1 ORDER BY id")?;
+        let events = stmt
+            .query_map([token], |row| row.get::<_, String>(0))?
+            .map(|payload| Ok(serde_json::from_str::<TransactionEvent>(&payload?)?))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        Ok(TransactionRecord::from_events(events))
+    }
+
+    fn select(&self, sql: &str, args: impl rusqlite::Params) -> Result<Vec<TransactionRecord>, StoreError> {
+        let conn = self.lock();
+        let tokens = conn
+            .prepare_cached(sql)?
+            .query_map(args, |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        tokens.iter().filter_map(|t| Self::load(&conn, t).transpose()).collect()
+    }
+}
+
+#[async_trait]
+impl TransactionStore for SqliteStore {
+    async fn record(&self, event: TransactionEvent) -> Result<(), StoreError> {
+        let payload = serde_json::to_string(&event)?;
+        let token = event.token.as_str();
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+
+        match (Self::load(&tx, token)?, &event.kind) {
+            (Some(_), EventKind::Created { .. }) => return Err(StoreError::Duplicate(event.token)),
+            (None, EventKind::Created { .. }) => {
+                let record = TransactionRecord::from_created(event.clone()).expect("created event");
+                tx.execute(
+                    "INSERT INTO webpay_transactions (token, buy_order, status, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![token, record.buy_order.as_str(), record.status, record.created_at.to_rfc3339()],
+                )?;
+            }
+            (Some(mut record), _) => {
+                record.apply(event.clone());
+                tx.execute("UPDATE webpay_transactions SET status = ?2 WHERE token = ?1", params![token, record.status])?;
+            }
+            (None, _) => return Err(StoreError::NotFound(event.token)),
+        }
+        tx.execute(
+            "INSERT INTO webpay_transaction_events (token, at, payload) VALUES (?1, ?2, ?3)",
+            params![token, event.at.to_rfc3339(), payload],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn get(&self, token: &TokenWs) -> Result<Option<TransactionRecord>, StoreError> {
+        Self::load(&self.lock(), token.as_str())
+    }
+
+    async fn find_by_buy_order(&self, buy_order: &BuyOrder) -> Result<Vec<TransactionRecord>, StoreError> {
+        self.select("SELECT token FROM webpay_transactions WHERE buy_order = ?1 ORDER BY created_at", [buy_order.as_str()])
+    }
+
+    async fn pending(&self) -> Result<Vec<TransactionRecord>, StoreError> {
+        self.select("SELECT token FROM webpay_transactions WHERE status = ?1 ORDER BY created_at", ["INITIALIZED"])
+    }
+
+    async fn created_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<TransactionRecord>, StoreError> {
+        // Timestamps are stored as RFC 3339 in UTC, which sorts chronologically as text.
+        self.select(
+            "SELECT token FROM webpay_transactions WHERE created_at >= ?1 AND created_at < ?2 ORDER BY created_at",
+            [from.to_rfc3339(), to.to_rfc3339()],
+        )
+    }
+}
@@ -0,0 +1,204 @@
+//! Tower integration (feature `tower`).
+//!
+//! * `WebpayClient` is a `Service<WebpayRequest>`, so it can be wrapped in standard tower
+//!   layers (timeouts, concurrency limits, load shedding, retries).
+//! * [`ServiceTransport`] (or `WebpayClient::with_service`) sends the client's HTTP
+//!   requests through any tower HTTP service, such as [`ReqwestService`] wrapped in
+//!   layers, instead of the fixed `reqwest` client.
+//! * [`TransbankAuthLayer`] adds the Transbank auth headers to requests sent through any
+//!   inner HTTP service.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use ::tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::client::{Credentials, WebpayClient};
+use crate::transport::{execute, Transport};
+use crate::types::*;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// A Webpay Plus call, as the request type of `WebpayClient`'s `Service` impl.
+#[derive(Clone, Debug)]
+pub enum WebpayRequest {
+    Create(CreateRequest),
+    Commit { token_ws: TokenWs },
+    Status { token_ws: TokenWs },
+    Refund { token_ws: TokenWs, amount: i64 },
+    Capture { token_ws: TokenWs, buy_order: BuyOrder, authorization_code: String, capture_amount: i64 },
+}
+
+/// The response to a [`WebpayRequest`], in the variant matching the request.
+#[derive(Debug)]
+pub enum WebpayResponse {
+    Created(CreateResponse),
+    Committed(CommitResponse),
+    Status(StatusResponse),
+    Refunded(RefundResponse),
+    Captured(CaptureResponse),
+}
+
+impl Service<WebpayRequest> for WebpayClient {
+    type Response = WebpayResponse;
+    type Error = WebpayError;
+    type Future = BoxFuture<Result<WebpayResponse, WebpayError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: WebpayRequest) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move {
+            Ok(match req {
+                WebpayRequest::Create(req) => WebpayResponse::Created(client.wp_create(&req).await?),
+                WebpayRequest::Commit { token_ws } => WebpayResponse::Committed(client.wp_commit(&token_ws).await?),
+                WebpayRequest::Status { token_ws } => WebpayResponse::Status(client.wp_status(&token_ws).await?),
+                WebpayRequest::Refund { token_ws, amount } => WebpayResponse::Refunded(client.wp_refund(&token_ws, amount).await?),
+                WebpayRequest::Capture { token_ws, buy_order, authorization_code, capture_amount } => {
+                    WebpayResponse::Captured(client.wp_capture(&token_ws, &buy_order, &authorization_code, capture_amount).await?)
+                }
+            })
+        })
+    }
+}
+
+/// A tower HTTP service backed by a `reqwest::Client`, to be wrapped in layers and
+/// passed to `WebpayClient::with_service`.
+#[derive(Clone, Debug, Default)]
+pub struct ReqwestService {
+    http: reqwest::Client,
+}
+
+impl ReqwestService {
+    /// Wraps a configured `reqwest::Client`.
+    pub fn new(http: reqwest::Client) -> Self { Self { http } }
+}
+
+impl Service<http::Request<Bytes>> for ReqwestService {
+    type Response = http::Response<Bytes>;
+    type Error = reqwest::Error;
+    type Future = BoxFuture<Result<http::Response<Bytes>, reqwest::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<Bytes>) -> Self::Future {
+        let http = self.http.clone();
+        Box::pin(async move { execute(&http, req).await })
+    }
+}
+
+/// A [`Transport`] that sends requests through a tower HTTP service.
+///
+/// The service is cloned for every request and driven to readiness before being called.
+/// Layers whose clones share state, such as `ConcurrencyLimit`, apply across requests;
+/// `RateLimit` keeps its state per clone, so put it behind a `Buffer` (which drives a
+/// single instance) or use a shared limiter to rate-limit calls.
+pub struct ServiceTransport<S> {
+    service: Mutex<S>,
+}
+
+impl<S> ServiceTransport<S> {
+    /// Wraps a tower service taking `http::Request<Bytes>`, e.g., a layered [`ReqwestService`].
+    pub fn new(service: S) -> Self { Self { service: Mutex::new(service) } }
+}
+
+#[async_trait]
+impl<S> Transport for ServiceTransport<S>
+where
+    S: Service<http::Request<Bytes>, Response = http::Response<Bytes>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+{
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        let service = self.service.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        service.oneshot(req).await.map_err(|e| into_webpay_error(e.into()))
+    }
+}
+
+/// Keeps `reqwest` errors in `WebpayError::Http`, and everything else (e.g., a tower
+/// timeout or overload error) in `WebpayError::Transport`.
+fn into_webpay_error(e: BoxError) -> WebpayError {
+    match e.downcast::<reqwest::Error>() {
+        Ok(e) => WebpayError::Http(*e),
+        Err(e) => WebpayError::Transport(e),
+    }
+}
+
+impl WebpayClient {
+    /// Sends every request through a tower HTTP service.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use webpay::client::{Credentials, Environment, WebpayClient};
+    /// # use webpay::tower::ReqwestService;
+    /// # let creds = Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() };
+    /// let http = tower::ServiceBuilder::new()
+    ///     .concurrency_limit(8)
+    ///     .service(ReqwestService::default());
+    /// let client = WebpayClient::new(Environment::Integration, creds).with_service(http);
+    /// ```
+    pub fn with_service<S>(self, service: S) -> Self
+    where
+        S: Service<http::Request<Bytes>, Response = http::Response<Bytes>> + Clone + Send + 'static,
+        S::Future: Send,
+        S::Error: Into<BoxError>,
+    {
+        self.with_transport(ServiceTransport::new(service))
+    }
+}
+
+/// A layer adding the `Tbk-Api-Key-Id` and `Tbk-Api-Key-Secret` headers to every request.
+#[derive(Clone, Debug)]
+pub struct TransbankAuthLayer {
+    headers: HeaderMap,
+}
+
+impl TransbankAuthLayer {
+    pub fn new(creds: &Credentials) -> Self {
+        Self { headers: creds.auth_headers() }
+    }
+}
+
+impl<S> Layer<S> for TransbankAuthLayer {
+    type Service = TransbankAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TransbankAuth { inner, headers: self.headers.clone() }
+    }
+}
+
+/// The service produced by [`TransbankAuthLayer`].
+#[derive(Clone, Debug)]
+pub struct TransbankAuth<S> {
+    inner: S,
+    headers: HeaderMap,
+}
+
+impl<S, B> Service<http::Request<B>> for TransbankAuth<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        for (name, value) in &self.headers {
+            req.headers_mut().insert(name, value.clone());
+        }
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::Client as HttpClient;
+
+use crate::types::WebpayError;
+
+/// Sends the HTTP requests built by `WebpayClient`.
+///
+/// The default is [`ReqwestTransport`]. Swap it with `WebpayClient::with_transport`
+/// (or `with_service` for tower services, feature `tower`) to add middleware, record
+/// traffic or point the client at a test double.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Sends `req`, which already carries the URL, headers and JSON body, and returns the
+    /// full response. Non-2xx responses are not errors at this level.
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError>;
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for std::sync::Arc<T> {
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        (**self).send(req).await
+    }
+}
+
+/// A [`Transport`] backed by a `reqwest::Client`.
+#[derive(Clone, Debug)]
+pub struct ReqwestTransport {
+    http: HttpClient,
+}
+
+impl ReqwestTransport {
+    /// Wraps a configured `reqwest::Client` (timeouts, proxies, TLS, ...).
+    pub fn new(http: HttpClient) -> Self { Self { http } }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        Ok(execute(&self.http, req).await?)
+    }
+}
+
+/// Sends `req` with `http`, reading the whole response body.
+pub(crate) async fn execute(http: &HttpClient, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, reqwest::Error> {
+    let res = http.execute(reqwest::Request::try_from(req)?).await?;
+    let mut builder = http::Response::builder().status(res.status()).version(res.version());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = res.headers().clone();
+    }
+    let body = res.bytes().await?;
+    Ok(builder.body(body).expect("response parts copied from a valid response"))
+}
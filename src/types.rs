@@ -1,59 +1,358 @@
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::StatusCode;
+use std::fmt;
+use std::str::FromStr;
+use crate::client::{ApiVersion, Product};
 
 #[derive(Debug, thiserror::Error)]
 pub enum WebpayError {
     #[error("http {0}")]
     Http(#[from] reqwest::Error),
     #[error("webpay error: {0}")]
-    Api(String),
+    Status(ApiError),
+    /// `wp_status` on a transaction older than 7 days, which Transbank no longer reports on.
+    #[error("webpay error: {0}")]
+    StatusExpired(ApiError),
+    #[error("transport: {0}")]
+    Transport(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A 2xx response whose body isn't the expected JSON, kept whole for audits.
+    #[error(transparent)]
+    Decode(Box<DecodeError>),
     #[error("unexpected response")]
     Unexpected,
+    #[error("{product} API {version} does not support {operation} (requires {required} or newer)")]
+    Unsupported {
+        product: Product,
+        operation: &'static str,
+        version: ApiVersion,
+        required: ApiVersion,
+    },
+    #[error(transparent)]
+    InvalidId(#[from] InvalidId),
+    #[error(transparent)]
+    Store(#[from] crate::store::StoreError),
+    #[error(transparent)]
+    Mismatch(Box<crate::verify::CommitMismatch>),
+    /// Another process still holds the commit of this transaction; ask `wp_status` later.
+    #[error("transaction {token} is still being committed elsewhere")]
+    CommitInProgress { token: TokenWs },
+}
+
+impl WebpayError {
+    /// Returns the HTTP status of a non-2xx Transbank response.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            WebpayError::Status(e) | WebpayError::StatusExpired(e) => Some(e.status),
+            WebpayError::Http(e) => e.status(),
+            _ => None,
+        }
+    }
+}
+
+/// A 2xx response from Transbank that could not be decoded.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid json in {operation} response: {error}")]
+pub struct DecodeError {
+    /// The operation called, e.g., `"commit"`.
+    pub operation: &'static str,
+    #[source]
+    pub error: serde_json::Error,
+    /// The response, with its exact body.
+    pub response: RawResponse<()>,
+}
+
+/// A non-2xx response from Transbank.
+#[derive(Clone, Debug)]
+pub struct ApiError {
+    /// The operation that failed, e.g., `"commit"`.
+    pub operation: &'static str,
+    pub status: StatusCode,
+    /// The raw response body.
+    pub body: String,
+}
+
+impl ApiError {
+    /// Returns the `error_message` field of the JSON body, if any.
+    pub fn message(&self) -> Option<String> {
+        let body: serde_json::Value = serde_json::from_str(&self.body).ok()?;
+        body.get("error_message")?.as_str().map(Into::into)
+    }
+
+    /// Returns `true` if this is Transbank refusing a status query because the
+    /// transaction is more than 7 days old.
+    pub fn is_status_expired(&self) -> bool {
+        let message = self.message().unwrap_or_else(|| self.body.clone()).to_lowercase();
+        self.status.as_u16() == 422 && (message.contains("7 days") || message.contains("max time"))
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {} {}", self.operation, self.status, self.body)
+    }
+}
+
+//
+// Identifiers
+//
+/// Returned when a string is not a valid [`TokenWs`], [`BuyOrder`] or [`SessionId`].
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("invalid {field}: {reason}")]
+pub struct InvalidId {
+    pub field: &'static str,
+    pub reason: &'static str,
+}
+
+macro_rules! id_newtype {
+    ($name:ident) => {
+        impl $name {
+            /// Returns the identifier as sent to Transbank.
+            pub fn as_str(&self) -> &str { &self.0 }
+
+            /// Returns the inner `String`.
+            pub fn into_inner(self) -> String { self.0 }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = InvalidId;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = InvalidId;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = InvalidId;
+
+            fn try_from(s: &str) -> Result<Self, Self::Error> {
+                Self::new(s)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> String { id.0 }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str { &self.0 }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool { self.0 == other }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool { self.0 == *other }
+        }
+    };
+}
+
+/// The `token_ws` identifying a Webpay transaction: 64 hexadecimal characters.
+///
+/// `Display` prints the full token; `Debug` only prints its first characters so tokens
+/// don't end up whole in logs.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TokenWs(String);
+
+impl TokenWs {
+    /// Validates and wraps a token.
+    pub fn new(s: impl Into<String>) -> Result<Self, InvalidId> {
+        let s = s.into();
+        if s.len() != 64 {
+            return Err(InvalidId { field: "token_ws", reason: "must be 64 characters long" });
+        }
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(InvalidId { field: "token_ws", reason: "must only contain hexadecimal characters" });
+        }
+        Ok(Self(s))
+    }
+}
+
+impl fmt::Debug for TokenWs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenWs(\"{}…\")", &self.0[..6])
+    }
+}
+
+id_newtype!(TokenWs);
+
+/// A commerce-assigned order identifier: up to 26 letters, digits or `|_=&%.,~:/?[+!@()>-`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BuyOrder(String);
+
+impl BuyOrder {
+    /// Maximum length accepted by Transbank.
+    pub const MAX_LEN: usize = 26;
+
+    /// Validates and wraps a buy order.
+    pub fn new(s: impl Into<String>) -> Result<Self, InvalidId> {
+        const SYMBOLS: &str = "|_=&%.,~:/?[+!@()>-";
+        let s = s.into();
+        if s.is_empty() || s.len() > Self::MAX_LEN {
+            return Err(InvalidId { field: "buy_order", reason: "must be between 1 and 26 characters long" });
+        }
+        if !s.chars().all(|c| c.is_ascii_alphanumeric() || SYMBOLS.contains(c)) {
+            return Err(InvalidId { field: "buy_order", reason: "must only contain letters, digits or |_=&%.,~:/?[+!@()>-" });
+        }
+        Ok(Self(s))
+    }
+}
+
+id_newtype!(BuyOrder);
+
+/// A commerce-assigned session identifier: up to 61 characters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SessionId(String);
+
+impl SessionId {
+    /// Maximum length accepted by Transbank.
+    pub const MAX_LEN: usize = 61;
+
+    /// Validates and wraps a session id.
+    pub fn new(s: impl Into<String>) -> Result<Self, InvalidId> {
+        let s = s.into();
+        if s.is_empty() || s.len() > Self::MAX_LEN {
+            return Err(InvalidId { field: "session_id", reason: "must be between 1 and 61 characters long" });
+        }
+        Ok(Self(s))
+    }
+}
+
+id_newtype!(SessionId);
+
+// Response fields are not validated: Transbank echoes what it stored, and a value this
+// crate would refuse in a request must not fail a commit that already happened.
+
+fn deserialize_buy_order_unchecked<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<BuyOrder, D::Error> {
+    String::deserialize(deserializer).map(BuyOrder)
+}
+
+fn deserialize_session_id_unchecked<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<SessionId, D::Error> {
+    String::deserialize(deserializer).map(SessionId)
 }
 
 //
 // Create
 //
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CreateRequest {
-    /// Up to 26 chars
-    pub buy_order: String,
-    pub session_id: String,
+    pub buy_order: BuyOrder,
+    pub session_id: SessionId,
     /// Amount in CLP (integer)
     pub amount: i64,
     /// Your return endpoint; Webpay will POST back here with token_ws
     pub return_url: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CreateResponse {
-    pub token: String,
+    pub token: TokenWs,
     pub url: String, // redirect target to POST token_ws
+    /// Fields this crate doesn't define, exactly as Transbank sent them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 //
 // Commit / Status responses
 //
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CardDetail {
     pub card_number: Option<String>, // last 4 digits
+    /// Fields this crate doesn't define, exactly as Transbank sent them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize)]
+/// A timestamp that doesn't parse is left `None` and kept in `extra`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct CommitResponse {
     pub vci: Option<String>,
     pub amount: i64,
     pub status: String, // "AUTHORIZED" on success
-    pub buy_order: String,
-    pub session_id: String,
+    /// As Transbank echoes it, not validated.
+    #[serde(deserialize_with = "deserialize_buy_order_unchecked")]
+    pub buy_order: BuyOrder,
+    /// As Transbank echoes it, not validated.
+    #[serde(deserialize_with = "deserialize_session_id_unchecked")]
+    pub session_id: SessionId,
     pub card_detail: Option<CardDetail>,
-    pub accounting_date: Option<String>,   // e.g., "0522"
+    pub accounting_date: Option<String>,   // e.g., "0522"; see `accounting_date_naive`
+    #[serde(default, deserialize_with = "crate::dates::deserialize_timestamp", skip_serializing_if = "Option::is_none")]
     pub transaction_date: Option<DateTime<Utc>>,
     pub authorization_code: Option<String>,// e.g., "1213"
     pub payment_type_code: Option<String>, // "VN","VD","VC","SI","S2","NC"
     pub response_code: Option<i32>,        // success == 0
     pub installments_number: Option<i32>,
     pub installments_amount: Option<i64>,
+    /// Remaining balance after refunds. API v1.1 and newer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<i64>,
+    /// Remaining balance of a prepaid card. API v1.3 and newer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prepaid_balance: Option<i64>,
+    /// Fields this crate doesn't define, exactly as Transbank sent them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CommitResponse {
+    /// Returns `accounting_date` as a date, taking the year closest to `transaction_date`,
+    /// or `None` without one. See [`crate::dates::accounting_date`] to pick another date.
+    pub fn accounting_date_naive(&self) -> Option<NaiveDate> {
+        let near = crate::dates::to_santiago(self.transaction_date?).date_naive();
+        crate::dates::accounting_date(self.accounting_date.as_deref()?, near)
+    }
+
+    /// Returns `transaction_date` in Santiago local time, as printed on receipts.
+    pub fn transaction_date_santiago(&self) -> Option<DateTime<chrono_tz::Tz>> {
+        self.transaction_date.map(crate::dates::to_santiago)
+    }
+}
+
+impl crate::dates::Timestamped for CommitResponse {
+    const TIMESTAMP: &'static str = "transaction_date";
+
+    fn from_json(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        CommitResponse::deserialize(value)
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> { self.transaction_date }
+
+    fn extra_mut(&mut self) -> &mut serde_json::Map<String, serde_json::Value> { &mut self.extra }
+}
+
+impl<'de> Deserialize<'de> for CommitResponse {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::dates::deserialize_timestamped(deserializer)
+    }
+}
+
+impl Serialize for CommitResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CommitResponse::serialize(self, serializer)
+    }
 }
 
 pub type StatusResponse = CommitResponse;
@@ -61,18 +360,133 @@ pub type StatusResponse = CommitResponse;
 //
 // Refund
 //
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RefundRequest {
     pub amount: i64,
 }
 
-#[derive(Debug, Deserialize)]
+/// An `authorization_date` that doesn't parse is left `None` and kept in `extra`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct RefundResponse {
     #[serde(rename = "type")]
     pub type_: Option<String>,
     pub authorization_code: Option<String>,
+    #[serde(default, deserialize_with = "crate::dates::deserialize_timestamp", skip_serializing_if = "Option::is_none")]
     pub authorization_date: Option<DateTime<Utc>>,
     pub nullified_amount: Option<i64>,
     pub balance: Option<i64>,
     pub response_code: Option<i32>, // 0 on success
+    /// Fields this crate doesn't define, exactly as Transbank sent them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl crate::dates::Timestamped for RefundResponse {
+    const TIMESTAMP: &'static str = "authorization_date";
+
+    fn from_json(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        RefundResponse::deserialize(value)
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> { self.authorization_date }
+
+    fn extra_mut(&mut self) -> &mut serde_json::Map<String, serde_json::Value> { &mut self.extra }
+}
+
+impl<'de> Deserialize<'de> for RefundResponse {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::dates::deserialize_timestamped(deserializer)
+    }
+}
+
+impl Serialize for RefundResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RefundResponse::serialize(self, serializer)
+    }
+}
+
+impl RefundResponse {
+    /// Returns `true` if Transbank accepted the refund: a `NULLIFIED` (or partially
+    /// nullified) refund needs `response_code` 0, while a `REVERSED` one may omit it.
+    pub fn is_approved(&self) -> bool {
+        match self.response_code {
+            Some(code) => code == 0,
+            None => self.type_.as_deref() == Some("REVERSED"),
+        }
+    }
+}
+
+//
+// Capture (deferred capture commerce codes only)
+//
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureRequest {
+    pub buy_order: BuyOrder,
+    pub authorization_code: String,
+    pub capture_amount: i64,
+}
+
+/// An `authorization_date` that doesn't parse is left `None` and kept in `extra`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct CaptureResponse {
+    pub token: Option<TokenWs>,
+    pub authorization_code: Option<String>,
+    #[serde(default, deserialize_with = "crate::dates::deserialize_timestamp", skip_serializing_if = "Option::is_none")]
+    pub authorization_date: Option<DateTime<Utc>>,
+    pub captured_amount: Option<i64>,
+    pub response_code: Option<i32>, // 0 on success
+    /// Fields this crate doesn't define, exactly as Transbank sent them.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl crate::dates::Timestamped for CaptureResponse {
+    const TIMESTAMP: &'static str = "authorization_date";
+
+    fn from_json(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        CaptureResponse::deserialize(value)
+    }
+
+    fn timestamp(&self) -> Option<DateTime<Utc>> { self.authorization_date }
+
+    fn extra_mut(&mut self) -> &mut serde_json::Map<String, serde_json::Value> { &mut self.extra }
+}
+
+impl<'de> Deserialize<'de> for CaptureResponse {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::dates::deserialize_timestamped(deserializer)
+    }
+}
+
+impl Serialize for CaptureResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CaptureResponse::serialize(self, serializer)
+    }
+}
+
+/// A decoded response together with the HTTP exchange it came from, as returned by the
+/// `wp_*_raw` methods. Keep `body` for audits and bug reports: it is exactly what
+/// Transbank sent.
+#[derive(Clone, Debug)]
+pub struct RawResponse<T> {
+    pub value: T,
+    pub status: StatusCode,
+    pub headers: http::HeaderMap,
+    pub body: bytes::Bytes,
+    /// Time from sending the request to receiving the whole response body.
+    pub latency: std::time::Duration,
+}
+
+impl<T> RawResponse<T> {
+    /// Transforms the decoded value, keeping the rest.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> RawResponse<U> {
+        RawResponse { value: f(self.value), status: self.status, headers: self.headers, body: self.body, latency: self.latency }
+    }
+
+    /// Parses the body as generic JSON.
+    pub fn json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
 }
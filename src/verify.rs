@@ -0,0 +1,112 @@
+//! Checks that a commit belongs to the order it was meant for.
+//!
+//! `is_authorized` only looks at `status` and `response_code`. A token taken from one
+//! order and replayed against another order's return URL commits just fine, but for the
+//! wrong buy order and amount. [`commit_verified`] commits and compares the result with
+//! the [`ExpectedOrder`], failing with `WebpayError::Mismatch` if they disagree.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::client::WebpayClient;
+use crate::gateway::PaymentGateway;
+use crate::types::*;
+
+/// What a commit must match: the buy order, session and amount the transaction was
+/// created with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedOrder {
+    pub buy_order: BuyOrder,
+    pub session_id: SessionId,
+    pub amount: i64,
+}
+
+impl From<&CreateRequest> for ExpectedOrder {
+    fn from(req: &CreateRequest) -> Self {
+        Self { buy_order: req.buy_order.clone(), session_id: req.session_id.clone(), amount: req.amount }
+    }
+}
+
+/// A field of the commit that differs from the [`ExpectedOrder`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    BuyOrder { expected: BuyOrder, actual: BuyOrder },
+    SessionId { expected: SessionId, actual: SessionId },
+    Amount { expected: i64, actual: i64 },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::BuyOrder { expected, actual } => write!(f, "buy_order is {}, expected {}", actual, expected),
+            Mismatch::SessionId { expected, actual } => write!(f, "session_id is {}, expected {}", actual, expected),
+            Mismatch::Amount { expected, actual } => write!(f, "amount is {}, expected {}", actual, expected),
+        }
+    }
+}
+
+/// A commit that does not match its order.
+///
+/// Transbank has already committed the transaction, so `commit` (which may be
+/// authorized) is kept for the refund or investigation that should follow.
+#[derive(Clone, Debug)]
+pub struct CommitMismatch {
+    pub mismatches: Vec<Mismatch>,
+    pub commit: CommitResponse,
+}
+
+impl fmt::Display for CommitMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "commit does not match its order: ")?;
+        for (i, m) in self.mismatches.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", m)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommitMismatch {}
+
+/// Compares a commit (or status) response with the order it should belong to.
+pub fn verify_commit(expected: &ExpectedOrder, commit: CommitResponse) -> Result<CommitResponse, Box<CommitMismatch>> {
+    let mut mismatches = Vec::new();
+    if commit.buy_order != expected.buy_order {
+        mismatches.push(Mismatch::BuyOrder { expected: expected.buy_order.clone(), actual: commit.buy_order.clone() });
+    }
+    if commit.session_id != expected.session_id {
+        mismatches.push(Mismatch::SessionId { expected: expected.session_id.clone(), actual: commit.session_id.clone() });
+    }
+    if commit.amount != expected.amount {
+        mismatches.push(Mismatch::Amount { expected: expected.amount, actual: commit.amount });
+    }
+    if mismatches.is_empty() {
+        Ok(commit)
+    } else {
+        Err(Box::new(CommitMismatch { mismatches, commit }))
+    }
+}
+
+/// Commits `token_ws` through any [`PaymentGateway`] and verifies the result against
+/// `expected`.
+pub async fn commit_verified<G: PaymentGateway + ?Sized>(gateway: &G, token_ws: &TokenWs, expected: &ExpectedOrder) -> Result<CommitResponse, WebpayError> {
+    let commit = gateway.wp_commit(token_ws).await?;
+    verify_commit(expected, commit).map_err(WebpayError::Mismatch)
+}
+
+impl WebpayClient {
+    /// Commit a Webpay Plus transaction and check that it belongs to the expected order.
+    ///
+    /// Fails with `WebpayError::Mismatch` if the committed buy order, session or amount
+    /// differ from `expected`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ws` - The token received in the return callback.
+    /// * `expected` - The order the token should belong to, e.g., `ExpectedOrder::from(&create_request)`.
+    pub async fn wp_commit_verified(&self, token_ws: &TokenWs, expected: &ExpectedOrder) -> Result<CommitResponse, WebpayError> {
+        commit_verified(self, token_ws, expected).await
+    }
+}
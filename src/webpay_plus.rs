@@ -1,9 +1,109 @@
-use crate::client::WebpayClient;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Instant;
+
+use crate::client::{ApiVersion, Product, WebpayClient};
 use crate::types::*;
 
-static V1: &str = "/rswebpaytransaction/api/webpay/v1.2";
+static BASE_PATH: &str = "/rswebpaytransaction/api/webpay";
+
+/// A Webpay Plus operation, used to check what each API version offers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Create,
+    Commit,
+    Status,
+    Refund,
+    Capture,
+}
+
+impl Operation {
+    /// Returns a short lowercase name, as used in error messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Commit => "commit",
+            Operation::Status => "status",
+            Operation::Refund => "refund",
+            Operation::Capture => "capture",
+        }
+    }
+
+    /// Returns the oldest API version that offers this operation.
+    pub fn since(&self) -> ApiVersion {
+        match self {
+            Operation::Capture => ApiVersion::V1_1,
+            _ => ApiVersion::V1_0,
+        }
+    }
+
+    /// Returns `true` if `version` offers this operation.
+    pub fn is_supported_by(&self, version: ApiVersion) -> bool {
+        version >= self.since()
+    }
+}
+
+/// Moves the response fields the configured API version does not define to `extra`, so
+/// typed fields never depend on whatever the server happened to send.
+pub(crate) fn gate_fields(version: ApiVersion, r: &mut CommitResponse) {
+    if version < ApiVersion::V1_1 {
+        if let Some(balance) = r.balance.take() {
+            r.extra.insert("balance".into(), balance.into());
+        }
+    }
+    if version < ApiVersion::V1_3 {
+        if let Some(prepaid_balance) = r.prepaid_balance.take() {
+            r.extra.insert("prepaid_balance".into(), prepaid_balance.into());
+        }
+    }
+}
+
+/// Returns the path of a Webpay Plus operation for `version`, or
+/// `WebpayError::Unsupported` if that version lacks the operation.
+pub(crate) fn wp_path(version: ApiVersion, op: Operation, path: &str) -> Result<String, WebpayError> {
+    if !op.is_supported_by(version) {
+        return Err(WebpayError::Unsupported {
+            product: Product::WebpayPlus,
+            operation: op.name(),
+            version,
+            required: op.since(),
+        });
+    }
+    Ok(format!("{}/{}{}", BASE_PATH, version, path))
+}
+
+/// Decodes the JSON response of a Webpay Plus operation.
+///
+/// Non-2xx responses become `WebpayError::Status`, or `WebpayError::StatusExpired` for
+/// status queries on transactions older than 7 days.
+pub(crate) fn wp_decode<T: DeserializeOwned>(op: Operation, raw: RawResponse<()>) -> Result<RawResponse<T>, WebpayError> {
+    if raw.status.is_success() {
+        return match serde_json::from_slice(&raw.body) {
+            Ok(value) => Ok(raw.map(|()| value)),
+            Err(error) => Err(WebpayError::Decode(Box::new(DecodeError { operation: op.name(), error, response: raw }))),
+        };
+    }
+    let error = ApiError { operation: op.name(), status: raw.status, body: String::from_utf8_lossy(&raw.body).into_owned() };
+    if op == Operation::Status && error.is_status_expired() {
+        Err(WebpayError::StatusExpired(error))
+    } else {
+        Err(WebpayError::Status(error))
+    }
+}
 
 impl WebpayClient {
+    /// Calls a Webpay Plus operation and decodes its JSON response, keeping the raw response.
+    async fn wp_call<T: DeserializeOwned>(&self, op: Operation, method: Method, path: &str, body: Option<&impl Serialize>) -> Result<RawResponse<T>, WebpayError> {
+        let url = self.endpoint(&wp_path(self.api_version(Product::WebpayPlus), op, path)?);
+        let body = body.map(serde_json::to_vec).transpose()?;
+        let started = Instant::now();
+        let res = self.execute(method, &url, body).await?;
+        let latency = started.elapsed();
+        let (parts, body) = res.into_parts();
+        wp_decode(op, RawResponse { value: (), status: parts.status, headers: parts.headers, body, latency })
+    }
+
     /// Create a Webpay Plus transaction.
     ///
     /// This is the first step in the transaction flow. It returns a token and a URL that the user should be redirected to.
@@ -12,20 +112,13 @@ impl WebpayClient {
     ///
     /// * `req` - A `CreateRequest` struct with the transaction details.
     pub async fn wp_create(&self, req: &CreateRequest) -> Result<CreateResponse, WebpayError> {
-        let url = self.endpoint(&format!("{}/transactions", V1));
-        let res = self.http()
-            .post(url)
-            .headers(self.headers_ref())
-            .json(req)
-            .send().await?;
-
-        let status = res.status();
-        if status.is_success() {
-            Ok(res.json::<CreateResponse>().await?)
-        } else {
-            let body = res.text().await.unwrap_or_default();
-            Err(WebpayError::Api(format!("create failed: {} {}", status, body)))
-        }
+        Ok(self.wp_create_raw(req).await?.value)
+    }
+
+    /// Like [`wp_create`](Self::wp_create), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub async fn wp_create_raw(&self, req: &CreateRequest) -> Result<RawResponse<CreateResponse>, WebpayError> {
+        self.wp_call(Operation::Create, Method::POST, "/transactions", Some(req)).await
     }
 
     /// Commit (confirm) a Webpay Plus transaction.
@@ -35,20 +128,17 @@ impl WebpayClient {
     /// # Arguments
     ///
     /// * `token_ws` - The token received in the `CreateResponse`.
-    pub async fn wp_commit(&self, token_ws: &str) -> Result<CommitResponse, WebpayError> {
-        let url = self.endpoint(&format!("{}/transactions/{}", V1, token_ws));
-        let res = self.http()
-            .put(url)
-            .headers(self.headers_ref())
-            .send().await?;
-
-        let status = res.status();
-        if status.is_success() {
-            Ok(res.json::<CommitResponse>().await?)
-        } else {
-            let body = res.text().await.unwrap_or_default();
-            Err(WebpayError::Api(format!("commit failed: {} {}", status, body)))
-        }
+    pub async fn wp_commit(&self, token_ws: &TokenWs) -> Result<CommitResponse, WebpayError> {
+        Ok(self.wp_commit_raw(token_ws).await?.value)
+    }
+
+    /// Like [`wp_commit`](Self::wp_commit), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub async fn wp_commit_raw(&self, token_ws: &TokenWs) -> Result<RawResponse<CommitResponse>, WebpayError> {
+        let path = format!("/transactions/{}", token_ws);
+        let mut commit: RawResponse<CommitResponse> = self.wp_call(Operation::Commit, Method::PUT, &path, None::<&()>).await?;
+        gate_fields(self.api_version(Product::WebpayPlus), &mut commit.value);
+        Ok(commit)
     }
 
     /// Get the status of a Webpay Plus transaction.
@@ -56,20 +146,17 @@ impl WebpayClient {
     /// # Arguments
     ///
     /// * `token_ws` - The token received in the `CreateResponse`.
-    pub async fn wp_status(&self, token_ws: &str) -> Result<StatusResponse, WebpayError> {
-        let url = self.endpoint(&format!("{}/transactions/{}", V1, token_ws));
-        let res = self.http()
-            .get(url)
-            .headers(self.headers_ref())
-            .send().await?;
-
-        let status = res.status();
-        if status.is_success() {
-            Ok(res.json::<StatusResponse>().await?)
-        } else {
-            let body = res.text().await.unwrap_or_default();
-            Err(WebpayError::Api(format!("status failed: {} {}", status, body)))
-        }
+    pub async fn wp_status(&self, token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        Ok(self.wp_status_raw(token_ws).await?.value)
+    }
+
+    /// Like [`wp_status`](Self::wp_status), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub async fn wp_status_raw(&self, token_ws: &TokenWs) -> Result<RawResponse<StatusResponse>, WebpayError> {
+        let path = format!("/transactions/{}", token_ws);
+        let mut status: RawResponse<StatusResponse> = self.wp_call(Operation::Status, Method::GET, &path, None::<&()>).await?;
+        gate_fields(self.api_version(Product::WebpayPlus), &mut status.value);
+        Ok(status)
     }
 
     /// Refund a Webpay Plus transaction.
@@ -78,22 +165,43 @@ impl WebpayClient {
     ///
     /// * `token_ws` - The token of the transaction to refund.
     /// * `amount` - The amount to refund.
-    pub async fn wp_refund(&self, token_ws: &str, amount: i64) -> Result<RefundResponse, WebpayError> {
-        let url = self.endpoint(&format!("{}/transactions/{}/refunds", V1, token_ws));
+    pub async fn wp_refund(&self, token_ws: &TokenWs, amount: i64) -> Result<RefundResponse, WebpayError> {
+        Ok(self.wp_refund_raw(token_ws, amount).await?.value)
+    }
+
+    /// Like [`wp_refund`](Self::wp_refund), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub async fn wp_refund_raw(&self, token_ws: &TokenWs, amount: i64) -> Result<RawResponse<RefundResponse>, WebpayError> {
+        let path = format!("/transactions/{}/refunds", token_ws);
         let req = RefundRequest { amount };
-        let res = self.http()
-            .post(url)
-            .headers(self.headers_ref())
-            .json(&req)
-            .send().await?;
-
-        let status = res.status();
-        if status.is_success() {
-            Ok(res.json::<RefundResponse>().await?)
-        } else {
-            let body = res.text().await.unwrap_or_default();
-            Err(WebpayError::Api(format!("refund failed: {} {}", status, body)))
-        }
+        self.wp_call(Operation::Refund, Method::POST, &path, Some(&req)).await
+    }
+
+    /// Capture a Webpay Plus transaction authorized with a deferred-capture commerce code.
+    ///
+    /// Requires API v1.1 or newer; older versions return `WebpayError::Unsupported`
+    /// without sending a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_ws` - The token of the transaction to capture.
+    /// * `buy_order` - The buy order of the transaction.
+    /// * `authorization_code` - The authorization code returned by `wp_commit`.
+    /// * `capture_amount` - The amount to capture (up to the authorized amount).
+    pub async fn wp_capture(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<CaptureResponse, WebpayError> {
+        Ok(self.wp_capture_raw(token_ws, buy_order, authorization_code, capture_amount).await?.value)
+    }
+
+    /// Like [`wp_capture`](Self::wp_capture), also returning the HTTP status, headers, raw
+    /// body and latency.
+    pub async fn wp_capture_raw(&self, token_ws: &TokenWs, buy_order: &BuyOrder, authorization_code: &str, capture_amount: i64) -> Result<RawResponse<CaptureResponse>, WebpayError> {
+        let path = format!("/transactions/{}/capture", token_ws);
+        let req = CaptureRequest {
+            buy_order: buy_order.clone(),
+            authorization_code: authorization_code.into(),
+            capture_amount,
+        };
+        self.wp_call(Operation::Capture, Method::PUT, &path, Some(&req)).await
     }
 }
 
@@ -0,0 +1,87 @@
+#![cfg(feature = "actix")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use actix_web::{test, App, HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use webpay::actix::{scope, CheckoutHandler};
+use webpay::callback::PaymentOutcome;
+use webpay::gateway::fake::FakeGateway;
+use webpay::types::{CreateRequest, CreateResponse, TokenWs};
+use webpay::verify::ExpectedOrder;
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+#[derive(Clone, Default)]
+struct Shop {
+    orders: Arc<Mutex<HashMap<TokenWs, ExpectedOrder>>>,
+}
+
+#[async_trait(?Send)]
+impl CheckoutHandler for Shop {
+    async fn order(&self, _req: &HttpRequest) -> Result<CreateRequest, HttpResponse> {
+        Ok(CreateRequest {
+            buy_order: "ORDER-1".parse().unwrap(),
+            session_id: "sess-1".parse().unwrap(),
+            amount: 1000,
+            return_url: "http://localhost:8080/webpay/return".into(),
+        })
+    }
+
+    async fn on_created(&self, request: &CreateRequest, response: &CreateResponse) {
+        self.orders.lock().unwrap().insert(response.token.clone(), ExpectedOrder::from(request));
+    }
+
+    async fn expected_order(&self, token: &TokenWs) -> Result<ExpectedOrder, HttpResponse> {
+        self.orders.lock().unwrap().get(token).cloned().ok_or_else(|| HttpResponse::NotFound().finish())
+    }
+
+    async fn on_outcome(&self, outcome: PaymentOutcome) -> HttpResponse {
+        match outcome {
+            PaymentOutcome::Rejected(t) => HttpResponse::Ok().body(format!("rejected {}", t.commit().buy_order)),
+            PaymentOutcome::TimedOut { buy_order, .. } => HttpResponse::Ok().body(format!("timed out {}", buy_order)),
+            other => HttpResponse::Ok().body(format!("{:?}", other)),
+        }
+    }
+}
+
+#[actix_web::test]
+async fn test_pay_and_return() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_create(Ok(CreateResponse { token: TOKEN.parse().unwrap(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(serde_json::from_value(serde_json::json!({
+            "amount": 1000,
+            "status": "FAILED",
+            "buy_order": "ORDER-1",
+            "session_id": "sess-1",
+            "response_code": -1
+        })).unwrap()));
+    let shop = Shop::default();
+    let app = test::init_service(App::new().service(scope("/webpay", fake.clone(), shop.clone()))).await;
+
+    let req = test::TestRequest::get().uri("/webpay/pay").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 405);
+    assert!(fake.calls().is_empty());
+
+    let req = test::TestRequest::post().uri("/webpay/pay").to_request();
+    let html = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert!(html.contains(r#"action="https://webpay.test/init""#));
+    assert!(html.contains(TOKEN));
+    assert_eq!(shop.orders.lock().unwrap()[&TOKEN.parse::<TokenWs>().unwrap()].amount, 1000);
+
+    let req = test::TestRequest::post().uri("/webpay/return").set_form([("token_ws", TOKEN)]).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "rejected ORDER-1");
+
+    let req = test::TestRequest::get().uri("/webpay/return?TBK_ORDEN_COMPRA=ORDER-2&TBK_ID_SESION=sess-2").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "timed out ORDER-2");
+
+    let req = test::TestRequest::post().uri("/webpay/return").set_form([("token_ws", "x")]).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // Tokens the shop never created are not committed.
+    shop.orders.lock().unwrap().clear();
+    let req = test::TestRequest::post().uri("/webpay/return").set_form([("token_ws", TOKEN)]).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    assert_eq!(fake.calls().len(), 2);
+}
@@ -0,0 +1,93 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use serde_json::{json, Value};
+use webpay::client::{ApiVersion, Credentials, Environment, Product, WebpayClient};
+use webpay::types::{TokenWs, WebpayError};
+
+const TOKEN: &str = "01ab4a0bca4cb1ad9ad3e5c3ec6ddd5d38e3f9ac4a6c3fd7d8d2ba1bd9b1b6ee";
+
+fn token() -> TokenWs { TOKEN.parse().unwrap() }
+
+// Echoes the requested version back in `session_id` and always includes every
+// balance field, so the tests can see which ones the client keeps.
+async fn status(Path((version, token)): Path<(String, String)>) -> Json<Value> {
+    Json(json!({
+        "vci": "TSY",
+        "amount": 1000,
+        "status": "AUTHORIZED",
+        "buy_order": "ORDER-1",
+        "session_id": version,
+        "card_detail": { "card_number": "6623" },
+        "accounting_date": "0522",
+        "transaction_date": "2019-05-22T16:41:21.063Z",
+        "authorization_code": token[..4].to_string(),
+        "payment_type_code": "VN",
+        "response_code": 0,
+        "installments_number": 0,
+        "balance": 1000,
+        "prepaid_balance": 5000
+    }))
+}
+
+async fn spawn_server() -> String {
+    let app = Router::new().route("/rswebpaytransaction/api/webpay/:version/transactions/:token", get(status));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn client(base_url: String) -> WebpayClient {
+    WebpayClient::new(
+        Environment::Custom(base_url),
+        Credentials {
+            commerce_code: "597055555532".into(),
+            api_key: "579B532A7440BB0C9079DED94D31EA1615BACEB56610332264630D42D0A36B1C".into(),
+        },
+    )
+}
+
+#[tokio::test]
+async fn test_version_is_used_in_path() {
+    let base = spawn_server().await;
+
+    let status = client(base.clone()).wp_status(&token()).await.expect("status");
+    assert_eq!(status.session_id, "v1.2");
+
+    let status = client(base)
+        .with_api_version(Product::WebpayPlus, ApiVersion::V1_3)
+        .wp_status(&token()).await.expect("status");
+    assert_eq!(status.session_id, "v1.3");
+}
+
+#[tokio::test]
+async fn test_fields_are_gated_by_version() {
+    let base = spawn_server().await;
+
+    let v13 = client(base.clone()).with_api_version(Product::WebpayPlus, ApiVersion::V1_3);
+    let status = v13.wp_status(&token()).await.expect("status");
+    assert_eq!(status.balance, Some(1000));
+    assert_eq!(status.prepaid_balance, Some(5000));
+
+    let v10 = client(base).with_api_version(Product::WebpayPlus, ApiVersion::V1_0);
+    let status = v10.wp_status(&token()).await.expect("status");
+    assert_eq!(status.balance, None);
+    assert_eq!(status.prepaid_balance, None);
+}
+
+#[tokio::test]
+async fn test_unsupported_operation() {
+    // No server: the error must be raised before any request is sent.
+    let client = client("http://127.0.0.1:9".into()).with_api_version(Product::WebpayPlus, ApiVersion::V1_0);
+    let err = client.wp_capture(&token(), &"ORDER-1".parse().unwrap(), "1213", 1000).await.unwrap_err();
+    assert!(matches!(
+        err,
+        WebpayError::Unsupported { operation: "capture", version: ApiVersion::V1_0, required: ApiVersion::V1_1, .. }
+    ));
+}
+
+#[test]
+fn test_parse_version() {
+    assert_eq!("v1.3".parse::<ApiVersion>(), Ok(ApiVersion::V1_3));
+    assert_eq!("1.0".parse::<ApiVersion>(), Ok(ApiVersion::V1_0));
+    assert!("v2.0".parse::<ApiVersion>().is_err());
+}
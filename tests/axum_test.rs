@@ -0,0 +1,134 @@
+#![cfg(feature = "axum")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::Request, http::StatusCode, response::{IntoResponse, Response}, Router};
+use webpay::axum::{router, CheckoutHandler};
+use webpay::callback::PaymentOutcome;
+use webpay::gateway::fake::FakeGateway;
+use webpay::types::{CreateRequest, CreateResponse, TokenWs, WebpayError};
+use webpay::verify::ExpectedOrder;
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+#[derive(Clone, Default)]
+struct Shop {
+    orders: Arc<Mutex<HashMap<TokenWs, ExpectedOrder>>>,
+    outcomes: Arc<Mutex<Vec<String>>>,
+}
+
+#[axum::async_trait]
+impl CheckoutHandler for Shop {
+    async fn order(&self, _req: Request) -> Result<CreateRequest, Response> {
+        Ok(CreateRequest {
+            buy_order: "ORDER-1".parse().unwrap(),
+            session_id: "sess-1".parse().unwrap(),
+            amount: 1000,
+            return_url: "http://localhost:3000/return".into(),
+        })
+    }
+
+    async fn on_created(&self, request: &CreateRequest, response: &CreateResponse) {
+        self.orders.lock().unwrap().insert(response.token.clone(), ExpectedOrder::from(request));
+    }
+
+    async fn expected_order(&self, token: &TokenWs) -> Result<ExpectedOrder, Response> {
+        self.orders.lock().unwrap().get(token).cloned().ok_or_else(|| StatusCode::NOT_FOUND.into_response())
+    }
+
+    async fn on_outcome(&self, outcome: PaymentOutcome) -> Response {
+        let label = match outcome {
+            PaymentOutcome::Authorized(t) => format!("authorized {}", t.commit().buy_order),
+            PaymentOutcome::Rejected(_) => "rejected".into(),
+            PaymentOutcome::Aborted { buy_order, .. } => format!("aborted {}", buy_order),
+            PaymentOutcome::TimedOut { .. } => "timed out".into(),
+            PaymentOutcome::FormError { .. } => "form error".into(),
+            PaymentOutcome::CommitFailed { error: WebpayError::Mismatch(_), .. } => "mismatch".into(),
+            PaymentOutcome::CommitFailed { .. } => "commit failed".into(),
+        };
+        self.outcomes.lock().unwrap().push(label.clone());
+        label.into_response()
+    }
+}
+
+async fn spawn(fake: Arc<FakeGateway>, shop: Shop) -> String {
+    let app: Router = Router::new().nest("/webpay", router(fake, shop));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/webpay", addr)
+}
+
+fn token() -> TokenWs { TOKEN.parse().unwrap() }
+
+fn commit(buy_order: &str) -> webpay::types::CommitResponse {
+    serde_json::from_value(serde_json::json!({
+        "amount": 1000,
+        "status": "AUTHORIZED",
+        "buy_order": buy_order,
+        "session_id": "sess-1",
+        "response_code": 0
+    })).unwrap()
+}
+
+#[tokio::test]
+async fn test_pay_renders_redirect_form() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }));
+    let shop = Shop::default();
+    let base = spawn(fake.clone(), shop.clone()).await;
+    let http = reqwest::Client::new();
+
+    let html = http.post(format!("{}/pay", base)).send().await.unwrap().text().await.unwrap();
+    assert!(html.contains(r#"action="https://webpay.test/init""#));
+    assert!(html.contains(TOKEN));
+    assert_eq!(shop.orders.lock().unwrap()[&token()].buy_order, "ORDER-1");
+
+    // GET must not create transactions.
+    assert_eq!(http.get(format!("{}/pay", base)).send().await.unwrap().status(), 405);
+    assert_eq!(fake.calls().len(), 1);
+}
+
+#[tokio::test]
+async fn test_return_via_form_and_query() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_commit(Ok(commit("ORDER-1")));
+    let shop = Shop::default();
+    shop.orders.lock().unwrap().insert(token(), ExpectedOrder { buy_order: "ORDER-1".parse().unwrap(), session_id: "sess-1".parse().unwrap(), amount: 1000 });
+    let base = spawn(fake, shop.clone()).await;
+    let http = reqwest::Client::new();
+
+    let res = http.post(format!("{}/return", base)).form(&[("token_ws", TOKEN)]).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "authorized ORDER-1");
+
+    let res = http
+        .get(format!("{}/return?TBK_TOKEN={}&TBK_ORDEN_COMPRA=ORDER-2&TBK_ID_SESION=sess-2", base, TOKEN))
+        .send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "aborted ORDER-2");
+
+    let res = http.post(format!("{}/return", base)).form(&[("token_ws", "garbage")]).send().await.unwrap();
+    assert_eq!(res.status(), 400);
+
+    assert_eq!(*shop.outcomes.lock().unwrap(), ["authorized ORDER-1", "aborted ORDER-2"]);
+}
+
+#[tokio::test]
+async fn test_return_verifies_the_order() {
+    let fake = Arc::new(FakeGateway::new());
+    // The token belongs to ORDER-1, but Transbank committed it for ORDER-2.
+    fake.push_commit(Ok(commit("ORDER-2")));
+    let shop = Shop::default();
+    shop.orders.lock().unwrap().insert(token(), ExpectedOrder { buy_order: "ORDER-1".parse().unwrap(), session_id: "sess-1".parse().unwrap(), amount: 1000 });
+    let base = spawn(fake.clone(), shop.clone()).await;
+    let http = reqwest::Client::new();
+
+    let res = http.post(format!("{}/return", base)).form(&[("token_ws", TOKEN)]).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "mismatch");
+
+    // Unknown tokens are answered by the handler and never committed.
+    shop.orders.lock().unwrap().clear();
+    let res = http.post(format!("{}/return", base)).form(&[("token_ws", TOKEN)]).send().await.unwrap();
+    assert_eq!(res.status(), 404);
+    assert_eq!(fake.calls().len(), 1);
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "blocking")]
+
+use axum::{extract::Path, routing::{get, post}, Json, Router};
+use serde_json::{json, Value};
+use webpay::blocking::WebpayClient;
+use webpay::client::{ApiVersion, Credentials, Environment, Product};
+use webpay::types::{CreateRequest, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+async fn create(Json(req): Json<Value>) -> Json<Value> {
+    assert_eq!(req["buy_order"], "ORDER-1");
+    Json(json!({ "token": TOKEN, "url": "https://webpay.test/init" }))
+}
+
+async fn refund(Path(_token): Path<String>) -> (axum::http::StatusCode, String) {
+    (axum::http::StatusCode::UNPROCESSABLE_ENTITY, r#"{"error_message":"Invalid status"}"#.into())
+}
+
+// The server runs on its own runtime; the blocking client is used from the test thread,
+// outside of any runtime, as it would be in a sync program.
+fn spawn_server(rt: &tokio::runtime::Runtime) -> String {
+    rt.block_on(async {
+        let app = Router::new()
+            .route("/rswebpaytransaction/api/webpay/v1.2/transactions", post(create))
+            .route("/rswebpaytransaction/api/webpay/v1.2/transactions/:token/refunds", post(refund))
+            .route("/rswebpaytransaction/api/webpay/v1.2/transactions/:token", get(|| async { Json(json!({})) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    })
+}
+
+fn creds() -> Credentials {
+    Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() }
+}
+
+#[test]
+fn test_blocking_client() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let client = WebpayClient::new(Environment::Custom(spawn_server(&rt)), creds());
+
+    let created = client.wp_create(&CreateRequest {
+        buy_order: "ORDER-1".parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    }).expect("create");
+    assert_eq!(created.token, TOKEN);
+
+    let err = client.wp_refund(&created.token, 500).unwrap_err();
+    assert_eq!(err.status().map(|s| s.as_u16()), Some(422));
+    assert!(matches!(err, WebpayError::Status(e) if e.message().as_deref() == Some("Invalid status")));
+
+    // Same error model for bodies that don't match the response type.
+    assert!(matches!(client.wp_status(&created.token), Err(WebpayError::Decode(_))));
+
+    let v10 = client.with_api_version(Product::WebpayPlus, ApiVersion::V1_0);
+    let err = v10.wp_capture(&created.token, &"ORDER-1".parse().unwrap(), "1213", 1000).unwrap_err();
+    assert!(matches!(err, WebpayError::Unsupported { .. }));
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+use webpay::bulk::{parse_refunds, refund_results_csv, RateLimiter, RefundJournal, RefundOutcome, RefundRunner};
+use webpay::gateway::fake::{FakeGateway, GatewayCall};
+use reqwest::StatusCode;
+use webpay::types::{ApiError, RefundResponse, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+const OTHER: &str = "01ab4a0bca4cb1ad9ad3e5c3ec6ddd5d38e3f9ac4a6c3fd7d8d2ba1bd9b1b6ee";
+
+fn rejected(operation: &'static str) -> WebpayError {
+    WebpayError::Status(ApiError {
+        operation,
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+        body: r#"{"error_message":"Invalid status"}"#.into(),
+    })
+}
+
+fn refunded(balance: i64) -> RefundResponse {
+    serde_json::from_value(serde_json::json!({
+        "type": "NULLIFIED", "authorization_code": "123456", "nullified_amount": 500, "balance": balance, "response_code": 0
+    })).unwrap()
+}
+
+fn batch() -> String {
+    format!("order,token,amount\n\"ORDER-1, VIP\",{},500\nORDER-2,{},700\n", TOKEN, OTHER)
+}
+
+fn journal_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("webpay-bulk-{}-{}.journal", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_parse_refunds_validates_every_row() {
+    let rows = parse_refunds(&batch()).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!((rows[0].line, rows[0].amount), (2, 500));
+    assert_eq!(rows[1].token, OTHER);
+
+    let err = parse_refunds(&format!("token,amount\n{},0\nnot-a-token,10\n{},-5\n{},100\n{},100\n", TOKEN, OTHER, TOKEN, TOKEN)).unwrap_err();
+    let lines: Vec<_> = err.0.iter().map(|e| e.line).collect();
+    assert_eq!(lines, [2, 3, 4, 6]);
+    assert!(parse_refunds("token\n").is_err());
+}
+
+#[tokio::test]
+async fn test_rerun_skips_refunded_rows() {
+    let path = journal_path("rerun");
+    let fake = FakeGateway::new();
+    fake.push_refund(Ok(refunded(500)))
+        .push_refund(Err(rejected("refund")));
+    let runner = RefundRunner::new(fake).concurrency(1).journal(RefundJournal::open(&path).unwrap());
+
+    let results = runner.run(parse_refunds(&batch()).unwrap()).await;
+    assert!(matches!(results[0].outcome, RefundOutcome::Refunded(_)));
+    assert!(matches!(results[1].outcome, RefundOutcome::Failed(_)));
+
+    let csv = refund_results_csv(&results);
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "line,token,amount,outcome,type,authorization_code,authorization_date,nullified_amount,balance,response_code,error");
+    assert_eq!(lines[1], format!("2,{},500,refunded,NULLIFIED,123456,,500,500,0,", TOKEN));
+    assert!(lines[2].starts_with(&format!("3,{},700,failed,,,,,,,", OTHER)));
+
+    // The second run only retries the rejected row.
+    let fake = FakeGateway::new();
+    fake.push_refund(Ok(refunded(0)));
+    let runner = RefundRunner::new(fake).journal(RefundJournal::open(&path).unwrap());
+    let results = runner.run(parse_refunds(&batch()).unwrap()).await;
+    assert!(matches!(&results[0].outcome, RefundOutcome::Skipped(r) if r.balance == Some(500)));
+    assert!(matches!(results[1].outcome, RefundOutcome::Refunded(_)));
+    assert!(matches!(runner.gateway().calls().as_slice(), [GatewayCall::Refund { token_ws, amount: 700 }] if *token_ws == OTHER));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_unrecorded_refund_is_in_doubt() {
+    let path = journal_path("doubt");
+    std::fs::write(&path, format!("{{\"state\":\"started\",\"token\":\"{}\",\"amount\":500}}\n", TOKEN)).unwrap();
+    let fake = FakeGateway::new();
+    fake.push_refund(Ok(refunded(0)));
+    let runner = RefundRunner::new(fake).journal(RefundJournal::open(&path).unwrap());
+
+    let results = runner.run(parse_refunds(&batch()).unwrap()).await;
+    assert!(matches!(results[0].outcome, RefundOutcome::InDoubt));
+    assert!(matches!(results[1].outcome, RefundOutcome::Refunded(_)));
+    assert_eq!(runner.gateway().calls().len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_declined_refund_is_retried() {
+    let path = journal_path("declined");
+    let declined: RefundResponse = serde_json::from_value(serde_json::json!({ "type": "NULLIFIED", "response_code": -1 })).unwrap();
+    let fake = FakeGateway::new();
+    fake.push_refund(Ok(declined)).push_refund(Ok(refunded(0)));
+    let runner = RefundRunner::new(fake).concurrency(1).journal(RefundJournal::open(&path).unwrap());
+
+    let results = runner.run(parse_refunds(&batch()).unwrap()).await;
+    assert!(matches!(&results[0].outcome, RefundOutcome::Declined(r) if r.response_code == Some(-1)));
+    assert!(results.iter().all(|r| r.journal_error.is_none()));
+    assert!(refund_results_csv(&results).lines().nth(1).unwrap().starts_with(&format!("2,{},500,declined,NULLIFIED,", TOKEN)));
+
+    let fake = FakeGateway::new();
+    fake.push_refund(Ok(refunded(0)));
+    let runner = RefundRunner::new(fake).journal(RefundJournal::open(&path).unwrap());
+    let results = runner.run(parse_refunds(&batch()).unwrap()).await;
+    assert!(matches!(results[0].outcome, RefundOutcome::Refunded(_)));
+    assert!(matches!(results[1].outcome, RefundOutcome::Skipped(_)));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_rate_limit_spaces_calls() {
+    let limiter = RateLimiter::every(Duration::from_millis(20));
+    let start = Instant::now();
+    for _ in 0..4 {
+        limiter.acquire().await;
+    }
+    assert!(start.elapsed() >= Duration::from_millis(60));
+}
+
+mod status {
+    use axum::{extract::Path, http::StatusCode, routing::get, Router};
+    use futures_util::StreamExt;
+    use std::collections::HashMap;
+    use webpay::bulk::BulkOptions;
+    use webpay::client::{Credentials, Environment, WebpayClient};
+    use webpay::types::{TokenWs, WebpayError};
+
+    fn token(n: u8) -> TokenWs {
+        format!("{:064x}", n).parse().unwrap()
+    }
+
+    async fn status(Path(token): Path<String>) -> (StatusCode, String) {
+        match token.trim_start_matches('0') {
+            "1" => (StatusCode::OK, r#"{"amount":1000,"status":"AUTHORIZED","buy_order":"ORDER-1","session_id":"sess-1","response_code":0}"#.into()),
+            "2" => (StatusCode::UNPROCESSABLE_ENTITY, r#"{"error_message":"Transaction's date has passed max time (7 days) to recover the status"}"#.into()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "oops".into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_many_separates_expired() {
+        let app = Router::new().route("/rswebpaytransaction/api/webpay/v1.2/transactions/:token", get(status));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let creds = Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() };
+        let client = WebpayClient::new(Environment::Custom(format!("http://{}", addr)), creds);
+
+        let options = BulkOptions { concurrency: 2, per_second: Some(100) };
+        let results: HashMap<_, _> = client.wp_status_many((1..=3).map(token), options).collect().await;
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[&token(1)].as_ref().unwrap().status, "AUTHORIZED");
+        assert!(matches!(&results[&token(2)], Err(WebpayError::StatusExpired(e)) if e.status == 422));
+        assert!(matches!(&results[&token(3)], Err(WebpayError::Status(e)) if e.status == 500));
+    }
+}
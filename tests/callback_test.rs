@@ -0,0 +1,45 @@
+use webpay::callback::{InvalidReturn, ReturnParams, WebpayReturn};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+fn params(token_ws: Option<&str>, tbk_token: Option<&str>, orden: Option<&str>, sesion: Option<&str>) -> ReturnParams {
+    ReturnParams {
+        token_ws: token_ws.map(Into::into),
+        tbk_token: tbk_token.map(Into::into),
+        tbk_orden_compra: orden.map(Into::into),
+        tbk_id_sesion: sesion.map(Into::into),
+    }
+}
+
+#[test]
+fn test_classify_return() {
+    assert!(matches!(
+        params(Some(TOKEN), None, None, None).classify(),
+        Ok(WebpayReturn::Completed { .. })
+    ));
+    assert!(matches!(
+        params(None, Some(TOKEN), Some("ORDER-1"), Some("sess-1")).classify(),
+        Ok(WebpayReturn::Aborted { buy_order, .. }) if buy_order == "ORDER-1"
+    ));
+    assert!(matches!(
+        params(None, Some(""), Some("ORDER-1"), Some("sess-1")).classify(),
+        Ok(WebpayReturn::TimedOut { .. })
+    ));
+    assert!(matches!(
+        params(Some(TOKEN), Some(TOKEN), Some("ORDER-1"), Some("sess-1")).classify(),
+        Ok(WebpayReturn::FormError { buy_order: Some(_), .. })
+    ));
+    assert!(matches!(params(None, None, None, None).classify(), Err(InvalidReturn::Missing)));
+    assert!(matches!(params(Some("1234"), None, None, None).classify(), Err(InvalidReturn::InvalidId(_))));
+
+    // A malformed echo only matters where it is used.
+    let too_long = "X".repeat(30);
+    assert!(matches!(
+        params(Some(TOKEN), None, Some(&too_long), Some("sess-1")).classify(),
+        Ok(WebpayReturn::Completed { .. })
+    ));
+    assert!(matches!(
+        params(None, None, Some(&too_long), Some("sess-1")).classify(),
+        Err(InvalidReturn::InvalidId(_))
+    ));
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "testing")]
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde_json::json;
+use webpay::cassette::{Cassette, Recorder, Replayer};
+use webpay::client::{Credentials, Environment, WebpayClient};
+use webpay::transport::Transport;
+use webpay::types::{CreateRequest, TokenWs, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+const API_KEY: &str = "579B532A7440BB0C9079DED94D31EA1615BACEB56610332264630D42D0A36B1C";
+
+// Answers like the integration environment would, with a real-looking token.
+struct FakeTransbank;
+
+#[async_trait]
+impl Transport for FakeTransbank {
+    async fn send(&self, req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        let body = if req.method() == http::Method::POST {
+            json!({ "token": TOKEN, "url": "https://webpay3gint.transbank.cl/webpayserver/initTransaction" })
+        } else {
+            json!({ "amount": 1000, "status": "INITIALIZED", "buy_order": "ORDER-1", "session_id": "sess-1" })
+        };
+        Ok(http::Response::new(Bytes::from(body.to_string())))
+    }
+}
+
+fn client() -> WebpayClient {
+    WebpayClient::new(Environment::Integration, Credentials { commerce_code: "597055555532".into(), api_key: API_KEY.into() })
+}
+
+fn create_request() -> CreateRequest {
+    CreateRequest {
+        buy_order: "ORDER-1".parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    }
+}
+
+async fn record() -> Cassette {
+    let recorder = Recorder::new(FakeTransbank);
+    let client = client().with_transport(recorder.clone());
+    let created = client.wp_create(&create_request()).await.unwrap();
+    assert_eq!(created.token.as_str(), TOKEN, "the caller sees the real token");
+    client.wp_status(&created.token).await.unwrap();
+    recorder.cassette()
+}
+
+#[tokio::test]
+async fn test_recording_scrubs_secrets_and_tokens() {
+    let cassette = record().await;
+    assert_eq!(cassette.interactions.len(), 2);
+
+    let saved = serde_json::to_string(&cassette).unwrap();
+    assert!(!saved.contains(API_KEY));
+    assert!(!saved.contains(TOKEN));
+
+    let placeholder = format!("{:064x}", 1);
+    let [create, status] = &cassette.interactions[..] else { unreachable!() };
+    assert_eq!(create.request.method, "POST");
+    assert_eq!(create.request.path, "/rswebpaytransaction/api/webpay/v1.2/transactions");
+    assert_eq!(create.request.body["buy_order"], "ORDER-1");
+    assert_eq!(create.response.body["token"], placeholder.as_str());
+    assert_eq!(status.request.method, "GET");
+    assert_eq!(status.request.path, format!("/rswebpaytransaction/api/webpay/v1.2/transactions/{}", placeholder));
+    assert!(status.request.body.is_null());
+}
+
+#[tokio::test]
+async fn test_replay_serves_recorded_flow() {
+    let dir = std::env::temp_dir().join(format!("webpay-cassette-{}", std::process::id()));
+    let path = dir.join("cassettes/flow.json");
+    record().await.save(&path).unwrap();
+
+    let replayer = std::sync::Arc::new(Replayer::load(&path).unwrap());
+    let client = client().with_transport(replayer.clone());
+    let created = client.wp_create(&create_request()).await.unwrap();
+    let status = client.wp_status(&created.token).await.unwrap();
+    assert_eq!(status.status, "INITIALIZED");
+    assert_eq!(replayer.remaining(), 0);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn test_replay_rejects_unrecorded_requests() {
+    let client = client().with_transport(Replayer::new(record().await));
+
+    let mut other = create_request();
+    other.amount = 2000;
+    assert!(matches!(client.wp_create(&other).await, Err(WebpayError::Transport(_))));
+
+    // Each interaction is served once.
+    let token: TokenWs = format!("{:064x}", 1).parse().unwrap();
+    client.wp_status(&token).await.unwrap();
+    assert!(matches!(client.wp_status(&token).await, Err(WebpayError::Transport(_))));
+}
@@ -0,0 +1,136 @@
+#![cfg(feature = "cli")]
+
+use axum::{extract::Path, http::StatusCode, routing::{get, post}, Json, Router};
+use serde_json::{json, Value};
+use std::process::{Command, Output};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+const OTHER: &str = "01ab4a0bca4cb1ad9ad3e5c3ec6ddd5d38e3f9ac4a6c3fd7d8d2ba1bd9b1b6ee";
+const DECLINED: &str = "7d3f2e1c0b9a8f7e6d5c4b3a29181716151413121110f0e0d0c0b0a090807060";
+
+fn spawn_server(rt: &tokio::runtime::Runtime) -> String {
+    rt.block_on(async {
+        let app = Router::new()
+            .route("/rswebpaytransaction/api/webpay/v1.2/transactions/:token", get(|| async {
+                Json(json!({
+                    "amount": 1000, "status": "AUTHORIZED", "buy_order": "ORDER-1", "session_id": "sess-1",
+                    "card_detail": { "card_number": "6623" }, "response_code": 0
+                }))
+            }))
+            .route("/rswebpaytransaction/api/webpay/v1.2/transactions/:token/refunds", post(|Path(token): Path<String>| async move {
+                match token.as_str() {
+                    TOKEN => (StatusCode::OK, r#"{"type":"NULLIFIED","nullified_amount":500,"balance":500,"response_code":0}"#),
+                    DECLINED => (StatusCode::OK, r#"{"type":"NULLIFIED","balance":1000,"response_code":-1}"#),
+                    _ => (StatusCode::UNPROCESSABLE_ENTITY, r#"{"error_message":"Invalid status"}"#),
+                }
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    })
+}
+
+fn webpay(base_url: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_webpay"))
+        .args(args)
+        .env("WEBPAY_ENV", base_url)
+        .env("WEBPAY_COMMERCE_CODE", "597055555532")
+        .env("WEBPAY_API_KEY", "secret")
+        .env_remove("WEBPAY_PROFILE")
+        .env_remove("WEBPAY_PROFILE_FILE")
+        .env("HOME", std::env::temp_dir().join("webpay-cli-test-home"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_status_table_and_json() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let base_url = spawn_server(&rt);
+
+    let out = webpay(&base_url, &["status", TOKEN]);
+    assert!(out.status.success());
+    let table = String::from_utf8(out.stdout).unwrap();
+    assert!(table.lines().any(|l| l.split_whitespace().eq(["status", "AUTHORIZED"])), "{}", table);
+    assert!(table.lines().any(|l| l.split_whitespace().eq(["card_detail.card_number", "6623"])), "{}", table);
+
+    let out = webpay(&base_url, &["status", TOKEN, "--json"]);
+    let status: Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(status["buy_order"], "ORDER-1");
+}
+
+#[test]
+fn test_errors_have_exit_codes() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let base_url = spawn_server(&rt);
+
+    let out = webpay(&base_url, &["refund", OTHER, "500", "--json"]);
+    assert_eq!(out.status.code(), Some(4));
+    let error: Value = serde_json::from_slice(&out.stderr).unwrap();
+    assert_eq!(error["error"]["status"], 422);
+    assert_eq!(error["error"]["error_message"], "Invalid status");
+
+    // A declined refund is still printed, but fails like a rejected one.
+    let out = webpay(&base_url, &["refund", DECLINED, "500", "--json"]);
+    assert_eq!(out.status.code(), Some(4));
+    let response: Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(response["response_code"], -1);
+    let error: Value = serde_json::from_slice(&out.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "declined");
+    assert_eq!((&error["error"]["operation"], &error["error"]["response_code"]), (&json!("refund"), &json!(-1)));
+
+    assert_eq!(webpay(&base_url, &["refund", TOKEN]).status.code(), Some(2));
+    assert_eq!(webpay(&base_url, &["status", "not-a-token"]).status.code(), Some(2));
+    assert_eq!(webpay(&base_url, &["status", TOKEN, "--env", "staging"]).status.code(), Some(3));
+    assert_eq!(webpay(&base_url, &["status", TOKEN, "--profile", "ops"]).status.code(), Some(3));
+}
+
+#[test]
+fn test_profile_file() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let base_url = spawn_server(&rt);
+    let path = std::env::temp_dir().join(format!("webpay-cli-test-{}", std::process::id()));
+    std::fs::write(&path, format!("# ops credentials\n[ops]\nenv = {}\ncommerce_code = 597055555532\napi_key = secret\n", base_url)).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_webpay"))
+        .args(["status", TOKEN, "--profile", "ops", "--profile-file"])
+        .arg(&path)
+        .env_remove("WEBPAY_ENV")
+        .env_remove("WEBPAY_COMMERCE_CODE")
+        .env_remove("WEBPAY_API_KEY")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+}
+
+#[test]
+fn test_refund_batch_resumes() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let base_url = spawn_server(&rt);
+    let dir = std::env::temp_dir().join(format!("webpay-cli-batch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("refunds.csv");
+    std::fs::write(&input, format!("token,amount\n{},500\n{},700\nbad,1\n", TOKEN, OTHER)).unwrap();
+    let input = input.to_str().unwrap();
+
+    let out = webpay(&base_url, &["refund-batch", input, "--json"]);
+    assert_eq!(out.status.code(), Some(2));
+    let error: Value = serde_json::from_slice(&out.stderr).unwrap();
+    assert_eq!(error["error"]["rows"][0]["line"], 4);
+
+    std::fs::write(input, format!("token,amount\n{},500\n{},700\n", TOKEN, OTHER)).unwrap();
+    let out = webpay(&base_url, &["refund-batch", input, "--rate", "50"]);
+    assert_eq!(out.status.code(), Some(4));
+    let results = String::from_utf8(out.stdout).unwrap();
+    assert!(results.lines().nth(1).unwrap().starts_with(&format!("2,{},500,refunded,NULLIFIED", TOKEN)));
+    assert!(results.lines().nth(2).unwrap().starts_with(&format!("3,{},700,failed", OTHER)));
+
+    let output = dir.join("results.csv");
+    let out = webpay(&base_url, &["refund-batch", input, "--output", output.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(4));
+    let results = std::fs::read_to_string(&output).unwrap();
+    assert!(results.lines().nth(1).unwrap().starts_with(&format!("2,{},500,skipped", TOKEN)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use webpay::commit_guard::{is_commit_conflict, CommitCoordinator, CommitLock};
+use webpay::gateway::fake::{FakeGateway, GatewayCall};
+use webpay::gateway::PaymentGateway;
+use webpay::types::{ApiError, CommitResponse, TokenWs, WebpayError};
+
+fn token() -> TokenWs {
+    "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap()
+}
+
+fn response(status: &str) -> CommitResponse {
+    serde_json::from_value(serde_json::json!({
+        "amount": 1000,
+        "status": status,
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "response_code": 0
+    })).unwrap()
+}
+
+fn conflict(message: &str) -> WebpayError {
+    WebpayError::Status(ApiError {
+        operation: "commit",
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+        body: serde_json::json!({ "error_message": message }).to_string(),
+    })
+}
+
+fn commits(fake: &FakeGateway) -> usize {
+    fake.calls().iter().filter(|c| matches!(c, GatewayCall::Commit { .. })).count()
+}
+
+#[tokio::test]
+async fn test_concurrent_commits_share_one_call() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_commit(Ok(response("AUTHORIZED")));
+    let coordinator = CommitCoordinator::new(fake.clone());
+
+    let token = token();
+    let (a, b) = tokio::join!(coordinator.wp_commit(&token), coordinator.wp_commit(&token));
+    assert_eq!(a.expect("first").status, "AUTHORIZED");
+    assert_eq!(b.expect("second").status, "AUTHORIZED");
+    // A refresh long after the first commit gets the cached result too.
+    assert_eq!(coordinator.commit(&token).await.expect("cached").status, "AUTHORIZED");
+    assert_eq!(commits(&fake), 1);
+}
+
+#[tokio::test]
+async fn test_already_committed_resolves_via_status() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_commit(Err(conflict("Invalid status '6' for transaction while authorizing. Commerce: 597055555532")))
+        .push_status(Ok(response("AUTHORIZED")));
+    let coordinator = CommitCoordinator::new(fake.clone());
+
+    let commit = coordinator.commit(&token()).await.expect("resolved");
+    assert_eq!(commit.status, "AUTHORIZED");
+    assert!(matches!(fake.calls().last(), Some(GatewayCall::Status { .. })));
+}
+
+#[tokio::test]
+async fn test_failed_commit_is_not_cached() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_commit(Err(WebpayError::Status(ApiError { operation: "commit", status: StatusCode::INTERNAL_SERVER_ERROR, body: String::new() })))
+        .push_commit(Ok(response("FAILED")));
+    let coordinator = CommitCoordinator::new(fake.clone());
+
+    assert!(coordinator.commit(&token()).await.is_err());
+    assert_eq!(coordinator.commit(&token()).await.expect("retry").status, "FAILED");
+    assert_eq!(commits(&fake), 2);
+}
+
+struct HeldElsewhere(AtomicBool);
+
+#[async_trait]
+impl CommitLock for HeldElsewhere {
+    async fn try_acquire(&self, _token: &TokenWs, _ttl: Duration) -> Result<bool, WebpayError> {
+        Ok(!self.0.load(Ordering::SeqCst))
+    }
+
+    async fn release(&self, _token: &TokenWs) -> Result<(), WebpayError> { Ok(()) }
+}
+
+#[tokio::test]
+async fn test_lock_held_elsewhere_polls_status() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_status(Ok(response("INITIALIZED")))
+        .push_status(Ok(response("AUTHORIZED")));
+    let coordinator = CommitCoordinator::with_lock(fake.clone(), HeldElsewhere(AtomicBool::new(true)))
+        .status_polling(3, Duration::from_millis(1));
+
+    assert_eq!(coordinator.commit(&token()).await.expect("resolved").status, "AUTHORIZED");
+    assert_eq!(commits(&fake), 0);
+    assert_eq!(fake.calls().len(), 2);
+}
+
+#[test]
+fn test_is_commit_conflict() {
+    assert!(is_commit_conflict(&conflict("Transaction already locked by another process")));
+    assert!(!is_commit_conflict(&conflict("Invalid value for parameter: token")));
+    assert!(!is_commit_conflict(&WebpayError::Status(ApiError { operation: "commit", status: StatusCode::UNPROCESSABLE_ENTITY, body: String::new() })));
+}
+
+#[tokio::test]
+async fn test_lock_held_too_long_is_typed() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_status(Ok(response("INITIALIZED"))).push_status(Ok(response("INITIALIZED")));
+    let coordinator = CommitCoordinator::with_lock(fake.clone(), HeldElsewhere(AtomicBool::new(true)))
+        .status_polling(2, Duration::from_millis(1));
+
+    let err = coordinator.commit(&token()).await.unwrap_err();
+    assert!(matches!(err, WebpayError::CommitInProgress { token: t } if t == token()));
+}
+
+struct UnreleasableLock;
+
+#[async_trait]
+impl CommitLock for UnreleasableLock {
+    async fn try_acquire(&self, _token: &TokenWs, ttl: Duration) -> Result<bool, WebpayError> {
+        assert_eq!(ttl, Duration::from_secs(30));
+        Ok(true)
+    }
+
+    async fn release(&self, _token: &TokenWs) -> Result<(), WebpayError> {
+        Err(WebpayError::Transport("lock store unavailable".into()))
+    }
+}
+
+#[tokio::test]
+async fn test_release_failure_keeps_commit() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_commit(Ok(response("AUTHORIZED")));
+    let coordinator = CommitCoordinator::with_lock(fake.clone(), UnreleasableLock).lock_ttl(Duration::from_secs(30));
+
+    assert_eq!(coordinator.commit(&token()).await.expect("committed").status, "AUTHORIZED");
+}
+
+#[tokio::test]
+async fn test_cached_results_expire() {
+    let fake = Arc::new(FakeGateway::new());
+    fake.push_commit(Ok(response("AUTHORIZED")))
+        .push_commit(Err(conflict("Invalid status '2' for transaction while authorizing")))
+        .push_status(Ok(response("AUTHORIZED")));
+    let coordinator = CommitCoordinator::new(fake.clone()).cache_ttl(Duration::from_millis(10));
+
+    coordinator.commit(&token()).await.unwrap();
+    coordinator.commit(&token()).await.unwrap();
+    assert_eq!(commits(&fake), 1);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(coordinator.commit(&token()).await.expect("resolved").status, "AUTHORIZED");
+    assert_eq!(commits(&fake), 2);
+}
@@ -0,0 +1,114 @@
+use chrono::{NaiveDate, Offset, TimeZone, Utc};
+use serde_json::json;
+use webpay::dates::{accounting_date, parse_timestamp, to_santiago};
+use webpay::types::{CommitResponse, RefundResponse};
+
+fn commit(transaction_date: serde_json::Value, accounting_date: &str) -> CommitResponse {
+    serde_json::from_value(json!({
+        "amount": 1000,
+        "status": "AUTHORIZED",
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "accounting_date": accounting_date,
+        "transaction_date": transaction_date,
+        "response_code": 0
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_timestamp_formats() {
+    let expected = Utc.with_ymd_and_hms(2019, 5, 22, 16, 41, 21).unwrap() + chrono::Duration::milliseconds(63);
+    for s in [
+        "2019-05-22T16:41:21.063Z",
+        "2019-05-22T16:41:21.063+00:00",
+        "2019-05-22T12:41:21.063-04:00",
+        "2019-05-22T12:41:21.063-0400",
+        "2019-05-22T16:41:21.063",
+        "2019-05-22 16:41:21.063",
+        " 2019-05-22T16:41:21.063Z ",
+        "1558543281063",
+    ] {
+        assert_eq!(parse_timestamp(s), Some(expected), "{}", s);
+    }
+    assert_eq!(parse_timestamp("2019-05-22T16:41:21"), Some(expected - chrono::Duration::milliseconds(63)));
+    assert_eq!(parse_timestamp("2019-05-22"), Some(Utc.with_ymd_and_hms(2019, 5, 22, 0, 0, 0).unwrap()));
+    assert_eq!(parse_timestamp(""), None);
+    assert_eq!(parse_timestamp("22/05/2019"), None);
+}
+
+#[test]
+fn test_lenient_response_fields() {
+    let without_offset = commit(json!("2019-05-22T16:41:21.063"), "0522");
+    assert_eq!(without_offset.transaction_date, parse_timestamp("2019-05-22T16:41:21.063Z"));
+
+    let epoch = commit(json!(1558543281), "0522");
+    assert_eq!(epoch.transaction_date, parse_timestamp("2019-05-22T16:41:21Z"));
+
+    // An unknown format doesn't fail the commit.
+    let unknown = commit(json!("22/05/2019 16:41"), "0522");
+    assert_eq!(unknown.transaction_date, None);
+    assert_eq!(unknown.status, "AUTHORIZED");
+    // ... and the original is kept, and serialized back, as sent.
+    assert_eq!(unknown.extra.get("transaction_date"), Some(&json!("22/05/2019 16:41")));
+    assert_eq!(serde_json::to_value(&unknown).unwrap()["transaction_date"], "22/05/2019 16:41");
+    assert_eq!(unknown.accounting_date_naive(), None, "no transaction date to take the year from");
+
+    let refund: RefundResponse = serde_json::from_value(json!({
+        "type": "NULLIFIED",
+        "authorization_date": "2019-05-22 16:41:21",
+        "response_code": 0
+    }))
+    .unwrap();
+    assert_eq!(refund.authorization_date, parse_timestamp("2019-05-22T16:41:21Z"));
+
+    // Missing dates are still allowed.
+    let refund: RefundResponse = serde_json::from_value(json!({ "type": "REVERSED" })).unwrap();
+    assert_eq!(refund.authorization_date, None);
+    assert!(refund.extra.is_empty());
+}
+
+#[test]
+fn test_accounting_date_year() {
+    let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d);
+    assert_eq!(commit(json!("2019-05-22T16:41:21.063Z"), "0522").accounting_date_naive(), date(2019, 5, 22));
+    assert_eq!(commit(json!("2019-05-24T16:41:21.063Z"), "0527").accounting_date_naive(), date(2019, 5, 27));
+
+    // 2024-01-01T02:00Z is still December 31st in Santiago.
+    assert_eq!(commit(json!("2024-01-01T02:00:00Z"), "1231").accounting_date_naive(), date(2023, 12, 31));
+    // Paid on New Year's Eve, accounted in January.
+    assert_eq!(commit(json!("2023-12-31T20:00:00Z"), "0102").accounting_date_naive(), date(2024, 1, 2));
+
+    assert_eq!(accounting_date("0229", date(2024, 3, 1).unwrap()), date(2024, 2, 29));
+    assert_eq!(accounting_date("0229", date(2023, 3, 1).unwrap()), date(2024, 2, 29));
+    assert_eq!(accounting_date("1332", date(2024, 3, 1).unwrap()), None);
+    assert_eq!(accounting_date("522", date(2024, 3, 1).unwrap()), None);
+}
+
+#[test]
+fn test_santiago_time() {
+    let offset = |y, m, d, h, min| to_santiago(Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()).offset().fix().local_minus_utc() / 3600;
+
+    // 2024: summer time until Sunday April 7 at 03:00 UTC, and again from Sunday
+    // September 8 at 04:00 UTC.
+    assert_eq!(offset(2024, 1, 15, 12, 0), -3);
+    assert_eq!(offset(2024, 4, 7, 2, 59), -3);
+    assert_eq!(offset(2024, 4, 7, 3, 0), -4);
+    assert_eq!(offset(2024, 7, 1, 12, 0), -4);
+    assert_eq!(offset(2024, 9, 8, 3, 59), -4);
+    assert_eq!(offset(2024, 9, 8, 4, 0), -3);
+
+    // 2022 started a week late, and 2016-2018 followed other dates.
+    assert_eq!(offset(2022, 9, 5, 12, 0), -4);
+    assert_eq!(offset(2022, 9, 11, 4, 0), -3);
+    assert_eq!(offset(2018, 4, 20, 12, 0), -3);
+    assert_eq!(offset(2018, 5, 13, 3, 0), -4);
+    assert_eq!(offset(2018, 8, 12, 4, 0), -3);
+
+    // 2015 stayed on summer time all year.
+    assert_eq!(offset(2015, 7, 1, 12, 0), -3);
+
+    let receipt = commit(json!("2024-07-01T16:30:00Z"), "0701").transaction_date_santiago().unwrap();
+    assert_eq!(receipt.format("%d/%m/%Y %H:%M").to_string(), "01/07/2024 12:30");
+    assert_eq!(to_santiago(Utc.with_ymd_and_hms(2024, 1, 1, 2, 0, 0).unwrap()).date_naive(), NaiveDate::from_ymd_opt(2023, 12, 31).unwrap());
+}
@@ -0,0 +1,145 @@
+use webpay::callback::{PaymentOutcome, ReturnParams};
+use webpay::events::{AbortReason, BroadcastSink, EventEnvelope, EventGateway, EventSink, PaymentEvent, SinkError};
+use webpay::gateway::fake::FakeGateway;
+use webpay::gateway::PaymentGateway;
+use webpay::types::{CreateRequest, CreateResponse, TokenWs};
+
+fn token() -> TokenWs {
+    "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap()
+}
+
+fn request() -> CreateRequest {
+    CreateRequest {
+        buy_order: "ORDER-1".parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    }
+}
+
+fn json<T: serde::de::DeserializeOwned>(v: serde_json::Value) -> T {
+    serde_json::from_value(v).unwrap()
+}
+
+#[tokio::test]
+async fn test_lifecycle_is_broadcast() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(json(serde_json::json!({
+            "amount": 1000, "status": "AUTHORIZED", "buy_order": "ORDER-1", "session_id": "sess-1", "response_code": 0
+        }))))
+        .push_status(Ok(json(serde_json::json!({
+            "amount": 1000, "status": "AUTHORIZED", "buy_order": "ORDER-1", "session_id": "sess-1", "response_code": 0
+        }))))
+        .push_refund(Ok(json(serde_json::json!({ "type": "NULLIFIED", "balance": 600, "response_code": 0 }))));
+    let sink = BroadcastSink::new(16);
+    let mut rx = sink.subscribe();
+    let gateway = EventGateway::new(fake, sink);
+
+    gateway.wp_create(&request()).await.unwrap();
+    let params = ReturnParams { token_ws: Some(token().to_string()), ..Default::default() };
+    assert!(matches!(gateway.process_return(params.classify().unwrap()).await, PaymentOutcome::Authorized(_)));
+    gateway.wp_status(&token()).await.unwrap();
+    gateway.wp_refund(&token(), 400).await.unwrap();
+
+    let types: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).map(|e| e.event.event_type()).collect();
+    assert_eq!(types, ["transaction_created", "transaction_authorized", "refund_issued"]);
+}
+
+#[tokio::test]
+async fn test_timeout_emits_aborted() {
+    let sink = BroadcastSink::new(4);
+    let mut rx = sink.subscribe();
+    let gateway = EventGateway::new(FakeGateway::new(), sink);
+    let params = ReturnParams {
+        tbk_orden_compra: Some("ORDER-1".into()),
+        tbk_id_sesion: Some("sess-1".into()),
+        ..Default::default()
+    };
+
+    assert!(matches!(gateway.process_return(params.classify().unwrap()).await, PaymentOutcome::TimedOut { .. }));
+    let envelope = rx.try_recv().unwrap();
+    assert!(matches!(
+        &envelope.event,
+        PaymentEvent::TransactionAborted { reason: AbortReason::TimedOut, token: None, buy_order: Some(b), .. } if *b == "ORDER-1"
+    ));
+    assert!(gateway.gateway().calls().is_empty());
+
+    let outbox = envelope.to_outbox().unwrap();
+    assert_eq!(outbox.aggregate_id, "ORDER-1");
+    assert_eq!(outbox.event_type, "transaction_aborted");
+    assert_eq!(outbox.id, "ORDER-1:transaction_aborted:timed_out");
+    assert_eq!(EventEnvelope::now(envelope.event.clone()).to_outbox().unwrap().id, outbox.id, "re-emitting keeps the id");
+    let payload: serde_json::Value = serde_json::from_str(&outbox.payload).unwrap();
+    assert_eq!(payload["event"], "transaction_aborted");
+    assert_eq!(payload["reason"], "timed_out");
+    let back: EventEnvelope = serde_json::from_str(&outbox.payload).unwrap();
+    assert_eq!(back.at, envelope.at);
+}
+
+struct Failing;
+
+#[async_trait::async_trait]
+impl EventSink for Failing {
+    async fn emit(&self, _event: EventEnvelope) -> Result<(), SinkError> {
+        Err("outbox unavailable".into())
+    }
+}
+
+#[tokio::test]
+async fn test_sink_failure_keeps_response() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }));
+    let gateway = EventGateway::new(fake, Failing);
+
+    assert_eq!(gateway.wp_create(&request()).await.expect("created").token, token());
+    let undelivered = gateway.take_undelivered();
+    assert_eq!(undelivered.len(), 1);
+    assert_eq!(undelivered[0].event.event_type(), "transaction_created");
+    assert!(gateway.take_undelivered().is_empty());
+}
+
+#[tokio::test]
+async fn test_declined_refunds_and_captures() {
+    let fake = FakeGateway::new();
+    fake.push_refund(Ok(json(serde_json::json!({ "type": "NULLIFIED", "response_code": -1 }))))
+        .push_refund(Ok(json(serde_json::json!({ "type": "REVERSED" }))))
+        .push_capture(Ok(json(serde_json::json!({ "authorization_code": "1213", "captured_amount": 0, "response_code": -1 }))));
+    let sink = BroadcastSink::new(8);
+    let mut rx = sink.subscribe();
+    let gateway = EventGateway::new(fake, sink);
+
+    gateway.wp_refund(&token(), 400).await.unwrap();
+    gateway.wp_refund(&token(), 1000).await.unwrap();
+    gateway.wp_capture(&token(), &"ORDER-1".parse().unwrap(), "1213", 1000).await.unwrap();
+
+    let events: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+    let types: Vec<_> = events.iter().map(|e| e.event.event_type()).collect();
+    assert_eq!(types, ["refund_declined", "refund_issued", "capture_declined"]);
+    let attempt = |e: &webpay::events::EventEnvelope| e.at.format("%Y%m%dT%H%M%S%.fZ").to_string();
+    assert_eq!(events[0].to_outbox().unwrap().id, format!("{}:refund_declined:400:{}:", token(), attempt(&events[0])));
+    // A reversal has no authorization code or date either.
+    assert_eq!(events[1].to_outbox().unwrap().id, format!("{}:refund_issued:1000:{}:", token(), attempt(&events[1])));
+    // The echoed authorization code is the sale's, not the attempt's.
+    assert_eq!(events[2].to_outbox().unwrap().id, format!("{}:capture_declined:1000:{}", token(), attempt(&events[2])));
+}
+
+#[tokio::test]
+async fn test_repeated_declines_get_distinct_ids() {
+    let fake = FakeGateway::new();
+    let declined = serde_json::json!({ "type": "NULLIFIED", "balance": 1000, "response_code": -1 });
+    fake.push_refund(Ok(json(declined.clone()))).push_refund(Ok(json(declined)));
+    let sink = BroadcastSink::new(8);
+    let mut rx = sink.subscribe();
+    let gateway = EventGateway::new(fake, sink);
+
+    gateway.wp_refund(&token(), 400).await.unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    gateway.wp_refund(&token(), 400).await.unwrap();
+
+    let events: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+    let ids: Vec<_> = events.iter().map(|e| e.to_outbox().unwrap().id).collect();
+    assert_ne!(ids[0], ids[1]);
+    // Redelivering the same envelope keeps its id.
+    assert_eq!(events[0].to_outbox().unwrap().id, ids[0]);
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "testing")]
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use webpay::client::{Credentials, Environment, WebpayClient};
+use webpay::fault::{Fault, FaultyTransport, Schedule};
+use webpay::transport::Transport;
+use webpay::types::{TokenWs, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+// Answers every request with an INITIALIZED status and counts the requests it receives.
+#[derive(Default)]
+struct Healthy {
+    received: AtomicUsize,
+}
+
+#[async_trait]
+impl Transport for Healthy {
+    async fn send(&self, _req: http::Request<Bytes>) -> Result<http::Response<Bytes>, WebpayError> {
+        self.received.fetch_add(1, Ordering::SeqCst);
+        let body = json!({ "amount": 1000, "status": "INITIALIZED", "buy_order": "ORDER-1", "session_id": "sess-1" });
+        Ok(http::Response::new(Bytes::from(body.to_string())))
+    }
+}
+
+fn client(transport: std::sync::Arc<FaultyTransport<Healthy>>) -> WebpayClient {
+    let creds = Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() };
+    WebpayClient::new(Environment::Integration, creds).with_transport(transport)
+}
+
+fn token() -> TokenWs { TOKEN.parse().unwrap() }
+
+fn io_kind(e: &WebpayError) -> Option<std::io::ErrorKind> {
+    match e {
+        WebpayError::Transport(e) => e.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_server_error_burst() {
+    let transport = std::sync::Arc::new(FaultyTransport::new(Healthy::default()).inject(Fault::ServerError(503), Schedule::burst(1, 2)));
+    let client = client(transport.clone());
+
+    assert!(client.wp_status(&token()).await.is_ok());
+    for _ in 0..2 {
+        let err = client.wp_status(&token()).await.unwrap_err();
+        assert_eq!(err.status().map(|s| s.as_u16()), Some(503));
+    }
+    assert!(client.wp_status(&token()).await.is_ok());
+
+    assert_eq!(transport.inner().received.load(Ordering::SeqCst), 2, "5xx answers never reach the server");
+    assert_eq!(transport.requests(), 4);
+    assert_eq!(transport.injected().iter().map(|i| i.request).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[tokio::test]
+async fn test_connection_reset_and_timeout_after_send() {
+    let transport = std::sync::Arc::new(
+        FaultyTransport::new(Healthy::default())
+            .inject(Fault::ConnectionReset, Schedule::nth(0))
+            .inject(Fault::TimeoutAfterSend, Schedule::nth(0).method(http::Method::PUT)),
+    );
+    let client = client(transport.clone());
+
+    let reset = client.wp_status(&token()).await.unwrap_err();
+    assert_eq!(io_kind(&reset), Some(std::io::ErrorKind::ConnectionReset));
+    assert_eq!(transport.inner().received.load(Ordering::SeqCst), 0);
+
+    // The ambiguous commit: Transbank got the request, the caller got an error.
+    let timeout = client.wp_commit(&token()).await.unwrap_err();
+    assert_eq!(io_kind(&timeout), Some(std::io::ErrorKind::TimedOut));
+    assert_eq!(transport.inner().received.load(Ordering::SeqCst), 1);
+
+    assert!(client.wp_commit(&token()).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_corrupted_bodies() {
+    let transport = std::sync::Arc::new(
+        FaultyTransport::new(Healthy::default())
+            .inject(Fault::MalformedJson, Schedule::nth(0))
+            .inject(Fault::TruncatedBody, Schedule::nth(1))
+            .inject(Fault::ServerError(500), Schedule::every(2).path("/refunds")),
+    );
+    let client = client(transport.clone());
+
+    // The undecodable body is kept, exactly as received.
+    match client.wp_status(&token()).await {
+        Err(WebpayError::Decode(e)) => {
+            assert_eq!((e.operation, e.response.status.as_u16()), ("status", 200));
+            assert_eq!(&e.response.body[..], b"{\"token\": \"");
+        }
+        other => panic!("expected a decode error, got {:?}", other),
+    }
+    assert!(matches!(client.wp_status(&token()).await, Err(WebpayError::Decode(_))));
+    assert!(client.wp_status(&token()).await.is_ok());
+
+    // Only refunds count towards the refund schedule: the second one fails.
+    assert!(client.wp_refund(&token(), 100).await.is_ok());
+    assert_eq!(client.wp_refund(&token(), 100).await.unwrap_err().status().map(|s| s.as_u16()), Some(500));
+}
+
+#[tokio::test]
+async fn test_latency() {
+    let transport = std::sync::Arc::new(
+        FaultyTransport::new(Healthy::default())
+            .inject(Fault::Latency(Duration::from_millis(100)), Schedule::always())
+            .inject(Fault::ConnectionReset, Schedule::nth(1)),
+    );
+    let client = client(transport.clone());
+
+    let started = Instant::now();
+    assert!(client.wp_status(&token()).await.is_ok());
+    assert!(client.wp_status(&token()).await.is_err());
+    assert!(started.elapsed() >= Duration::from_millis(200), "latency applies alongside other faults");
+    assert_eq!(transport.injected().len(), 3);
+}
@@ -0,0 +1,77 @@
+use webpay::gateway::fake::{FakeGateway, GatewayCall};
+use webpay::gateway::PaymentGateway;
+use reqwest::StatusCode;
+use webpay::types::{ApiError, CommitResponse, CreateRequest, CreateResponse, RefundResponse, TokenWs, WebpayError};
+use webpay::webpay_plus::is_authorized;
+
+// A minimal order service, written against the trait the way application code would be.
+async fn checkout<G: PaymentGateway>(gateway: &G, order: &str, amount: i64) -> Result<String, WebpayError> {
+    let created = gateway.wp_create(&CreateRequest {
+        buy_order: order.parse()?,
+        session_id: "sess-1".parse()?,
+        amount,
+        return_url: "http://localhost:3000/return".into(),
+    }).await?;
+    Ok(format!("{}?token_ws={}", created.url, created.token))
+}
+
+async fn on_return<G: PaymentGateway>(gateway: &G, token_ws: &TokenWs) -> Result<bool, WebpayError> {
+    let commit = gateway.wp_commit(token_ws).await?;
+    if is_authorized(&commit) && commit.amount > 500 {
+        gateway.wp_refund(token_ws, commit.amount - 500).await?;
+    }
+    Ok(is_authorized(&commit))
+}
+
+fn token() -> TokenWs {
+    "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap()
+}
+
+fn authorized(amount: i64) -> CommitResponse {
+    serde_json::from_value(serde_json::json!({
+        "amount": amount,
+        "status": "AUTHORIZED",
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "response_code": 0
+    })).unwrap()
+}
+
+#[tokio::test]
+async fn test_scripted_responses_and_call_recording() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(authorized(1000)))
+        .push_refund(Ok(serde_json::from_value::<RefundResponse>(serde_json::json!({
+            "type": "NULLIFIED",
+            "nullified_amount": 500,
+            "response_code": 0
+        })).unwrap()));
+
+    let redirect = checkout(&fake, "ORDER-1", 1000).await.expect("checkout");
+    assert_eq!(redirect, format!("https://webpay.test/init?token_ws={}", token()));
+    assert!(on_return(&fake, &token()).await.expect("return"));
+
+    let calls = fake.calls();
+    assert_eq!(calls.len(), 3);
+    assert!(matches!(&calls[0], GatewayCall::Create(req) if req.buy_order == "ORDER-1" && req.amount == 1000));
+    assert!(matches!(&calls[1], GatewayCall::Commit { token_ws } if *token_ws == token()));
+    assert!(matches!(&calls[2], GatewayCall::Refund { token_ws, amount: 500 } if *token_ws == token()));
+}
+
+#[tokio::test]
+async fn test_unscripted_call_fails() {
+    let fake = FakeGateway::new();
+    fake.push_commit(Err(WebpayError::Status(ApiError {
+        operation: "commit",
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+        body: r#"{"error_message":"Invalid status"}"#.into(),
+    })));
+
+    assert!(on_return(&fake, &token()).await.is_err());
+    // Nothing left in the script.
+    let err = fake.wp_status(&token()).await.unwrap_err();
+    assert_eq!(err.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    assert!(matches!(err, WebpayError::Status(ApiError { operation: "status", .. })));
+    assert_eq!(fake.calls().len(), 2);
+}
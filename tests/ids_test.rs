@@ -0,0 +1,67 @@
+use webpay::types::{BuyOrder, CommitResponse, CreateRequest, InvalidId, SessionId, TokenWs};
+
+const TOKEN: &str = "01ab4a0bca4cb1ad9ad3e5c3ec6ddd5d38e3f9ac4a6c3fd7d8d2ba1bd9b1b6ee";
+
+#[test]
+fn test_token_validation() {
+    let token: TokenWs = TOKEN.parse().expect("valid token");
+    assert_eq!(token.to_string(), TOKEN);
+    assert_eq!(format!("{:?}", token), "TokenWs(\"01ab4a…\")");
+
+    assert!(TokenWs::new(&TOKEN[1..]).is_err());
+    assert_eq!(
+        TokenWs::new(TOKEN.replace('a', "z")).unwrap_err(),
+        InvalidId { field: "token_ws", reason: "must only contain hexadecimal characters" }
+    );
+}
+
+#[test]
+fn test_buy_order_validation() {
+    assert!(BuyOrder::new("ORDER-123_a|b=c&d%e.f,g~h:").is_ok());
+    assert!(BuyOrder::new("a".repeat(26)).is_ok());
+    assert!(BuyOrder::new("a".repeat(27)).is_err());
+    assert!(BuyOrder::new("").is_err());
+    assert!(BuyOrder::new("ORDER 123").is_err());
+    assert!(BuyOrder::new("ORDEN-Ñ").is_err());
+}
+
+#[test]
+fn test_session_id_validation() {
+    assert!(SessionId::new("s".repeat(61)).is_ok());
+    assert!(SessionId::new("s".repeat(62)).is_err());
+    assert!(SessionId::new("").is_err());
+}
+
+#[test]
+fn test_serde_validates() {
+    let req = CreateRequest {
+        buy_order: "ORDER-1".parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    };
+    let json = serde_json::to_value(&req).unwrap();
+    assert_eq!(json["buy_order"], "ORDER-1");
+    assert_eq!(json["session_id"], "sess-1");
+
+    assert!(serde_json::from_str::<TokenWs>(&format!("\"{}\"", TOKEN)).is_ok());
+    assert!(serde_json::from_str::<TokenWs>("\"not-a-token\"").is_err());
+}
+
+#[test]
+fn test_responses_accept_any_echo() {
+    let commit: CommitResponse = serde_json::from_value(serde_json::json!({
+        "amount": 1000,
+        "status": "AUTHORIZED",
+        "buy_order": "ORDEN-Ñ-CON-MÁS-DE-26-CARACTERES",
+        "session_id": "",
+        "response_code": 0
+    }))
+    .expect("an authorized commit decodes whatever Transbank echoes");
+    assert_eq!(commit.buy_order, "ORDEN-Ñ-CON-MÁS-DE-26-CARACTERES");
+    assert_eq!(commit.session_id, "");
+
+    // Requests stay strict.
+    let request = serde_json::json!({ "buy_order": "ORDEN-Ñ", "session_id": "s", "amount": 1, "return_url": "http://x" });
+    assert!(serde_json::from_value::<CreateRequest>(request).is_err());
+}
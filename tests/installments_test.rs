@@ -0,0 +1,104 @@
+use serde_json::json;
+use webpay::installments::{format_clp, InstallmentPlan, InstallmentsError, PaymentType};
+use webpay::types::CommitResponse;
+
+fn commit(payment_type_code: &str, amount: i64, installments_number: Option<i32>, installments_amount: Option<i64>) -> CommitResponse {
+    serde_json::from_value(json!({
+        "amount": amount,
+        "status": "AUTHORIZED",
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "payment_type_code": payment_type_code,
+        "response_code": 0,
+        "installments_number": installments_number,
+        "installments_amount": installments_amount
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_payment_types() {
+    for code in ["VD", "VN", "VP", "VC", "SI", "S2", "NC"] {
+        assert_eq!(code.parse::<PaymentType>().unwrap().code(), code);
+    }
+    assert!(matches!("XX".parse::<PaymentType>(), Err(InstallmentsError::UnknownPaymentType(_))));
+    assert!(!PaymentType::Normal.is_installments());
+    assert!(PaymentType::Installments.is_installments() && !PaymentType::Installments.is_interest_free());
+    assert!(PaymentType::InterestFree.is_interest_free());
+}
+
+#[test]
+fn test_committed_plans() {
+    assert_eq!(commit("VN", 10_000, Some(0), None).installments(), None);
+    assert_eq!(commit("VD", 10_000, None, None).installments(), None);
+
+    let si = commit("SI", 10_000, Some(3), Some(3_334)).installments().unwrap();
+    assert_eq!((si.payment_type, si.count, si.interest_free, si.total), (PaymentType::ThreeInterestFree, 3, true, 10_000));
+    assert_eq!((si.installment_amount, si.last_installment_amount), (3_334, 3_332));
+
+    // The count of S2 is implied, and a missing amount is computed.
+    let s2 = commit("S2", 9_999, Some(0), None).installments().unwrap();
+    assert_eq!((s2.count, s2.installment_amount, s2.last_installment_amount), (2, 4_999, 5_000));
+    // Reported amounts may be rounded down too.
+    let si = commit("SI", 10_000, Some(3), Some(3_333)).installments().unwrap();
+    assert_eq!((si.installment_amount, si.last_installment_amount), (3_333, 3_334));
+
+    // A reported amount that doesn't add up is replaced by the computed split.
+    let odd = commit("NC", 10_000, Some(6), Some(3_000)).installments().unwrap();
+    assert_eq!((odd.installment_amount, odd.last_installment_amount), (1_666, 1_670));
+    let odd = commit("NC", 10_000, Some(6), Some(1_000)).installments().unwrap();
+    assert_eq!((odd.installment_amount, odd.last_installment_amount), (1_666, 1_670));
+    let odd = commit("NC", 10_000, Some(6), Some(i64::MAX)).installments().unwrap();
+    assert_eq!((odd.installment_amount, odd.last_installment_amount), (1_666, 1_670));
+    assert_eq!(commit("VC", 100_000, Some(12), Some(i64::MAX)).installments(), None);
+
+    let nc = commit("NC", 60_000, Some(6), Some(10_000)).installments().unwrap();
+    assert_eq!((nc.count, nc.total), (6, 60_000));
+    assert_eq!(nc.to_string(), "6 cuotas sin interés de $10.000");
+
+    let vc = commit("VC", 100_000, Some(12), Some(9_100)).installments().unwrap();
+    assert_eq!((vc.interest_free, vc.total), (false, 109_200));
+    assert_eq!(vc.to_string(), "12 cuotas de $9.100 (total $109.200)");
+    assert_eq!(commit("VC", 100_000, Some(12), None).installments(), None, "interest is up to the issuer");
+}
+
+#[test]
+fn test_simulation() {
+    let plan = InstallmentPlan::interest_free(10_000, 3).unwrap();
+    assert_eq!(plan.payment_type, PaymentType::ThreeInterestFree);
+    assert_eq!(plan.installment_amount * 2 + plan.last_installment_amount, plan.total);
+    assert_eq!((plan.installment_amount, plan.last_installment_amount), (3_333, 3_334));
+    assert_eq!(plan.to_string(), "3 cuotas sin interés de $3.333");
+    assert_eq!(InstallmentPlan::interest_free(10_000, 2).unwrap().payment_type, PaymentType::TwoInterestFree);
+    assert_eq!(InstallmentPlan::interest_free(10_000, 6).unwrap().payment_type, PaymentType::InterestFree);
+
+    // 100.000 at 2% monthly over 12 months: 9.456 a month.
+    let vc = InstallmentPlan::with_interest(100_000, 12, 0.02).unwrap();
+    assert_eq!((vc.installment_amount, vc.total), (9_456, 113_472));
+    let zero = InstallmentPlan::with_interest(100_000, 4, 0.0).unwrap();
+    assert_eq!((zero.installment_amount, zero.total, zero.interest_free), (25_000, 100_000, false));
+
+    assert_eq!(InstallmentPlan::interest_free(0, 3), Err(InstallmentsError::InvalidAmount(0)));
+    assert!(matches!(InstallmentPlan::interest_free(10_000, 1), Err(InstallmentsError::InvalidCount { count: 1, .. })));
+    assert!(matches!(InstallmentPlan::with_interest(10_000, 49, 0.01), Err(InstallmentsError::InvalidCount { .. })));
+    assert!(matches!(InstallmentPlan::with_interest(10_000, 3, -0.01), Err(InstallmentsError::InvalidRate(_))));
+    assert!(matches!(InstallmentPlan::with_interest(i64::MAX, 48, 0.05), Err(InstallmentsError::Overflow { .. })));
+    assert!(matches!(InstallmentPlan::with_interest(i64::MAX / 2, 3, 1.0), Err(InstallmentsError::Overflow { .. })));
+    let tiny = InstallmentPlan::with_interest(100_000, 4, 1e-300).unwrap();
+    assert_eq!((tiny.installment_amount, tiny.total), (25_000, 100_000));
+
+    // Any amount with a peso per installment splits, the last taking the remainder.
+    let small = InstallmentPlan::interest_free(5, 4).unwrap();
+    assert_eq!((small.installment_amount, small.last_installment_amount, small.total), (1, 2, 5));
+    assert_eq!(InstallmentPlan::interest_free(4, 4).unwrap().last_installment_amount, 1);
+    assert_eq!(InstallmentPlan::interest_free(3, 4), Err(InstallmentsError::AmountTooSmall { amount: 3, count: 4 }));
+}
+
+#[test]
+fn test_format_clp() {
+    assert_eq!(format_clp(0), "$0");
+    assert_eq!(format_clp(999), "$999");
+    assert_eq!(format_clp(1_000), "$1.000");
+    assert_eq!(format_clp(1_234_567), "$1.234.567");
+    assert_eq!(format_clp(-5_000), "-$5.000");
+}
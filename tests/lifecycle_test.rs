@@ -0,0 +1,87 @@
+use webpay::gateway::fake::{FakeGateway, GatewayCall};
+use webpay::lifecycle::{CommitOutcome, PendingTransaction};
+use webpay::types::{CommitResponse, CreateRequest, CreateResponse, TokenWs};
+
+fn token() -> TokenWs {
+    "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap()
+}
+
+fn request() -> CreateRequest {
+    CreateRequest {
+        buy_order: "ORDER-1".parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    }
+}
+
+fn commit(status: &str, response_code: i32) -> CommitResponse {
+    serde_json::from_value(serde_json::json!({
+        "amount": 1000,
+        "status": status,
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "authorization_code": "1213",
+        "response_code": response_code
+    })).unwrap()
+}
+
+#[tokio::test]
+async fn test_authorized_flow_survives_storage() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(commit("AUTHORIZED", 0)))
+        .push_capture(Ok(serde_json::from_value(serde_json::json!({ "captured_amount": 1000, "response_code": 0 })).unwrap()));
+
+    let pending = PendingTransaction::create(&fake, request()).await.expect("create");
+    assert_eq!(pending.url(), "https://webpay.test/init");
+
+    // Stored between the redirect and the return callback.
+    let stored = serde_json::to_string(&pending).unwrap();
+    let pending: PendingTransaction = serde_json::from_str(&stored).unwrap();
+    assert_eq!(pending.request().buy_order, "ORDER-1");
+
+    let authorized = match pending.commit(&fake).await.expect("commit") {
+        CommitOutcome::Authorized(t) => t,
+        CommitOutcome::Rejected(_) => panic!("expected an authorized transaction"),
+    };
+    let stored = serde_json::to_value(&authorized).unwrap();
+    assert_eq!(stored["commit"]["authorization_code"], "1213");
+
+    let captured = authorized.capture(&fake, 1000).await.expect("capture");
+    assert_eq!(captured.captured_amount, Some(1000));
+
+    assert!(matches!(
+        &fake.calls()[2],
+        GatewayCall::Capture { buy_order, authorization_code, capture_amount: 1000, .. }
+            if *buy_order == "ORDER-1" && authorization_code == "1213"
+    ));
+}
+
+#[tokio::test]
+async fn test_rejected_flow() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(commit("FAILED", -1)));
+
+    let pending = PendingTransaction::create(&fake, request()).await.expect("create");
+    let outcome = pending.commit(&fake).await.expect("commit");
+    assert_eq!(outcome.response().status, "FAILED");
+    assert!(outcome.authorized().is_none());
+}
+
+#[tokio::test]
+async fn test_failed_commit_hands_the_transaction_back() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_status(Ok(serde_json::from_value(serde_json::json!({
+            "amount": 1000, "status": "INITIALIZED", "buy_order": "ORDER-1", "session_id": "sess-1"
+        })).unwrap()));
+
+    let pending = PendingTransaction::create(&fake, request()).await.expect("create");
+    // Nothing scripted for the commit, so it fails.
+    let err = pending.commit(&fake).await.unwrap_err();
+    assert_eq!(err.error.status().map(|s| s.as_u16()), Some(500));
+    let pending = err.transaction;
+    assert_eq!(pending.status(&fake).await.expect("status").status, "INITIALIZED");
+}
@@ -0,0 +1,139 @@
+#![cfg(feature = "mock")]
+
+use std::collections::HashMap;
+
+use axum::{routing::post, Form, Router};
+use webpay::callback::{PaymentOutcome, WebpayReturn};
+use webpay::client::{Credentials, WebpayClient};
+use webpay::commit_guard::is_commit_conflict;
+use webpay::mock::{Cardholder, MockWebpay, TestCard, TEST_RUT};
+use webpay::types::{CreateRequest, CreateResponse, WebpayError};
+
+fn client(mock: &MockWebpay) -> WebpayClient {
+    WebpayClient::new(mock.environment(), Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() })
+}
+
+fn order(amount: i64) -> CreateRequest {
+    CreateRequest {
+        buy_order: "ORDER-MOCK-1".parse().unwrap(),
+        session_id: "sess-mock-1".parse().unwrap(),
+        amount,
+        return_url: "http://localhost:3000/webpay/return".into(),
+    }
+}
+
+async fn create(mock: &MockWebpay, amount: i64) -> (WebpayClient, CreateResponse) {
+    let client = client(mock);
+    let created = client.wp_create(&order(amount)).await.unwrap();
+    assert!(created.url.starts_with(mock.base_url()));
+    (client, created)
+}
+
+#[tokio::test]
+async fn test_approved_card_is_authorized() {
+    let mock = MockWebpay::start().await.unwrap();
+    let (client, created) = create(&mock, 1500).await;
+    assert_eq!(mock.status(&created.token).as_deref(), Some("INITIALIZED"));
+
+    let callback = Cardholder::new().pay(&created, "4051 8856 0044 6623").await.unwrap();
+    assert_eq!(callback.action, "http://localhost:3000/webpay/return");
+    let outcome = callback.params().classify().unwrap().process(&client).await;
+    let PaymentOutcome::Authorized(tx) = outcome else { panic!("expected an authorized payment, got {:?}", outcome) };
+    assert_eq!(tx.commit().amount, 1500);
+    assert_eq!(tx.commit().card_detail.as_ref().and_then(|c| c.card_number.as_deref()), Some("6623"));
+    assert_eq!(tx.commit().payment_type_code.as_deref(), Some("VN"));
+
+    let status = client.wp_status(&created.token).await.unwrap();
+    assert_eq!(status.status, "AUTHORIZED");
+
+    let again = client.wp_commit(&created.token).await.unwrap_err();
+    assert!(is_commit_conflict(&again), "{}", again);
+}
+
+#[tokio::test]
+async fn test_rejections() {
+    let mock = MockWebpay::start().await.unwrap();
+
+    let (client, created) = create(&mock, 1000).await;
+    let callback = Cardholder::new().pay(&created, TestCard::MASTERCARD.number).await.unwrap();
+    let outcome = callback.params().classify().unwrap().process(&client).await;
+    assert!(matches!(outcome, PaymentOutcome::Rejected(_)), "{:?}", outcome);
+
+    // An approved card with the wrong password is rejected too.
+    let (client, created) = create(&mock, 1000).await;
+    let callback = Cardholder::new().login(TEST_RUT, "wrong").pay(&created, TestCard::VISA.number).await.unwrap();
+    let commit = client.wp_commit(&callback.params().token_ws.unwrap().parse().unwrap()).await.unwrap();
+    assert_eq!((commit.status.as_str(), commit.response_code), ("FAILED", Some(-1)));
+}
+
+#[tokio::test]
+async fn test_abort_and_timeout() {
+    let mock = MockWebpay::start().await.unwrap();
+
+    let (client, created) = create(&mock, 1000).await;
+    let callback = Cardholder::new().abort(&created).await.unwrap();
+    let ret = callback.params().classify().unwrap();
+    assert_eq!(ret, WebpayReturn::Aborted {
+        token: created.token.clone(),
+        buy_order: "ORDER-MOCK-1".parse().unwrap(),
+        session_id: "sess-mock-1".parse().unwrap(),
+    });
+    assert!(client.wp_commit(&created.token).await.is_err());
+
+    // The form can't be used again.
+    assert!(matches!(Cardholder::new().pay(&created, TestCard::VISA.number).await, Err(WebpayError::Status(e)) if e.status == 400));
+
+    let (_, created) = create(&mock, 1000).await;
+    let callback = Cardholder::new().time_out(&created).await.unwrap();
+    assert!(matches!(callback.params().classify().unwrap(), WebpayReturn::TimedOut { .. }));
+}
+
+#[tokio::test]
+async fn test_refunds() {
+    let mock = MockWebpay::start().await.unwrap();
+
+    let (client, created) = create(&mock, 3000).await;
+    let token = Cardholder::new().pay(&created, TestCard::REDCOMPRA.number).await.unwrap().params().token_ws.unwrap().parse().unwrap();
+    assert!(client.wp_refund(&token, 500).await.is_err(), "not committed yet");
+    client.wp_commit(&token).await.unwrap();
+
+    let partial = client.wp_refund(&token, 500).await.unwrap();
+    assert_eq!((partial.type_.as_deref(), partial.balance), (Some("NULLIFIED"), Some(2500)));
+    assert_eq!(client.wp_status(&token).await.unwrap().status, "PARTIALLY_NULLIFIED");
+    assert!(client.wp_refund(&token, 5000).await.is_err());
+    client.wp_refund(&token, 2500).await.unwrap();
+    assert_eq!(client.wp_status(&token).await.unwrap().status, "NULLIFIED");
+
+    let (client, created) = create(&mock, 3000).await;
+    let token = Cardholder::new().pay(&created, TestCard::VISA.number).await.unwrap().params().token_ws.unwrap().parse().unwrap();
+    client.wp_commit(&token).await.unwrap();
+    let reversal = client.wp_refund(&token, 3000).await.unwrap();
+    assert_eq!(reversal.type_.as_deref(), Some("REVERSED"));
+}
+
+#[tokio::test]
+async fn test_return_form_is_posted_to_the_store() {
+    let app = Router::new().route("/webpay/return", post(|Form(fields): Form<HashMap<String, String>>| async move {
+        format!("token_ws={}", fields.get("token_ws").cloned().unwrap_or_default())
+    }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let mock = MockWebpay::start().await.unwrap();
+    let client = client(&mock);
+    let req = CreateRequest { return_url: format!("http://{}/webpay/return", addr), ..order(1000) };
+    let created = client.wp_create(&req).await.unwrap();
+
+    let res = Cardholder::new().pay(&created, TestCard::VISA.number).await.unwrap().submit().await.unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body().as_ref(), format!("token_ws={}", created.token).as_bytes());
+}
+
+#[tokio::test]
+async fn test_api_requires_credentials() {
+    let mock = MockWebpay::start().await.unwrap();
+    let anonymous = WebpayClient::new(mock.environment(), Credentials { commerce_code: String::new(), api_key: String::new() });
+    let err = anonymous.wp_create(&order(1000)).await.unwrap_err();
+    assert_eq!(err.status().map(|s| s.as_u16()), Some(401));
+}
@@ -0,0 +1,105 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, routing::{get, post}, Json, Router};
+use serde_json::{json, Value};
+use webpay::client::{ApiVersion, Credentials, Environment, Product, WebpayClient};
+use webpay::types::{CommitResponse, TokenWs, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+fn token() -> TokenWs { TOKEN.parse().unwrap() }
+
+// A status response with fields this crate doesn't know, at the top level and in
+// `card_detail`.
+fn status_body() -> Value {
+    json!({
+        "vci": "TSY",
+        "amount": 1000,
+        "status": "AUTHORIZED",
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "card_detail": { "card_number": "6623", "card_brand": "VISA" },
+        "response_code": 0,
+        "balance": 1000,
+        "prepaid_balance": 5000,
+        "installments_type": "NO_INSTALLMENTS",
+        "merchant_reference": { "channel": "web" }
+    })
+}
+
+async fn status(Path(_): Path<(String, String)>) -> impl IntoResponse {
+    ([("x-request-id", "req-42")], Json(status_body()))
+}
+
+async fn refund() -> impl IntoResponse {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "error_message": "Invalid status" })))
+}
+
+async fn spawn_server() -> String {
+    let app = Router::new()
+        .route("/rswebpaytransaction/api/webpay/:version/transactions/:token", get(status))
+        .route("/rswebpaytransaction/api/webpay/:version/transactions/:token/refunds", post(refund));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn client(base_url: String) -> WebpayClient {
+    WebpayClient::new(Environment::Custom(base_url), Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() })
+}
+
+#[tokio::test]
+async fn test_unknown_fields_are_kept() {
+    let client = client(spawn_server().await);
+    let status = client.wp_status(&token()).await.unwrap();
+
+    assert_eq!(status.extra.get("installments_type"), Some(&json!("NO_INSTALLMENTS")));
+    assert_eq!(status.extra.get("merchant_reference"), Some(&json!({ "channel": "web" })));
+    assert!(!status.extra.contains_key("amount"), "known fields are not duplicated");
+    let card = status.card_detail.as_ref().unwrap();
+    assert_eq!(card.extra.get("card_brand"), Some(&json!("VISA")));
+
+    // Serializing gives the unknown fields back.
+    let reserialized = serde_json::to_value(&status).unwrap();
+    assert_eq!(reserialized["merchant_reference"]["channel"], "web");
+    assert_eq!(reserialized["card_detail"]["card_brand"], "VISA");
+    // `prepaid_balance` is in `extra` under v1.2, and serialized once.
+    assert_eq!(reserialized["prepaid_balance"], 5000);
+    let again: CommitResponse = serde_json::from_value(reserialized).unwrap();
+    assert_eq!(again.extra.get("merchant_reference"), status.extra.get("merchant_reference"));
+}
+
+#[tokio::test]
+async fn test_fields_of_newer_versions_move_to_extra() {
+    let client = client(spawn_server().await).with_api_version(Product::WebpayPlus, ApiVersion::V1_0);
+    let status = client.wp_status(&token()).await.unwrap();
+    assert_eq!((status.balance, status.prepaid_balance), (None, None));
+    assert_eq!(status.extra.get("balance"), Some(&json!(1000)));
+    assert_eq!(status.extra.get("prepaid_balance"), Some(&json!(5000)));
+}
+
+#[tokio::test]
+async fn test_raw_response() {
+    let client = client(spawn_server().await);
+    let raw = client.wp_status_raw(&token()).await.unwrap();
+
+    assert_eq!(raw.status, 200);
+    assert_eq!(raw.headers.get("x-request-id").unwrap(), "req-42");
+    assert_eq!(raw.json().unwrap(), status_body());
+    assert_eq!(raw.value.status, "AUTHORIZED");
+    assert!(!raw.latency.is_zero());
+
+    let amount = raw.map(|s| s.amount);
+    assert_eq!(amount.value, 1000);
+}
+
+#[tokio::test]
+async fn test_raw_errors_keep_the_body() {
+    let client = client(spawn_server().await);
+    match client.wp_refund_raw(&token(), 500).await {
+        Err(WebpayError::Status(e)) => {
+            assert_eq!(e.status, 422);
+            assert_eq!(e.message().as_deref(), Some("Invalid status"));
+        }
+        other => panic!("expected a status error, got {:?}", other.map(|r| r.value)),
+    }
+}
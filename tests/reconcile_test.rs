@@ -0,0 +1,143 @@
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use webpay::gateway::fake::{FakeGateway, GatewayCall};
+use webpay::reconcile::{Reconciled, Reconciler, Reconciliation};
+use webpay::store::{EventKind, MemoryStore, TransactionEvent, TransactionStore};
+use webpay::types::{BuyOrder, CaptureResponse, CreateRequest, CreateResponse, RefundResponse, StatusResponse, TokenWs, WebpayError};
+
+fn token(n: u8) -> TokenWs {
+    format!("{:064x}", n).parse().unwrap()
+}
+
+async fn created(store: &MemoryStore, n: u8, age: Duration) {
+    let request = CreateRequest {
+        buy_order: format!("ORDER-{}", n).parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    };
+    let response = CreateResponse { token: token(n), url: "https://webpay.test/init".into(), extra: Default::default() };
+    let event = TransactionEvent { token: token(n), at: Utc::now() - age, kind: EventKind::Created { request, response } };
+    store.record(event).await.unwrap();
+}
+
+fn status(status: &str, response_code: Option<i32>) -> StatusResponse {
+    serde_json::from_value(serde_json::json!({
+        "amount": 1000,
+        "status": status,
+        "buy_order": "ORDER-1",
+        "session_id": "sess-1",
+        "response_code": response_code
+    })).unwrap()
+}
+
+#[tokio::test]
+async fn test_sweep_classifies_pending_transactions() {
+    let store = MemoryStore::new();
+    created(&store, 1, Duration::days(8)).await;
+    created(&store, 2, Duration::hours(3)).await;
+    created(&store, 3, Duration::hours(2)).await;
+    created(&store, 4, Duration::hours(1)).await;
+    created(&store, 5, Duration::minutes(1)).await;
+
+    // Records are swept oldest first; with a concurrency of 1, statuses are asked in order.
+    let fake = FakeGateway::new();
+    fake.push_status(Ok(status("FAILED", Some(-1))))
+        .push_status(Ok(status("INITIALIZED", None)))
+        .push_status(Ok(status("AUTHORIZED", Some(0))));
+    let reconciler = Reconciler::new(fake, store).concurrency(1);
+
+    let mut results = HashMap::new();
+    let count = reconciler.sweep(|r: Reconciled| { results.insert(r.record.token.clone(), r.outcome); }).await.unwrap();
+
+    assert_eq!(count, 4);
+    assert!(matches!(results[&token(1)], Reconciliation::Expired));
+    assert!(matches!(results[&token(2)], Reconciliation::Rejected(_)));
+    assert!(matches!(results[&token(3)], Reconciliation::Abandoned(_)));
+    assert!(matches!(results[&token(4)], Reconciliation::Authorized(_)));
+    assert!(!results.contains_key(&token(5)), "still within the token lifetime");
+    assert!(reconciler.gateway().calls().iter().all(|c| matches!(c, GatewayCall::Status { token_ws } if *token_ws != token(1))));
+}
+
+#[tokio::test]
+async fn test_failed_status_is_reported() {
+    let store = MemoryStore::new();
+    created(&store, 1, Duration::hours(1)).await;
+    let reconciler = Reconciler::new(FakeGateway::new(), store);
+
+    let mut outcomes = Vec::new();
+    reconciler.sweep(|r| outcomes.push(r.outcome)).await.unwrap();
+    assert!(matches!(outcomes.as_slice(), [Reconciliation::Failed(_)]));
+}
+
+#[tokio::test]
+async fn test_run_sends_results_to_channel() {
+    let store = MemoryStore::new();
+    created(&store, 1, Duration::days(10)).await;
+    let reconciler = Reconciler::new(FakeGateway::new(), store);
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+    let run = reconciler.run(std::time::Duration::from_millis(5), tx);
+    let received = async move {
+        let first = rx.recv().await.unwrap();
+        drop(rx);
+        first
+    };
+    let (_, first) = tokio::join!(run, received);
+    assert_eq!(first.record.token, token(1));
+    assert!(matches!(first.outcome, Reconciliation::Expired));
+}
+
+#[tokio::test]
+async fn test_status_expired_error_is_expired() {
+    let store = MemoryStore::new();
+    created(&store, 1, Duration::days(6)).await;
+    let fake = FakeGateway::new();
+    fake.push_status(Err(webpay::types::WebpayError::StatusExpired(webpay::types::ApiError {
+        operation: "status",
+        status: reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+        body: r#"{"error_message":"Transaction's date has passed max time (7 days) to recover the status"}"#.into(),
+    })));
+    let reconciler = Reconciler::new(fake, store);
+
+    let mut outcomes = Vec::new();
+    reconciler.sweep(|r| outcomes.push(r.outcome)).await.unwrap();
+    assert!(matches!(outcomes.as_slice(), [Reconciliation::Expired]));
+}
+
+/// A gateway whose `wp_status` never answers.
+struct Hanging;
+
+#[async_trait::async_trait]
+impl webpay::gateway::PaymentGateway for Hanging {
+    async fn wp_create(&self, _req: &CreateRequest) -> Result<CreateResponse, WebpayError> { unreachable!() }
+
+    async fn wp_commit(&self, _token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> { unreachable!() }
+
+    async fn wp_status(&self, _token_ws: &TokenWs) -> Result<StatusResponse, WebpayError> {
+        std::future::pending().await
+    }
+
+    async fn wp_refund(&self, _token_ws: &TokenWs, _amount: i64) -> Result<RefundResponse, WebpayError> { unreachable!() }
+
+    async fn wp_capture(&self, _token_ws: &TokenWs, _buy_order: &BuyOrder, _code: &str, _amount: i64) -> Result<CaptureResponse, WebpayError> {
+        unreachable!()
+    }
+}
+
+#[tokio::test]
+async fn test_run_streams_results() {
+    let store = MemoryStore::new();
+    created(&store, 1, Duration::days(10)).await;
+    created(&store, 2, Duration::hours(1)).await;
+    let reconciler = Reconciler::new(Hanging, store);
+
+    // The expired record arrives while the other one is still waiting for Transbank.
+    let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+    let first = tokio::select! {
+        _ = reconciler.run(std::time::Duration::from_secs(60), tx) => unreachable!("run only ends when the receiver is dropped"),
+        first = rx.recv() => first.unwrap(),
+    };
+    assert_eq!(first.record.token, token(1));
+    assert!(matches!(first.outcome, Reconciliation::Expired));
+}
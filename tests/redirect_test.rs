@@ -0,0 +1,50 @@
+use webpay::redirect::{escape_html, RedirectForm};
+use webpay::types::CreateResponse;
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+fn created(url: &str) -> CreateResponse {
+    CreateResponse { token: TOKEN.parse().unwrap(), url: url.into(), extra: Default::default() }
+}
+
+#[test]
+fn test_redirect_form_is_escaped() {
+    let html = created(r#"https://evil.test/"><script>alert(1)</script>"#).redirect_form();
+    assert!(!html.contains("<script>alert(1)"));
+    assert!(html.contains(r#"action="https://evil.test/&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;""#));
+    assert!(html.contains(&format!(r#"name="token_ws" value="{}""#, TOKEN)));
+    assert!(html.contains("<noscript>"));
+    assert!(html.contains("<script>document.getElementById"));
+}
+
+#[test]
+fn test_custom_template_and_nonce() {
+    let form = RedirectForm::default()
+        .nonce("abc\"def")
+        .button_label("Pagar")
+        .template("<form action='{{action}}'><input name='token_ws' value='{{token}}'><button>{{button_label}}</button></form><script{{nonce_attr}}></script>");
+    let html = created("https://webpay3gint.transbank.cl/webpayserver/initTransaction?a=1&b='2'").render_redirect_form(&form);
+    assert_eq!(
+        html,
+        format!(
+            "<form action='https://webpay3gint.transbank.cl/webpayserver/initTransaction?a=1&amp;b=&#x27;2&#x27;'><input name='token_ws' value='{}'><button>Pagar</button></form><script nonce=\"abc&quot;def\"></script>",
+            TOKEN
+        )
+    );
+}
+
+#[test]
+fn test_redirect_url() {
+    let url = created("https://webpay3gint.transbank.cl/webpayserver/initTransaction").redirect_url().unwrap();
+    assert_eq!(url.as_str(), format!("https://webpay3gint.transbank.cl/webpayserver/initTransaction?token_ws={}", TOKEN));
+
+    assert!(created("not a url").redirect_url().is_err());
+    assert_eq!(escape_html("<a href='x'>&</a>"), "&lt;a href=&#x27;x&#x27;&gt;&amp;&lt;/a&gt;");
+}
+
+#[test]
+fn test_values_are_not_expanded() {
+    let form = RedirectForm::default().button_label("{{token}}").template("<a href='{{action}}'>{{button_label}}</a>{{unknown}}{{");
+    let html = created("https://webpay.test/init?x={{button_label}}").render_redirect_form(&form);
+    assert_eq!(html, "<a href='https://webpay.test/init?x={{button_label}}'>{{token}}</a>{{unknown}}{{");
+}
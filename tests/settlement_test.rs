@@ -0,0 +1,108 @@
+use chrono::NaiveDate;
+use webpay::settlement::{parse_report, reconcile, EntryKind, Field, ReportLayout};
+use webpay::store::{EventKind, MemoryStore, TransactionEvent, TransactionStore};
+use webpay::types::{CreateRequest, CreateResponse, TokenWs};
+
+fn token(n: u8) -> TokenWs {
+    format!("{:064x}", n).parse().unwrap()
+}
+
+fn json<T: serde::de::DeserializeOwned>(v: serde_json::Value) -> T {
+    serde_json::from_value(v).unwrap()
+}
+
+// A committed sale of `amount`, authorized with `code`.
+async fn sale(store: &MemoryStore, n: u8, amount: i64, code: &str) {
+    let request = CreateRequest {
+        buy_order: format!("ORDER-{}", n).parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount,
+        return_url: "http://localhost:3000/return".into(),
+    };
+    let response = CreateResponse { token: token(n), url: "https://webpay.test/init".into(), extra: Default::default() };
+    store.record(TransactionEvent::now(token(n), EventKind::Created { request, response })).await.unwrap();
+    let commit = json(serde_json::json!({
+        "amount": amount, "status": "AUTHORIZED", "buy_order": format!("ORDER-{}", n), "session_id": "sess-1",
+        "authorization_code": code, "response_code": 0
+    }));
+    store.record(TransactionEvent::now(token(n), EventKind::Committed { response: commit })).await.unwrap();
+}
+
+const REPORT: &str = "\
+Fecha Transacción;Orden de Compra;Código Autorización;Tipo Transacción;Monto;Comisión;Fecha Abono
+01/10/2026;ORDER-1;001213;Venta;$ 10.000;-$ 150;03/10/2026
+01/10/2026;ORDER-2;4455;Venta;5.000;75;03/10/2026
+02/10/2026;ORDER-2;9911;Anulación;-2.000;0;03/10/2026
+02/10/2026;ORDER-9;7777;Venta;1.000;15;03/10/2026
+";
+
+#[test]
+fn test_parse_portal_csv() {
+    let rows = parse_report(REPORT, &ReportLayout::portal_csv()).unwrap();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0].line, 2);
+    assert_eq!((rows[0].amount, rows[0].commission), (10_000, Some(150)));
+    assert_eq!(rows[0].authorization_code, "1213");
+    assert_eq!(rows[0].payout_date, NaiveDate::from_ymd_opt(2026, 10, 3));
+    assert_eq!((rows[2].kind, rows[2].amount), (EntryKind::Refund, 2_000));
+
+    let err = parse_report("Orden de Compra;Código Autorización;Monto\nORDER-1;1213;diez\n", &ReportLayout::portal_csv()).unwrap_err();
+    assert_eq!(err.0[0].line, 2);
+    assert!(parse_report("Orden de Compra;Monto\n", &ReportLayout::portal_csv()).is_err());
+}
+
+#[test]
+fn test_parse_ambiguous_amounts() {
+    let parse = |amount: &str| parse_report(&format!("Orden de Compra;Código Autorización;Monto\nORDER-1;1213;{}\n", amount), &ReportLayout::portal_csv());
+    assert_eq!(parse("1.000,00").unwrap()[0].amount, 1_000);
+    assert_eq!(parse("1.000.000,0").unwrap()[0].amount, 1_000_000);
+    assert_eq!(parse("-$ 12.345").unwrap()[0].amount, 12_345);
+    for amount in ["1,000", "1.5", "1.000,50", "1000.000", "1.00.000", "1.000,", ".000", "1,00,0"] {
+        let err = parse(amount).unwrap_err();
+        assert_eq!(err.0[0].line, 2, "{}", amount);
+    }
+}
+
+#[test]
+fn test_parse_fixed_width() {
+    let layout = ReportLayout::FixedWidth {
+        skip_lines: 1,
+        fields: vec![
+            (Field::BuyOrder, 0..10),
+            (Field::AuthorizationCode, 10..16),
+            (Field::Amount, 16..26),
+            (Field::PayoutDate, 26..34),
+        ],
+    };
+    let report = "HEADER\nORDER-1   001213     1000020261003\nORDER-2   004455     -500020261003\n";
+    let rows = parse_report(report, &layout).unwrap();
+    assert_eq!(rows[0].buy_order, "ORDER-1");
+    assert_eq!(rows[0].amount, 10_000);
+    assert_eq!((rows[1].kind, rows[1].amount), (EntryKind::Refund, 5_000));
+    assert_eq!(rows[1].payout_date, NaiveDate::from_ymd_opt(2026, 10, 3));
+}
+
+#[tokio::test]
+async fn test_reconcile_against_store() {
+    let store = MemoryStore::new();
+    sale(&store, 1, 10_000, "1213").await;
+    sale(&store, 2, 4_000, "4455").await;
+    store.record(TransactionEvent::now(token(2), EventKind::Refunded {
+        amount: 2_000,
+        response: json(serde_json::json!({ "type": "NULLIFIED", "authorization_code": "9911", "balance": 2000, "response_code": 0 })),
+    })).await.unwrap();
+    sale(&store, 3, 3_000, "3131").await;
+
+    let rows = parse_report(REPORT, &ReportLayout::portal_csv()).unwrap();
+    let now = chrono::Utc::now();
+    let records = store.created_between(now - chrono::Duration::days(31), now + chrono::Duration::hours(1)).await.unwrap();
+    let result = reconcile(&rows, &records);
+
+    let matched: Vec<_> = result.matched.iter().map(|(row, _)| (row.buy_order.as_str(), row.kind)).collect();
+    assert_eq!(matched, [("ORDER-1", EntryKind::Sale), ("ORDER-2", EntryKind::Refund)]);
+    assert_eq!(result.amount_mismatch.len(), 1);
+    assert_eq!((result.amount_mismatch[0].0.amount, result.amount_mismatch[0].1.amount), (5_000, 4_000));
+    assert_eq!(result.missing_in_store.iter().map(|r| r.buy_order.as_str()).collect::<Vec<_>>(), ["ORDER-9"]);
+    assert_eq!(result.missing_in_report.len(), 1);
+    assert_eq!(result.missing_in_report[0].token, token(3));
+}
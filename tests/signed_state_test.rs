@@ -0,0 +1,70 @@
+#![cfg(feature = "signed-state")]
+
+use webpay::callback::ReturnParams;
+use webpay::signed_state::{StateError, StateSigner, MAX_STATE_LEN};
+use webpay::types::{BuyOrder, SessionId};
+
+fn signer() -> StateSigner {
+    StateSigner::new(b"0123456789abcdef0123456789abcdef")
+}
+
+fn order(s: &str) -> BuyOrder {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_session_id_round_trip() {
+    let session_id = signer().session_id(&order("ORDER-1"), "cart=42;user=7").unwrap();
+    assert!(session_id.as_str().len() <= SessionId::MAX_LEN);
+    assert_eq!(signer().verify_session_id(&order("ORDER-1"), &session_id).unwrap(), "cart=42;user=7");
+
+    let longest = "x".repeat(MAX_STATE_LEN);
+    assert_eq!(signer().session_id(&order("ORDER-1"), &longest).unwrap().as_str().len(), SessionId::MAX_LEN);
+    assert!(matches!(signer().session_id(&order("ORDER-1"), &format!("{}x", longest)), Err(StateError::TooLong(_))));
+}
+
+#[test]
+fn test_tampered_session_id_is_rejected() {
+    let session_id = signer().session_id(&order("ORDER-1"), "cart=42").unwrap();
+    // The tag is bound to the buy order, the state and the key.
+    assert!(matches!(signer().verify_session_id(&order("ORDER-2"), &session_id), Err(StateError::BadSignature)));
+    let edited: SessionId = session_id.as_str().replacen("42", "43", 1).parse().unwrap();
+    assert!(matches!(signer().verify_session_id(&order("ORDER-1"), &edited), Err(StateError::BadSignature)));
+    let other = StateSigner::new(b"another secret of enough length!");
+    assert!(matches!(other.verify_session_id(&order("ORDER-1"), &session_id), Err(StateError::BadSignature)));
+    assert!(matches!(signer().verify_session_id(&order("ORDER-1"), &"plain".parse().unwrap()), Err(StateError::Unsigned)));
+}
+
+#[test]
+fn test_verify_aborted_return() {
+    let req = signer().create_request(order("ORDER-1"), "cart=42", 1000, "https://shop.test/return").unwrap();
+    let params = ReturnParams {
+        tbk_token: Some("01ab4a0bca4cb1ad9ad3e5c3ec6ddd5d38e3f9ac4a6c3fd7d8d2ba1bd9b1b6ee".into()),
+        tbk_orden_compra: Some(req.buy_order.to_string()),
+        tbk_id_sesion: Some(req.session_id.to_string()),
+        ..Default::default()
+    };
+    assert_eq!(signer().verify_return(&params.classify().unwrap()).unwrap(), Some("cart=42"));
+
+    // The browser swaps in another order's number.
+    let forged = ReturnParams { tbk_orden_compra: Some("ORDER-2".into()), ..params };
+    assert!(matches!(signer().verify_return(&forged.classify().unwrap()), Err(StateError::BadSignature)));
+}
+
+#[test]
+fn test_return_url_round_trip() {
+    let url = signer().sign_return_url("https://shop.test/return?lang=es", &[("cart", "42"), ("next", "/thanks me")]).unwrap();
+    assert!(url.starts_with("https://shop.test/return?lang=es&cart=42&next=%2Fthanks+me&sig="));
+
+    let path = &url["https://shop.test".len()..];
+    let params = signer().verify_return_url(path).unwrap();
+    assert_eq!(params, vec![
+        ("lang".to_string(), "es".to_string()),
+        ("cart".to_string(), "42".to_string()),
+        ("next".to_string(), "/thanks me".to_string()),
+    ]);
+
+    assert!(matches!(signer().verify_return_url(&url.replace("cart=42", "cart=43")), Err(StateError::BadSignature)));
+    assert!(matches!(signer().verify_return_url(&format!("{}&admin=1", url)), Err(StateError::Unsigned)));
+    assert!(matches!(signer().verify_return_url("/return?cart=42"), Err(StateError::Unsigned)));
+}
@@ -0,0 +1,121 @@
+use webpay::gateway::fake::FakeGateway;
+use webpay::gateway::PaymentGateway;
+use webpay::store::{EventKind, MemoryStore, StoreError, StoreGateway, TransactionEvent, TransactionStore};
+use reqwest::StatusCode;
+use webpay::types::{ApiError, CreateRequest, CreateResponse, TokenWs, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+const OTHER: &str = "01ab4a0bca4cb1ad9ad3e5c3ec6ddd5d38e3f9ac4a6c3fd7d8d2ba1bd9b1b6ee";
+
+fn request(buy_order: &str) -> CreateRequest {
+    CreateRequest {
+        buy_order: buy_order.parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    }
+}
+
+fn json<T: serde::de::DeserializeOwned>(v: serde_json::Value) -> T {
+    serde_json::from_value(v).unwrap()
+}
+
+fn rejected(operation: &'static str) -> WebpayError {
+    WebpayError::Status(ApiError {
+        operation,
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+        body: r#"{"error_message":"Invalid status"}"#.into(),
+    })
+}
+
+// Runs the same lifecycle against any store.
+async fn exercise<S: TransactionStore>(store: S) {
+    let token: TokenWs = TOKEN.parse().unwrap();
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token.clone(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_create(Ok(CreateResponse { token: OTHER.parse().unwrap(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(json(serde_json::json!({
+            "amount": 1000, "status": "AUTHORIZED", "buy_order": "ORDER-1", "session_id": "sess-1",
+            "authorization_code": "1213", "response_code": 0
+        }))))
+        .push_refund(Ok(json(serde_json::json!({ "type": "NULLIFIED", "balance": 600, "response_code": 0 }))))
+        .push_refund(Err(rejected("refund")));
+    let gateway = StoreGateway::new(fake, store);
+
+    gateway.wp_create(&request("ORDER-1")).await.unwrap();
+    gateway.wp_create(&request("ORDER-2")).await.unwrap();
+    let pending = gateway.store().pending().await.unwrap();
+    assert_eq!(pending.len(), 2);
+    assert_eq!(pending[0].buy_order, "ORDER-1");
+
+    gateway.wp_commit(&token).await.unwrap();
+    gateway.wp_refund(&token, 400).await.unwrap();
+    // Failed calls are not recorded.
+    assert!(gateway.wp_refund(&token, 10_000).await.is_err());
+
+    let record = gateway.store().get(&token).await.unwrap().expect("record");
+    assert_eq!(record.status, "PARTIALLY_NULLIFIED");
+    assert_eq!(record.amount, 1000);
+    assert_eq!(record.refunded_amount(), 400);
+    assert_eq!(record.commit.as_ref().and_then(|c| c.authorization_code.as_deref()), Some("1213"));
+    assert_eq!(record.events.len(), 3);
+    assert!(matches!(record.events[2].kind, EventKind::Refunded { amount: 400, .. }));
+
+    assert_eq!(gateway.store().pending().await.unwrap().len(), 1);
+    assert_eq!(gateway.store().find_by_buy_order(&"ORDER-2".parse().unwrap()).await.unwrap().len(), 1);
+
+    let now = chrono::Utc::now();
+    let hour = chrono::Duration::hours(1);
+    assert_eq!(gateway.store().created_between(now - hour, now + hour).await.unwrap().len(), 2);
+    assert!(gateway.store().created_between(now + hour, now + hour * 2).await.unwrap().is_empty());
+
+    let duplicate = TransactionEvent::now(token.clone(), EventKind::Created {
+        request: request("ORDER-1"),
+        response: CreateResponse { token: token.clone(), url: String::new(), extra: Default::default() },
+    });
+    assert!(matches!(gateway.store().record(duplicate).await, Err(StoreError::Duplicate(_))));
+}
+
+#[tokio::test]
+async fn test_memory_store() {
+    exercise(MemoryStore::new()).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_sqlite_store() {
+    exercise(webpay::store::SqliteStore::open_in_memory().unwrap()).await;
+}
+
+#[tokio::test]
+async fn test_unknown_token() {
+    let store = MemoryStore::new();
+    let event = TransactionEvent::now(TOKEN.parse().unwrap(), EventKind::Captured {
+        amount: 1000,
+        response: json(serde_json::json!({ "response_code": 0 })),
+    });
+    assert!(matches!(store.record(event).await, Err(StoreError::NotFound(_))));
+}
+
+#[tokio::test]
+async fn test_declined_refund_changes_nothing() {
+    let token: TokenWs = TOKEN.parse().unwrap();
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token.clone(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(json(serde_json::json!({
+            "amount": 1000, "status": "AUTHORIZED", "buy_order": "ORDER-1", "session_id": "sess-1", "response_code": 0
+        }))))
+        .push_refund(Ok(json(serde_json::json!({ "type": "NULLIFIED", "balance": 0, "response_code": -1 }))))
+        .push_refund(Ok(json(serde_json::json!({ "type": "NULLIFIED", "balance": 0 }))));
+    let gateway = StoreGateway::new(fake, MemoryStore::new());
+
+    gateway.wp_create(&request("ORDER-1")).await.unwrap();
+    gateway.wp_commit(&token).await.unwrap();
+    gateway.wp_refund(&token, 1000).await.unwrap();
+    gateway.wp_refund(&token, 1000).await.unwrap();
+
+    let record = gateway.store().get(&token).await.unwrap().expect("record");
+    assert_eq!(record.status, "AUTHORIZED");
+    assert_eq!(record.refunded_amount(), 0);
+    assert_eq!(record.events.len(), 4, "declined refunds are still recorded");
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "tower")]
+
+use std::time::Duration;
+
+use axum::{extract::Path, http::HeaderMap, routing::get, Json, Router};
+use serde_json::{json, Value};
+use tower::{Service, ServiceBuilder, ServiceExt};
+use webpay::client::{Credentials, Environment, WebpayClient};
+use webpay::tower::{ReqwestService, TransbankAuthLayer, WebpayRequest, WebpayResponse};
+use webpay::types::{TokenWs, WebpayError};
+
+const TOKEN: &str = "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3";
+
+fn creds() -> Credentials {
+    Credentials { commerce_code: "597055555532".into(), api_key: "secret".into() }
+}
+
+// Reports which commerce code the request was authenticated with.
+async fn status(headers: HeaderMap, Path(_token): Path<String>) -> Json<Value> {
+    let key_id = headers.get("Tbk-Api-Key-Id").and_then(|v| v.to_str().ok()).unwrap_or("none");
+    Json(json!({
+        "amount": 1000,
+        "status": "INITIALIZED",
+        "buy_order": "ORDER-1",
+        "session_id": key_id,
+    }))
+}
+
+async fn slow_commit() -> Json<Value> {
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    Json(json!({}))
+}
+
+async fn spawn_server() -> String {
+    let app = Router::new()
+        .route("/rswebpaytransaction/api/webpay/v1.2/transactions/:token", get(status).put(slow_commit));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn token() -> TokenWs { TOKEN.parse().unwrap() }
+
+#[tokio::test]
+async fn test_client_as_service() {
+    let base = spawn_server().await;
+    let client = WebpayClient::new(Environment::Custom(base), creds());
+
+    let mut svc = ServiceBuilder::new().concurrency_limit(2).service(client);
+    let res = svc.ready().await.unwrap().call(WebpayRequest::Status { token_ws: token() }).await.unwrap();
+    assert!(matches!(res, WebpayResponse::Status(s) if s.status == "INITIALIZED"));
+}
+
+#[tokio::test]
+async fn test_client_over_layered_http_service() {
+    let base = spawn_server().await;
+    let http = ServiceBuilder::new()
+        .timeout(Duration::from_millis(200))
+        .service(ReqwestService::default());
+    let client = WebpayClient::new(Environment::Custom(base), creds()).with_service(http);
+
+    assert_eq!(client.wp_status(&token()).await.unwrap().session_id, "597055555532");
+    let err = client.wp_commit(&token()).await.unwrap_err();
+    assert!(matches!(err, WebpayError::Transport(e) if e.is::<tower::timeout::error::Elapsed>()));
+}
+
+#[tokio::test]
+async fn test_auth_layer() {
+    let base = spawn_server().await;
+    let mut http = ServiceBuilder::new()
+        .layer(TransbankAuthLayer::new(&creds()))
+        .service(ReqwestService::default());
+
+    let req = axum::http::Request::get(format!("{}/rswebpaytransaction/api/webpay/v1.2/transactions/{}", base, TOKEN))
+        .body(bytes::Bytes::new())
+        .unwrap();
+    let res = http.ready().await.unwrap().call(req).await.unwrap();
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["session_id"], "597055555532");
+}
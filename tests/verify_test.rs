@@ -0,0 +1,62 @@
+use webpay::gateway::fake::FakeGateway;
+use webpay::lifecycle::PendingTransaction;
+use webpay::types::{CommitResponse, CreateRequest, CreateResponse, TokenWs, WebpayError};
+use webpay::verify::{commit_verified, verify_commit, ExpectedOrder, Mismatch};
+
+fn token() -> TokenWs {
+    "e9d555262db0f989e49d724b4db0b0af367cc415cde41f500a776550fc5fddd3".parse().unwrap()
+}
+
+fn request() -> CreateRequest {
+    CreateRequest {
+        buy_order: "ORDER-1".parse().unwrap(),
+        session_id: "sess-1".parse().unwrap(),
+        amount: 1000,
+        return_url: "http://localhost:3000/return".into(),
+    }
+}
+
+fn commit(buy_order: &str, amount: i64) -> CommitResponse {
+    serde_json::from_value(serde_json::json!({
+        "amount": amount,
+        "status": "AUTHORIZED",
+        "buy_order": buy_order,
+        "session_id": "sess-1",
+        "authorization_code": "1213",
+        "response_code": 0
+    })).unwrap()
+}
+
+#[test]
+fn test_matching_commit_passes() {
+    let expected = ExpectedOrder::from(&request());
+    assert_eq!(verify_commit(&expected, commit("ORDER-1", 1000)).expect("match").amount, 1000);
+}
+
+#[tokio::test]
+async fn test_replayed_token_is_a_mismatch() {
+    // A token from a cheaper order, replayed against ORDER-1's return URL.
+    let fake = FakeGateway::new();
+    fake.push_commit(Ok(commit("ORDER-2", 10)));
+
+    let err = commit_verified(&fake, &token(), &ExpectedOrder::from(&request())).await.unwrap_err();
+    let WebpayError::Mismatch(mismatch) = err else { panic!("expected a mismatch, got {:?}", err) };
+    assert_eq!(mismatch.mismatches, vec![
+        Mismatch::BuyOrder { expected: "ORDER-1".parse().unwrap(), actual: "ORDER-2".parse().unwrap() },
+        Mismatch::Amount { expected: 1000, actual: 10 },
+    ]);
+    assert_eq!(mismatch.commit.authorization_code.as_deref(), Some("1213"));
+    assert_eq!(mismatch.to_string(), "commit does not match its order: buy_order is ORDER-2, expected ORDER-1, amount is 10, expected 1000");
+}
+
+#[tokio::test]
+async fn test_pending_transaction_commit_is_verified() {
+    let fake = FakeGateway::new();
+    fake.push_create(Ok(CreateResponse { token: token(), url: "https://webpay.test/init".into(), extra: Default::default() }))
+        .push_commit(Ok(commit("ORDER-1", 999)));
+
+    let pending = PendingTransaction::create(&fake, request()).await.unwrap();
+    let err = pending.commit(&fake).await.unwrap_err();
+    assert!(matches!(&err.error, WebpayError::Mismatch(m) if m.mismatches.len() == 1));
+    assert_eq!(err.transaction.token(), &token(), "the pending transaction is handed back");
+}